@@ -1,6 +1,32 @@
-use crate::user::USER_DATA;
+use crate::helpers::run_ordered;
+use crate::network::{Network, NETWORK_REGISTRY};
+use crate::provider::{
+    approve_request, create_smart_account, estimate_fees,
+    export_safe_transaction, fill_nonce_gaps, import_safe_transaction,
+    merge_safe_transactions, nonce_status, pending_approvals, reject_request,
+    reset_nonces, sign_for_owner, sign_safe_transaction, verify_message,
+    ProviderError, Response as ProviderResponse, SignedMessage,
+    SignedSafeTransaction, TransactionParams, MAX_BATCH_SIZE,
+};
+use crate::server::limits::{ClientLimits, RATE_LIMIT_CONFIG};
+use crate::tokens::{Token, TokenList, TOKEN_STORE};
+use crate::upstream::{health, Endpoint, UPSTREAM_CONFIG};
+use crate::user::{EntryPointVersion, Settings, USER_DATA};
 use async_trait::async_trait;
-use json_rpc2::{from_str, futures::*, Request, Response, Result};
+use ethers_core::types::U256;
+use json_rpc2::{from_str, from_value, futures::*, Request, Response, Result};
+use once_cell::sync::Lazy;
+use serde_json::{json, Value};
+
+/// OpenRPC document for the IPC methods.
+const OPENRPC: &str = include_str!("openrpc.json");
+
+static DOCUMENT: Lazy<Value> = Lazy::new(|| {
+    let mut document: Value =
+        serde_json::from_str(OPENRPC).expect("bundled openrpc.json is invalid");
+    document["info"]["version"] = Value::from(env!("CARGO_PKG_VERSION"));
+    document
+});
 
 struct IpcService;
 
@@ -55,6 +81,295 @@ impl Service for IpcService {
                     serde_json::to_value(accounts).map_err(Box::from)?;
                 Some((request, value).into())
             }
+            "Account.createSmart" => {
+                let (owner, chain_id, entry_point, factory, salt): (
+                    String,
+                    u64,
+                    Option<EntryPointVersion>,
+                    Option<String>,
+                    Option<u64>,
+                ) = request.deserialize()?;
+                let result = create_smart_account(
+                    owner,
+                    chain_id,
+                    entry_point.unwrap_or(EntryPointVersion::V07),
+                    factory,
+                    salt.unwrap_or_default(),
+                )
+                .await
+                .map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Connection.list" => {
+                let user = USER_DATA.read().unwrap();
+                let connections = user.connections().map_err(Box::from)?;
+                let value =
+                    serde_json::to_value(connections).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Connection.revoke" => {
+                let mut user = USER_DATA.write().unwrap();
+                let client_id: String = request.deserialize()?;
+                let result =
+                    user.revoke_connection(&client_id).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Network.list" => {
+                let registry = NETWORK_REGISTRY.read().unwrap();
+                let value =
+                    serde_json::to_value(registry.list()).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Network.add" => {
+                let network: Network = request.deserialize()?;
+                network.validate().map_err(Box::from)?;
+                network.verify().await.map_err(Box::from)?;
+                let mut registry = NETWORK_REGISTRY.write().unwrap();
+                let result = registry.add(network).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Network.remove" => {
+                let mut registry = NETWORK_REGISTRY.write().unwrap();
+                let chain_id: u64 = request.deserialize()?;
+                let result = registry.remove(chain_id).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Token.list" => {
+                let store = TOKEN_STORE.read().unwrap();
+                let account: String = request.deserialize()?;
+                let value = serde_json::to_value(store.list(&account))
+                    .map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Token.add" => {
+                let mut store = TOKEN_STORE.write().unwrap();
+                let (account, token): (String, Token) =
+                    request.deserialize()?;
+                let result = store.add(&account, token).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Token.remove" => {
+                let mut store = TOKEN_STORE.write().unwrap();
+                let (account, token): (String, Token) =
+                    request.deserialize()?;
+                let result =
+                    store.remove(&account, &token).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Token.import" => {
+                let mut store = TOKEN_STORE.write().unwrap();
+                let (account, list, chain_id): (
+                    String,
+                    TokenList,
+                    Option<u64>,
+                ) = request.deserialize()?;
+                let result = store
+                    .import(&account, &list, chain_id)
+                    .map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Settings.get" => {
+                let user = USER_DATA.read().unwrap();
+                let settings = user.settings().map_err(Box::from)?;
+                let value =
+                    serde_json::to_value(settings).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Settings.set" => {
+                let mut user = USER_DATA.write().unwrap();
+                let settings: Settings = request.deserialize()?;
+                let result = user.set_settings(settings).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Transaction.sign" => {
+                let (tx, chain_id, passphrase): (
+                    TransactionParams,
+                    u64,
+                    String,
+                ) = request.deserialize()?;
+                let result = sign_for_owner(tx, chain_id, passphrase)
+                    .await
+                    .map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Approval.list" => {
+                let value = serde_json::to_value(pending_approvals())
+                    .map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Approval.approve" => {
+                let (id, passphrase, accounts): (
+                    u64,
+                    Option<String>,
+                    Option<Vec<String>>,
+                ) = request.deserialize()?;
+                let result = approve_request(id, passphrase, accounts)
+                    .map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Approval.reject" => {
+                let id: u64 = request.deserialize()?;
+                let result = reject_request(id).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Fee.estimate" => {
+                let (chain_id, gas): (u64, Option<U256>) =
+                    request.deserialize()?;
+                let result =
+                    estimate_fees(chain_id, gas).await.map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Nonce.status" => {
+                let (chain_id, address): (u64, String) =
+                    request.deserialize()?;
+                let result =
+                    nonce_status(chain_id, address).await.map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Nonce.fill" => {
+                let (chain_id, address, passphrase): (u64, String, String) =
+                    request.deserialize()?;
+                let result = fill_nonce_gaps(chain_id, address, passphrase)
+                    .await
+                    .map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Nonce.reset" => {
+                let (chain_id, address): (u64, String) =
+                    request.deserialize()?;
+                let result =
+                    reset_nonces(chain_id, address).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "RateLimit.get" => {
+                let config = RATE_LIMIT_CONFIG.read().unwrap();
+                let client_id: String = request.deserialize()?;
+                let value = serde_json::to_value(config.limits(&client_id))
+                    .map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "RateLimit.set" => {
+                let mut config = RATE_LIMIT_CONFIG.write().unwrap();
+                let (client_id, limits): (String, ClientLimits) =
+                    request.deserialize()?;
+                let result =
+                    config.set_limits(&client_id, limits).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "RateLimit.remove" => {
+                let mut config = RATE_LIMIT_CONFIG.write().unwrap();
+                let client_id: String = request.deserialize()?;
+                let result =
+                    config.remove_limits(&client_id).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Safe.hash" => {
+                let tx: SignedSafeTransaction = request.deserialize()?;
+                let result = tx.hash().map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Safe.sign" => {
+                let (tx, owner, passphrase): (
+                    SignedSafeTransaction,
+                    String,
+                    String,
+                ) = request.deserialize()?;
+                let result = sign_safe_transaction(tx, owner, passphrase)
+                    .await
+                    .map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Safe.merge" => {
+                let transactions: Vec<SignedSafeTransaction> =
+                    request.deserialize()?;
+                let result =
+                    merge_safe_transactions(transactions).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Safe.import" => {
+                let json: String = request.deserialize()?;
+                let result = import_safe_transaction(&json).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Safe.export" => {
+                let tx: SignedSafeTransaction = request.deserialize()?;
+                let result = export_safe_transaction(&tx).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Safe.signatures" => {
+                let tx: SignedSafeTransaction = request.deserialize()?;
+                tx.verify().map_err(Box::from)?;
+                let value = json!({
+                    "signatures": tx.signatures(),
+                    "execTransactionData": tx.exec_transaction_data(),
+                });
+                Some((request, value).into())
+            }
+            "Signature.verify" => {
+                let (chain_id, address, message, signature): (
+                    u64,
+                    String,
+                    SignedMessage,
+                    String,
+                ) = request.deserialize()?;
+                let result =
+                    verify_message(chain_id, &address, &message, &signature)
+                        .await
+                        .map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Upstream.list" => {
+                let config = UPSTREAM_CONFIG.read().unwrap();
+                let value =
+                    serde_json::to_value(&*config).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Upstream.set" => {
+                let mut config = UPSTREAM_CONFIG.write().unwrap();
+                let (chain_id, endpoints): (u64, Vec<Endpoint>) =
+                    request.deserialize()?;
+                let result = config
+                    .set_endpoints(chain_id, endpoints)
+                    .map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Upstream.remove" => {
+                let mut config = UPSTREAM_CONFIG.write().unwrap();
+                let chain_id: u64 = request.deserialize()?;
+                let result =
+                    config.remove_endpoints(chain_id).map_err(Box::from)?;
+                let value = serde_json::to_value(result).map_err(Box::from)?;
+                Some((request, value).into())
+            }
+            "Upstream.health" => {
+                let value = serde_json::to_value(health::status())
+                    .map_err(Box::from)?;
+                Some((request, value).into())
+            }
             // SIGNUP
             "Signup.start" => {
                 let mut user = USER_DATA.write().unwrap();
@@ -99,16 +414,89 @@ impl Service for IpcService {
                 let value = serde_json::to_value(result).map_err(Box::from)?;
                 Some((request, value).into())
             }
+            "rpc.discover" => Some((request, DOCUMENT.clone()).into()),
             _ => None,
         };
         Ok(response)
     }
 }
 
-pub(crate) async fn handle(message: &str) -> Result<Option<Response>> {
-    let request = from_str(message)?;
+/// Methods that only read state and may run concurrently
+/// with other requests in a batch.
+const CONCURRENT_METHODS: &[&str] = &[
+    "Account.exists",
+    "Account.list",
+    "Approval.list",
+    "Connection.list",
+    "Network.list",
+    "Settings.get",
+    "Token.list",
+    "Upstream.list",
+    "Upstream.health",
+    "rpc.discover",
+];
+
+pub(crate) async fn handle(message: &str) -> Result<Option<Value>> {
     let service: Box<dyn Service<Data = ()>> = Box::new(IpcService {});
     let server = Server::new(vec![&service]);
+
+    if let Ok(Value::Array(requests)) = serde_json::from_str(message) {
+        return Ok(handle_batch(&server, requests).await);
+    }
+
+    let request = from_str(message)?;
     let response = server.serve(&request, &()).await;
-    Ok(response)
+    Ok(response.map(|response| json!(response)))
+}
+
+/// Serve a batch of requests.
+///
+/// Read only methods run concurrently and other methods run
+/// in the order they appear in the batch; responses keep the
+/// request order and notifications are omitted. Batches are
+/// limited to the same size as provider batches.
+async fn handle_batch(
+    server: &Server<'_, ()>,
+    requests: Vec<Value>,
+) -> Option<Value> {
+    if requests.is_empty() {
+        let error = ProviderError::invalid_request("batch must not be empty");
+        return Some(json!(ProviderResponse::error(Value::Null, error)));
+    }
+    if requests.len() > MAX_BATCH_SIZE {
+        let error = ProviderError::invalid_request(format!(
+            "batch must not exceed {} requests",
+            MAX_BATCH_SIZE
+        ));
+        return Some(json!(ProviderResponse::error(Value::Null, error)));
+    }
+
+    let requests: Vec<Result<Request>> =
+        requests.into_iter().map(from_value).collect();
+    let responses = run_ordered(
+        requests,
+        |request| match request {
+            Ok(request) => CONCURRENT_METHODS.contains(&request.method()),
+            Err(_) => true,
+        },
+        |request| async move {
+            match request {
+                Ok(request) => {
+                    server.serve(&request, &()).await.map(|r| json!(r))
+                }
+                Err(e) => Some(json!(ProviderResponse::error(
+                    Value::Null,
+                    ProviderError::invalid_request(e),
+                ))),
+            }
+        },
+    )
+    .await;
+
+    let responses: Vec<Value> = responses.into_iter().flatten().collect();
+    if responses.is_empty() {
+        None
+    } else {
+        Some(Value::Array(responses))
+    }
 }
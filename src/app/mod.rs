@@ -1,7 +1,7 @@
 use wry::{
     application::{
         event::{Event, StartCause, WindowEvent},
-        event_loop::{ControlFlow, EventLoop},
+        event_loop::{ControlFlow, EventLoop, EventLoopProxy},
         menu::{MenuBar, MenuItem},
         window::WindowBuilder,
     },
@@ -10,16 +10,43 @@ use wry::{
 };
 
 use image::{DynamicImage, Luma};
+use serde_json::json;
+use tokio::sync::broadcast::error::RecvError;
 use qrcode::QrCode;
 use url::Url;
 
 use std::collections::HashMap;
 use std::sync::mpsc::channel;
 
+use crate::events::{self, WalletEvent};
+use crate::provider::{enable_approvals, pending_approvals};
+
 //use log::debug;
 
 mod ipc;
 
+/// Send the pending approvals to the webview whenever the
+/// queue changes.
+async fn notify_approvals(event_proxy: EventLoopProxy<String>) {
+    let mut events = events::subscribe();
+    loop {
+        match events.recv().await {
+            Ok(WalletEvent::ApprovalsChanged) => {}
+            Ok(_) | Err(RecvError::Lagged(_)) => continue,
+            Err(RecvError::Closed) => break,
+        }
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "Approval.changed",
+            "params": [pending_approvals()],
+        });
+        let script = format!("window.postMessage({})", notification);
+        if event_proxy.send_event(script).is_err() {
+            break;
+        }
+    }
+}
+
 pub async fn window<S: AsRef<str>, T: AsRef<str>>(
     url: S,
     title: T,
@@ -29,6 +56,9 @@ pub async fn window<S: AsRef<str>, T: AsRef<str>>(
 
     let (tx, rx) = channel::<String>();
 
+    // Requests that need consent wait for the owner in the UI
+    enable_approvals();
+
     // Spawn a worker thread to execute IPC messages
     // and pass them back to the webview via the event proxy
     // when the JSON-RPC evaluation determines a reply is required.
@@ -37,6 +67,7 @@ pub async fn window<S: AsRef<str>, T: AsRef<str>>(
             .expect("could not create tokio runtime");
         runtime
             .block_on(async {
+                tokio::spawn(notify_approvals(event_proxy.clone()));
                 while let Ok(message) = rx.recv() {
                     let response = ipc::handle(&message).await?;
                     if let Some(reply) = &response {
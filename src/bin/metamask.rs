@@ -1,7 +1,8 @@
-use anyhow::Result;
-use clap::Parser;
+use anyhow::{bail, Result};
+use clap::{Parser, Subcommand};
 use log::{error, warn};
 use std::net::SocketAddr;
+use std::path::PathBuf;
 use tokio::sync::oneshot;
 
 use metamask::*;
@@ -21,6 +22,39 @@ struct Cli {
     /// URL for the window.
     #[clap(short, long, default_value = "http://localhost:7777")]
     url: String,
+
+    #[clap(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Verify a signature for an account.
+    ///
+    /// Exits with a non-zero status when the signature is invalid.
+    Verify {
+        /// Address of the account that signed.
+        address: String,
+
+        /// Hex encoded signature.
+        signature: String,
+
+        /// Personal message that was signed; hex when prefixed with 0x.
+        #[clap(short, long)]
+        message: Option<String>,
+
+        /// File containing the typed data that was signed (EIP-712).
+        #[clap(short, long)]
+        typed_data: Option<PathBuf>,
+
+        /// Hash that was signed.
+        #[clap(long)]
+        hash: Option<String>,
+
+        /// Chain used to call contract accounts.
+        #[clap(short, long, default_value = "1")]
+        chain_id: u64,
+    },
 }
 
 fn print_error(e: anyhow::Error) {
@@ -41,9 +75,48 @@ fn print_error(e: anyhow::Error) {
     std::process::exit(1);
 }
 
+/// Verify a signature and print the result.
+async fn verify(
+    address: String,
+    signature: String,
+    message: Option<String>,
+    typed_data: Option<PathBuf>,
+    hash: Option<String>,
+    chain_id: u64,
+) -> Result<bool> {
+    let message = match (message, typed_data, hash) {
+        (Some(message), None, None) => SignedMessage::Personal(message),
+        (None, Some(file), None) => {
+            let contents = std::fs::read_to_string(file)?;
+            SignedMessage::TypedData(serde_json::from_str(&contents)?)
+        }
+        (None, None, Some(hash)) => SignedMessage::Hash(hash.parse()?),
+        _ => bail!("one of --message, --typed-data or --hash is required"),
+    };
+    let verification =
+        verify_message(chain_id, &address, &message, &signature).await?;
+    println!("{}", serde_json::to_string_pretty(&verification)?);
+    Ok(verification.valid)
+}
+
 async fn run() -> Result<()> {
     let args = Cli::parse();
 
+    if let Some(Command::Verify {
+        address,
+        signature,
+        message,
+        typed_data,
+        hash,
+        chain_id,
+    }) = args.command
+    {
+        let valid =
+            verify(address, signature, message, typed_data, hash, chain_id)
+                .await?;
+        std::process::exit(if valid { 0 } else { 1 });
+    }
+
     let addr = args.address;
     let title = "MetaMask";
     let url = args.url;
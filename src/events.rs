@@ -0,0 +1,56 @@
+//! Wallet events broadcast to connected clients.
+//!
+//! Events describe what changed in the wallet; listeners decide
+//! how a change affects the client they serve.
+use once_cell::sync::Lazy;
+use tokio::sync::broadcast;
+
+/// Number of events buffered for slow listeners.
+const CAPACITY: usize = 64;
+
+static EVENTS: Lazy<broadcast::Sender<WalletEvent>> =
+    Lazy::new(|| broadcast::channel(CAPACITY).0);
+
+/// Change to the wallet state.
+#[derive(Debug, Clone)]
+pub enum WalletEvent {
+    /// The accounts exposed to a client changed; when no client
+    /// is given the wallet was locked or unlocked.
+    AccountsChanged { client_id: Option<String> },
+    /// A client selected a different chain.
+    ChainChanged { client_id: String, chain_id: u64 },
+    /// The upstream nodes for a chain became reachable or
+    /// unreachable.
+    ChainConnection { chain_id: u64, connected: bool },
+    /// The user revoked the connection of a client.
+    Revoked { client_id: String },
+    /// A request was added to or removed from the queue of
+    /// requests waiting for the owner.
+    ApprovalsChanged,
+}
+
+impl WalletEvent {
+    /// Determine if this event may affect a client.
+    pub fn affects(&self, client: &str) -> bool {
+        match self {
+            Self::AccountsChanged { client_id } => {
+                client_id.as_deref().map(|id| id == client).unwrap_or(true)
+            }
+            Self::ChainChanged { client_id, .. }
+            | Self::Revoked { client_id } => client_id == client,
+            Self::ChainConnection { .. } => true,
+            Self::ApprovalsChanged => false,
+        }
+    }
+}
+
+/// Broadcast an event to all listeners.
+pub fn emit(event: WalletEvent) {
+    // Sending only fails when nobody is listening
+    let _ = EVENTS.send(event);
+}
+
+/// Listen for events.
+pub fn subscribe() -> broadcast::Receiver<WalletEvent> {
+    EVENTS.subscribe()
+}
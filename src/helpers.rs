@@ -1,10 +1,29 @@
-use ethers_core::types::H160;
+use ethers_core::types::{H160, U256};
 use chrono::{SecondsFormat, Utc};
+use futures::future::join_all;
+use std::future::Future;
 
 pub fn format_address(address: H160) -> String {
     format!("0x{}", hex::encode(address.0))
 }
 
+/// Format an integer amount with the given number of decimals,
+/// eg: wei to ether with 18 decimals.
+pub fn format_units(amount: U256, decimals: usize) -> String {
+    let digits = amount.to_string();
+    if decimals == 0 {
+        return digits;
+    }
+    let digits = format!("{:0>width$}", digits, width = decimals + 1);
+    let (whole, fraction) = digits.split_at(digits.len() - decimals);
+    let fraction = fraction.trim_end_matches('0');
+    if fraction.is_empty() {
+        whole.to_string()
+    } else {
+        format!("{}.{}", whole, fraction)
+    }
+}
+
 /// Convert a public address to a `geth` style filename.
 pub fn address_to_filename<A>(address: A) -> String where A: AsRef<str> {
     let timestamp = Utc::now();
@@ -15,6 +34,34 @@ pub fn address_to_filename<A>(address: A) -> String where A: AsRef<str> {
     )
 }
 
+/// Run a task for each item preserving the order of the outputs.
+///
+/// Consecutive items that are safe to run concurrently are
+/// awaited together; any other item waits for the preceding
+/// items to complete and runs on its own.
+pub async fn run_ordered<T, O, F, Fut>(
+    items: Vec<T>,
+    concurrent: impl Fn(&T) -> bool,
+    run: F,
+) -> Vec<O>
+where
+    F: Fn(T) -> Fut,
+    Fut: Future<Output = O>,
+{
+    let mut outputs = Vec::with_capacity(items.len());
+    let mut group = Vec::new();
+    for item in items {
+        if concurrent(&item) {
+            group.push(run(item));
+        } else {
+            outputs.extend(join_all(group.drain(..)).await);
+            outputs.push(run(item).await);
+        }
+    }
+    outputs.extend(join_all(group).await);
+    outputs
+}
+
 pub mod bip39 {
     //! Utility to generate bip39 passphrase mnemonics.
     //!
@@ -1,9 +1,15 @@
 mod app;
+mod events;
 mod helpers;
+mod network;
+mod provider;
 mod rates;
 mod server;
+mod tokens;
+mod upstream;
 mod user;
 
 pub use app::window;
+pub use provider::{verify_message, SignedMessage, Verification};
 pub use rates::eth_usd;
 pub use server::server;
@@ -0,0 +1,278 @@
+//! Registry of known networks.
+//!
+//! Defaults are bundled from an offline snapshot of
+//! https://chainid.network/chains.json; networks added by the
+//! user are stored on disc next to the user data and take
+//! precedence over the bundled entries.
+use anyhow::{anyhow, bail, Result};
+use ethers_core::types::U64;
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use url::Url;
+
+use crate::upstream::{Endpoint, Upstream};
+
+const NETWORKS: &str = "networks.json";
+
+/// Snapshot of chains.json trimmed to the bundled networks.
+const CHAINS: &str = include_str!("chains.json");
+
+/// Largest chain identifier that can be represented safely
+/// by JavaScript clients.
+const MAX_SAFE_CHAIN_ID: u64 = 4_503_599_627_370_476;
+
+pub static NETWORK_REGISTRY: Lazy<RwLock<NetworkRegistry>> = Lazy::new(|| {
+    RwLock::new(NetworkRegistry::load().unwrap_or_else(|e| {
+        log::warn!("could not load networks: {}", e);
+        NetworkRegistry::new()
+    }))
+});
+
+/// Native currency of a network.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NativeCurrency {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// Network that clients may switch to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Network {
+    pub chain_id: u64,
+    pub name: String,
+    pub native_currency: NativeCurrency,
+    /// JSON-RPC endpoints in order of preference.
+    pub rpc_urls: Vec<Url>,
+    #[serde(default)]
+    pub block_explorer_urls: Vec<Url>,
+}
+
+impl Network {
+    /// Validate the network.
+    pub fn validate(&self) -> Result<()> {
+        if self.chain_id == 0 || self.chain_id > MAX_SAFE_CHAIN_ID {
+            bail!("chain id {} is not valid", self.chain_id);
+        }
+        if self.name.trim().is_empty() {
+            bail!("network name is required");
+        }
+        let symbol = self.native_currency.symbol.chars().count();
+        if !(2..=6).contains(&symbol) {
+            bail!("currency symbol must be between 2 and 6 characters");
+        }
+        if self.native_currency.decimals != 18 {
+            bail!("native currency must use 18 decimals");
+        }
+        if self.rpc_urls.is_empty() {
+            bail!("at least one RPC URL is required");
+        }
+        for url in &self.rpc_urls {
+            if !is_secure(url) {
+                bail!("RPC URL {} must use https", url);
+            }
+        }
+        for url in &self.block_explorer_urls {
+            if url.scheme() != "https" {
+                bail!("block explorer URL {} must use https", url);
+            }
+        }
+        Ok(())
+    }
+
+    /// Upstream endpoints for the RPC URLs.
+    pub fn endpoints(&self) -> Vec<Endpoint> {
+        self.rpc_urls.iter().cloned().map(Endpoint::new).collect()
+    }
+
+    /// Verify that the preferred RPC URL is connected to the
+    /// declared chain.
+    pub async fn verify(&self) -> Result<()> {
+        let url = self
+            .rpc_urls
+            .first()
+            .ok_or_else(|| anyhow!("at least one RPC URL is required"))?;
+        let upstream =
+            Upstream::new(self.chain_id, vec![Endpoint::new(url.clone())]);
+        let remote_chain_id: U64 =
+            upstream.call("eth_chainId", json!([])).await?;
+        if remote_chain_id.as_u64() != self.chain_id {
+            bail!(
+                "RPC URL {} is on chain {} but chain {} was declared",
+                url,
+                remote_chain_id,
+                self.chain_id
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Plain HTTP is only allowed for nodes on this machine.
+fn is_secure(url: &Url) -> bool {
+    match url.scheme() {
+        "https" => true,
+        "http" => {
+            matches!(url.host_str(), Some("localhost" | "127.0.0.1" | "[::1]"))
+        }
+        _ => false,
+    }
+}
+
+/// Entry in chains.json.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChainListEntry {
+    name: String,
+    chain_id: u64,
+    rpc: Vec<String>,
+    native_currency: NativeCurrency,
+    #[serde(default)]
+    explorers: Vec<ChainListExplorer>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChainListExplorer {
+    url: String,
+}
+
+impl From<ChainListEntry> for Network {
+    fn from(entry: ChainListEntry) -> Self {
+        // Skip endpoints that need an API key
+        let rpc_urls = entry
+            .rpc
+            .iter()
+            .filter(|url| !url.contains("${"))
+            .filter_map(|url| url.parse::<Url>().ok())
+            .filter(|url| matches!(url.scheme(), "http" | "https"))
+            .collect();
+        let block_explorer_urls = entry
+            .explorers
+            .iter()
+            .filter_map(|explorer| explorer.url.parse::<Url>().ok())
+            .collect();
+        Self {
+            chain_id: entry.chain_id,
+            name: entry.name,
+            native_currency: entry.native_currency,
+            rpc_urls,
+            block_explorer_urls,
+        }
+    }
+}
+
+/// Parse the bundled networks.
+fn bundled() -> BTreeMap<u64, Network> {
+    let entries: Vec<ChainListEntry> =
+        serde_json::from_str(CHAINS).expect("bundled chains.json is invalid");
+    entries
+        .into_iter()
+        .map(Network::from)
+        .map(|network| (network.chain_id, network))
+        .collect()
+}
+
+/// Bundled and user-added networks.
+#[derive(Debug)]
+pub struct NetworkRegistry {
+    bundled: BTreeMap<u64, Network>,
+    custom: BTreeMap<u64, Network>,
+}
+
+impl NetworkRegistry {
+    /// Create a registry with the bundled networks.
+    fn new() -> Self {
+        Self {
+            bundled: bundled(),
+            custom: BTreeMap::new(),
+        }
+    }
+
+    /// Load the user-added networks from disc.
+    fn load() -> Result<Self> {
+        let mut registry = Self::new();
+        let file = crate::user::storage()?.join(NETWORKS);
+        if file.is_file() {
+            let contents = std::fs::read_to_string(file)?;
+            let networks: Vec<Network> = serde_json::from_str(&contents)?;
+            registry.custom = networks
+                .into_iter()
+                .map(|network| (network.chain_id, network))
+                .collect();
+        }
+        Ok(registry)
+    }
+
+    /// Save the user-added networks to disc.
+    fn save(&self) -> Result<()> {
+        let file = crate::user::storage()?.join(NETWORKS);
+        let networks: Vec<&Network> = self.custom.values().collect();
+        let contents = serde_json::to_string_pretty(&networks)?;
+        std::fs::write(file, contents)?;
+        Ok(())
+    }
+
+    /// Find a network.
+    pub fn get(&self, chain_id: u64) -> Option<&Network> {
+        self.custom
+            .get(&chain_id)
+            .or_else(|| self.bundled.get(&chain_id))
+    }
+
+    /// List every network ordered by chain identifier.
+    pub fn list(&self) -> Vec<&Network> {
+        let mut networks: BTreeMap<u64, &Network> =
+            self.bundled.iter().map(|(k, v)| (*k, v)).collect();
+        networks.extend(self.custom.iter().map(|(k, v)| (*k, v)));
+        networks.into_values().collect()
+    }
+
+    /// Add a network or replace a user-added network.
+    pub fn add(&mut self, network: Network) -> Result<()> {
+        network.validate()?;
+        self.custom.insert(network.chain_id, network);
+        self.save()
+    }
+
+    /// Remove a user-added network.
+    pub fn remove(&mut self, chain_id: u64) -> Result<bool> {
+        let removed = self.custom.remove(&chain_id).is_some();
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundled_networks() {
+        let networks = bundled();
+        let mainnet = networks.get(&1).unwrap();
+        assert_eq!("ETH", mainnet.native_currency.symbol);
+        assert!(!mainnet.rpc_urls.is_empty());
+        assert!(mainnet
+            .rpc_urls
+            .iter()
+            .all(|url| !url.as_str().contains("INFURA_API_KEY")));
+        for network in networks.values() {
+            network.validate().unwrap();
+        }
+    }
+
+    #[test]
+    fn reject_insecure_rpc() {
+        let mut network = bundled().remove(&1).unwrap();
+        network.rpc_urls = vec!["http://example.com".parse().unwrap()];
+        assert!(network.validate().is_err());
+        network.rpc_urls = vec!["http://localhost:8545".parse().unwrap()];
+        assert!(network.validate().is_ok());
+    }
+}
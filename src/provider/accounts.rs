@@ -0,0 +1,71 @@
+//! Accounts exposed to provider clients.
+use crate::user::{AccountKind, USER_DATA};
+
+use super::{consent, Context, ProviderError, ProviderResult};
+
+/// Accounts the owner exposed to a client.
+///
+/// When the wallet is locked or the grant belongs to a
+/// different owner no accounts are returned.
+pub fn accounts(ctx: &Context) -> Vec<String> {
+    let user = USER_DATA.read().unwrap();
+    if user.owner_id().as_ref() != Some(&ctx.owner_id) {
+        return vec![];
+    }
+    user.connected_accounts(&ctx.client_id).unwrap_or_default()
+}
+
+/// Accounts exposed to a client, asking the owner for consent
+/// when nothing has been exposed yet.
+pub async fn request_accounts(ctx: &Context) -> ProviderResult<Vec<String>> {
+    let connected = {
+        let user = USER_DATA.read().unwrap();
+        if user.owner_id().as_ref() != Some(&ctx.owner_id) {
+            return Err(ProviderError::unauthorized());
+        }
+        user.connected_accounts(&ctx.client_id)?
+    };
+
+    if !connected.is_empty() {
+        return Ok(connected);
+    }
+    select_accounts(ctx, None).await
+}
+
+/// Ask the owner which accounts to expose to a client and
+/// connect the selected accounts.
+///
+/// When `restrict` is given only those accounts are offered.
+pub async fn select_accounts(
+    ctx: &Context,
+    restrict: Option<&[String]>,
+) -> ProviderResult<Vec<String>> {
+    let mut available = {
+        let user = USER_DATA.read().unwrap();
+        if user.owner_id().as_ref() != Some(&ctx.owner_id) {
+            return Err(ProviderError::unauthorized());
+        }
+        let mut available = user.list_accounts()?;
+        // Offer the primary account first
+        available.sort_by_key(|a| a.kind() != &AccountKind::Primary);
+        available
+            .iter()
+            .map(|a| a.address().to_string())
+            .collect::<Vec<_>>()
+    };
+    if let Some(restrict) = restrict {
+        available.retain(|address| {
+            restrict.iter().any(|r| r.eq_ignore_ascii_case(address))
+        });
+        if available.is_empty() {
+            return Err(ProviderError::invalid_params(
+                "none of the requested accounts belong to the wallet",
+            ));
+        }
+    }
+
+    let selected = consent::select_accounts(ctx, available).await?;
+    let mut user = USER_DATA.write().unwrap();
+    user.connect_accounts(&ctx.client_id, selected.clone())?;
+    Ok(selected)
+}
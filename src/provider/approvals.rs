@@ -0,0 +1,326 @@
+//! Requests waiting for the owner to approve them.
+//!
+//! When the wallet window is open, requests that need consent
+//! are added to a queue with the origin, method and decoded
+//! payload instead of showing a native dialog. The wallet UI
+//! lists the queue and approves or rejects each entry; the
+//! provider request waits until it is decided or times out.
+//!
+//! The queue does not depend on the window so pending requests
+//! are kept when the UI is reloaded. It is not written to disc:
+//! an entry can only be answered while the provider request is
+//! waiting for it, and no request outlives the process. An entry
+//! is removed when the request waiting for it is dropped, for
+//! example when the client disconnects. Information entries do
+//! not hold up the request that added them and are removed when
+//! dismissed or when they expire.
+use anyhow::{anyhow, Result};
+use chrono::{Duration as ChronoDuration, SecondsFormat, Utc};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use crate::events::{self, WalletEvent};
+
+use super::{
+    consent::Approval, Context, ProviderError, ProviderResult, USER_REJECTED,
+};
+
+/// Time the owner has to decide before a request is rejected.
+pub const APPROVAL_TIMEOUT: Duration = Duration::from_secs(300);
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+static QUEUE: Lazy<Mutex<BTreeMap<u64, Entry>>> =
+    Lazy::new(|| Mutex::new(BTreeMap::new()));
+
+/// What the owner is asked to do.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum ApprovalKind {
+    /// Select the accounts exposed to a client.
+    Connect,
+    /// Approve a request and unlock the account with a passphrase.
+    Sign,
+    /// Approve a request that does not use an account key.
+    Confirm,
+    /// Acknowledge information about a request.
+    Inform,
+}
+
+/// Request waiting in the queue.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PendingApproval {
+    pub id: u64,
+    pub kind: ApprovalKind,
+    /// Client that sent the request.
+    pub client_id: String,
+    /// Origin of the client.
+    pub origin: String,
+    pub method: String,
+    pub account: Option<String>,
+    pub summary: String,
+    /// Decoded request payload.
+    pub payload: Value,
+    pub warnings: Vec<String>,
+    /// Accounts the owner may select for a connection.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub accounts: Vec<String>,
+    /// RFC 3339 time the request was queued.
+    pub created: String,
+    /// RFC 3339 time the request is rejected if not decided.
+    pub expires: String,
+}
+
+/// Answer given by the owner when approving a request.
+#[derive(Debug, Default)]
+pub struct Answer {
+    /// Passphrase for the account of a signing request.
+    pub passphrase: Option<String>,
+    /// Accounts selected for a connection.
+    pub accounts: Vec<String>,
+}
+
+#[derive(Debug)]
+enum Decision {
+    Approve(Answer),
+    Reject,
+}
+
+struct Entry {
+    approval: PendingApproval,
+    sender: oneshot::Sender<Decision>,
+}
+
+/// Removes the queue entry when the waiting request finishes or
+/// is dropped.
+struct Waiting(u64);
+
+impl Drop for Waiting {
+    fn drop(&mut self) {
+        let removed = QUEUE.lock().unwrap().remove(&self.0).is_some();
+        if removed {
+            events::emit(WalletEvent::ApprovalsChanged);
+        }
+    }
+}
+
+/// Send requests that need consent to the queue rather than
+/// showing native dialogs.
+///
+/// Called when the wallet window is opened.
+pub fn enable_approvals() {
+    ENABLED.store(true, Ordering::SeqCst);
+}
+
+/// Determine if requests are sent to the queue.
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+/// Requests waiting for the owner, oldest first.
+pub fn pending_approvals() -> Vec<PendingApproval> {
+    let queue = QUEUE.lock().unwrap();
+    queue.values().map(|entry| entry.approval.clone()).collect()
+}
+
+fn decide(id: u64, decision: Decision) -> Result<()> {
+    let entry = QUEUE
+        .lock()
+        .unwrap()
+        .remove(&id)
+        .ok_or_else(|| anyhow!("no pending approval with id {}", id))?;
+    // The request may have been dropped since it was removed
+    let _ = entry.sender.send(decision);
+    events::emit(WalletEvent::ApprovalsChanged);
+    Ok(())
+}
+
+/// Approve a pending request.
+///
+/// Signing requests need the passphrase for the account and
+/// connection requests need at least one selected account.
+pub fn approve_request(
+    id: u64,
+    passphrase: Option<String>,
+    accounts: Option<Vec<String>>,
+) -> Result<()> {
+    let accounts = accounts.unwrap_or_default();
+    {
+        let queue = QUEUE.lock().unwrap();
+        let entry = queue
+            .get(&id)
+            .ok_or_else(|| anyhow!("no pending approval with id {}", id))?;
+        match entry.approval.kind {
+            ApprovalKind::Sign if passphrase.is_none() => {
+                return Err(anyhow!("passphrase is required"));
+            }
+            ApprovalKind::Connect => {
+                if accounts.is_empty() {
+                    return Err(anyhow!("select at least one account"));
+                }
+                if let Some(account) = accounts
+                    .iter()
+                    .find(|account| !entry.approval.accounts.contains(account))
+                {
+                    return Err(anyhow!("account {} was not offered", account));
+                }
+            }
+            _ => {}
+        }
+    }
+    decide(
+        id,
+        Decision::Approve(Answer {
+            passphrase,
+            accounts,
+        }),
+    )
+}
+
+/// Reject a pending request.
+pub fn reject_request(id: u64) -> Result<()> {
+    decide(id, Decision::Reject)
+}
+
+/// Add a request to the queue and wait for the owner to decide.
+///
+/// Rejected requests and requests that time out fail with the
+/// user rejected error.
+pub(super) async fn request(
+    ctx: &Context,
+    kind: ApprovalKind,
+    approval: &Approval,
+    accounts: Vec<String>,
+) -> ProviderResult<Answer> {
+    wait(ctx, kind, approval, accounts, APPROVAL_TIMEOUT).await
+}
+
+async fn wait(
+    ctx: &Context,
+    kind: ApprovalKind,
+    approval: &Approval,
+    accounts: Vec<String>,
+    timeout: Duration,
+) -> ProviderResult<Answer> {
+    let id = NEXT_ID.fetch_add(1, Ordering::SeqCst);
+    let now = Utc::now();
+    let expires = now
+        + ChronoDuration::from_std(timeout).map_err(ProviderError::internal)?;
+    let (sender, receiver) = oneshot::channel();
+    let pending = PendingApproval {
+        id,
+        kind,
+        client_id: ctx.client_id.clone(),
+        origin: ctx.origin(),
+        method: approval.method.clone(),
+        account: approval.account.clone(),
+        summary: approval.summary.clone(),
+        payload: approval.payload.clone(),
+        warnings: approval.warnings.clone(),
+        accounts,
+        created: now.to_rfc3339_opts(SecondsFormat::Secs, true),
+        expires: expires.to_rfc3339_opts(SecondsFormat::Secs, true),
+    };
+    QUEUE.lock().unwrap().insert(
+        id,
+        Entry {
+            approval: pending,
+            sender,
+        },
+    );
+    let _waiting = Waiting(id);
+    events::emit(WalletEvent::ApprovalsChanged);
+
+    match tokio::time::timeout(timeout, receiver).await {
+        Ok(Ok(Decision::Approve(answer))) => Ok(answer),
+        Ok(Ok(Decision::Reject)) | Ok(Err(_)) => {
+            Err(ProviderError::user_rejected())
+        }
+        Err(_) => Err(ProviderError::new(
+            USER_REJECTED,
+            "The request was not approved in time.",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context() -> Context {
+        Context {
+            client_id: "test-client".to_string(),
+            owner_id: "owner".to_string(),
+            scope: String::new(),
+            redirect_uri: "https://example.com/callback".parse().unwrap(),
+        }
+    }
+
+    fn spawn_request(
+        kind: ApprovalKind,
+        method: &'static str,
+    ) -> tokio::task::JoinHandle<ProviderResult<Answer>> {
+        tokio::spawn(async move {
+            let approval = Approval::new(method, "Test request");
+            request(&context(), kind, &approval, vec![]).await
+        })
+    }
+
+    async fn wait_for_entry(method: &str) -> PendingApproval {
+        loop {
+            if let Some(approval) =
+                pending_approvals().into_iter().find(|a| a.method == method)
+            {
+                return approval;
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    #[tokio::test]
+    async fn approve_and_reject_requests() -> Result<()> {
+        let ctx = context();
+
+        let waiting = spawn_request(ApprovalKind::Sign, "personal_sign");
+        let entry = wait_for_entry("personal_sign").await;
+        assert_eq!("test-client", entry.client_id);
+        assert_eq!("https://example.com", entry.origin);
+        assert!(approve_request(entry.id, None, None).is_err());
+        approve_request(entry.id, Some("passphrase".to_string()), None)?;
+        let answer = waiting.await?.unwrap();
+        assert_eq!(Some("passphrase".to_string()), answer.passphrase);
+        assert!(reject_request(entry.id).is_err());
+
+        let waiting =
+            spawn_request(ApprovalKind::Confirm, "wallet_addEthereumChain");
+        let entry = wait_for_entry("wallet_addEthereumChain").await;
+        reject_request(entry.id)?;
+        let error = waiting.await?.unwrap_err();
+        assert_eq!(USER_REJECTED, error.code);
+
+        let approval = Approval::new("eth_sendTransaction", "Send");
+        let error = wait(
+            &ctx,
+            ApprovalKind::Sign,
+            &approval,
+            vec![],
+            Duration::from_millis(10),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(USER_REJECTED, error.code);
+        assert!(pending_approvals()
+            .iter()
+            .all(|a| a.method != "eth_sendTransaction"));
+        Ok(())
+    }
+}
@@ -0,0 +1,337 @@
+//! Suggest tokens to track using `wallet_watchAsset` (EIP-747).
+use ethers_core::{
+    abi::{self, Token as AbiToken},
+    types::{Address, Bytes, U256},
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::tokens::{Token, TokenStandard, TOKEN_STORE};
+use crate::upstream::Upstream;
+
+use super::{
+    accounts::accounts,
+    chain_id,
+    consent::{self, Approval},
+    wallet::parse_address,
+    Context, ProviderError, ProviderResult, Request,
+};
+
+/// Selector for `decimals()`.
+const DECIMALS: [u8; 4] = [0x31, 0x3c, 0xe5, 0x67];
+/// Selector for `ownerOf(uint256)`.
+const OWNER_OF: [u8; 4] = [0x63, 0x52, 0x21, 0x1e];
+/// Selector for `balanceOf(address,uint256)`.
+const BALANCE_OF: [u8; 4] = [0x00, 0xfd, 0xd5, 0x8e];
+
+/// Parameter for `wallet_watchAsset`.
+#[derive(Debug, Deserialize)]
+struct WatchAssetParameter {
+    #[serde(rename = "type")]
+    standard: TokenStandard,
+    options: WatchAssetOptions,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WatchAssetOptions {
+    address: String,
+    symbol: Option<String>,
+    decimals: Option<u8>,
+    image: Option<String>,
+    /// Decimal identifier of a non-fungible token.
+    token_id: Option<String>,
+}
+
+/// Call a contract and return the first word of the result.
+async fn call_word(
+    upstream: &Upstream,
+    to: Address,
+    selector: [u8; 4],
+    args: &[AbiToken],
+) -> ProviderResult<[u8; 32]> {
+    let mut data = selector.to_vec();
+    data.extend(abi::encode(args));
+    let call = json!({ "to": to, "data": Bytes::from(data) });
+    let result: Bytes =
+        upstream.call("eth_call", json!([call, "latest"])).await?;
+    if result.as_ref().len() < 32 {
+        return Err(ProviderError::invalid_params(format!(
+            "unexpected result calling {}",
+            to
+        )));
+    }
+    let mut word = [0u8; 32];
+    word.copy_from_slice(&result.as_ref()[..32]);
+    Ok(word)
+}
+
+/// Verify the token contract and that the account owns a
+/// non-fungible token.
+async fn verify(
+    upstream: &Upstream,
+    account: Address,
+    token: &Token,
+) -> ProviderResult<()> {
+    let code: Bytes = upstream
+        .call("eth_getCode", json!([token.address, "latest"]))
+        .await?;
+    if code.as_ref().is_empty() {
+        return Err(ProviderError::invalid_params(format!(
+            "no contract at {}",
+            token.address
+        )));
+    }
+
+    match token.standard {
+        TokenStandard::ERC20 => {
+            // Some tokens do not implement decimals() so only
+            // a value that differs is an error
+            if let Ok(word) =
+                call_word(upstream, token.address, DECIMALS, &[]).await
+            {
+                let decimals = U256::from_big_endian(&word);
+                if Some(decimals) != token.decimals.map(U256::from) {
+                    return Err(ProviderError::invalid_params(format!(
+                        "token uses {} decimals",
+                        decimals
+                    )));
+                }
+            }
+        }
+        TokenStandard::ERC721 => {
+            let token_id = token.token_id.unwrap_or_default();
+            let word = call_word(
+                upstream,
+                token.address,
+                OWNER_OF,
+                &[AbiToken::Uint(token_id)],
+            )
+            .await?;
+            if Address::from_slice(&word[12..]) != account {
+                return Err(ProviderError::invalid_params(
+                    "account does not own the token",
+                ));
+            }
+        }
+        TokenStandard::ERC1155 => {
+            let token_id = token.token_id.unwrap_or_default();
+            let word = call_word(
+                upstream,
+                token.address,
+                BALANCE_OF,
+                &[AbiToken::Address(account), AbiToken::Uint(token_id)],
+            )
+            .await?;
+            if U256::from_big_endian(&word).is_zero() {
+                return Err(ProviderError::invalid_params(
+                    "account does not own the token",
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Parse and validate the token suggested by a client.
+fn parse_token(request: &Request, chain_id: u64) -> ProviderResult<Token> {
+    // Parameters are an object but some clients wrap it in an array
+    let param = match request.raw_params() {
+        Value::Array(mut params) if params.len() == 1 => params.remove(0),
+        param => param,
+    };
+    let param: WatchAssetParameter =
+        serde_json::from_value(param).map_err(ProviderError::invalid_params)?;
+    let options = param.options;
+    let token_id = options
+        .token_id
+        .map(|id| U256::from_dec_str(&id))
+        .transpose()
+        .map_err(ProviderError::invalid_params)?;
+
+    let token = Token {
+        standard: param.standard,
+        chain_id,
+        address: parse_address(&options.address)?,
+        token_id,
+        symbol: options.symbol,
+        decimals: options.decimals,
+        name: None,
+        image: options.image,
+    };
+    token.validate().map_err(ProviderError::invalid_params)?;
+    Ok(token)
+}
+
+/// Ask the owner to track a token for the first account
+/// exposed to the client.
+pub async fn watch_asset(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let account = accounts(ctx)
+        .into_iter()
+        .next()
+        .ok_or_else(ProviderError::unauthorized)?;
+
+    let token = parse_token(request, chain_id(ctx))?;
+
+    if TOKEN_STORE.read().unwrap().contains(&account, &token) {
+        return Ok(json!(true));
+    }
+
+    let upstream = Upstream::for_chain(token.chain_id)?;
+    verify(&upstream, parse_address(&account)?, &token).await?;
+
+    let mut summary =
+        format!("Track the {:?} token {}", token.standard, token.address);
+    if let Some(symbol) = &token.symbol {
+        summary.push_str(&format!(" ({})", symbol));
+    }
+    if let Some(token_id) = &token.token_id {
+        summary.push_str(&format!(" with id {}", token_id));
+    }
+    summary.push('?');
+    let approval = Approval::new(request.method(), summary)
+        .account(&account)
+        .payload(json!(token));
+    consent::confirm(ctx, approval).await?;
+
+    TOKEN_STORE.write().unwrap().add(&account, token)?;
+    Ok(json!(true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{mock::MockServer, INVALID_PARAMS};
+
+    const DAI: &str = "0x6B175474E89094C44Da98b954EedeAC495271d0F";
+    const ACCOUNT: &str = "0x9406Cc6185a346906296840746125a0E44976454";
+
+    fn request(params: Value) -> Request {
+        Request::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "wallet_watchAsset",
+            "params": params,
+        }))
+        .unwrap()
+    }
+
+    fn erc20() -> Value {
+        json!({
+            "type": "ERC20",
+            "options": {
+                "address": DAI,
+                "symbol": "DAI",
+                "decimals": 18,
+                "image": "https://example.com/dai.png",
+            },
+        })
+    }
+
+    fn error_code(param: Value) -> i64 {
+        parse_token(&request(param), 1).unwrap_err().code
+    }
+
+    #[test]
+    fn parse_watch_asset_params() {
+        let token = parse_token(&request(erc20()), 137).unwrap();
+        assert_eq!(TokenStandard::ERC20, token.standard);
+        assert_eq!(137, token.chain_id);
+        assert_eq!(DAI.parse::<Address>().unwrap(), token.address);
+        assert_eq!(Some(18), token.decimals);
+
+        // Some clients wrap the parameter in an array
+        let wrapped = parse_token(&request(json!([erc20()])), 1).unwrap();
+        assert_eq!(Some("DAI"), wrapped.symbol.as_deref());
+
+        let nft = json!({
+            "type": "ERC721",
+            "options": { "address": DAI, "tokenId": "42" },
+        });
+        let token = parse_token(&request(nft), 1).unwrap();
+        assert_eq!(Some(U256::from(42)), token.token_id);
+    }
+
+    #[test]
+    fn reject_invalid_watch_asset_params() {
+        let invalid = |f: fn(&mut Value)| {
+            let mut param = erc20();
+            f(&mut param);
+            error_code(param)
+        };
+        assert_eq!(INVALID_PARAMS, invalid(|p| p["type"] = json!("ERC777")));
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| p["options"]["address"] = json!("0x6B17"))
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| p["options"]["symbol"] = json!("DAISTABLECOIN"))
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| p["options"]["decimals"] = json!(37))
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| {
+                p["options"]["image"] = json!("http://example.com/dai.png")
+            })
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| p["options"]["tokenId"] = json!("0x2a"))
+        );
+        assert_eq!(INVALID_PARAMS, error_code(json!([erc20(), erc20()])));
+    }
+
+    #[tokio::test]
+    async fn verify_token_contract() {
+        let account: Address = ACCOUNT.parse().unwrap();
+        let word = |token: AbiToken| json!(Bytes::from(abi::encode(&[token])));
+        let node = MockServer::start(move |method, params| {
+            let data = params[0]["data"].as_str().unwrap_or_default();
+            Ok(match method {
+                "eth_getCode" if params[0] == json!(DAI.to_lowercase()) => {
+                    json!("0x6080")
+                }
+                "eth_getCode" => json!("0x"),
+                // decimals()
+                "eth_call" if data.starts_with("0x313ce567") => {
+                    word(AbiToken::Uint(U256::from(18)))
+                }
+                // ownerOf(uint256)
+                "eth_call" => word(AbiToken::Address(account)),
+                _ => Value::Null,
+            })
+        });
+        let upstream = node.upstream(1);
+
+        let token = parse_token(&request(erc20()), 1).unwrap();
+        verify(&upstream, account, &token).await.unwrap();
+
+        let mut param = erc20();
+        param["options"]["decimals"] = json!(6);
+        let token = parse_token(&request(param), 1).unwrap();
+        let error = verify(&upstream, account, &token).await.unwrap_err();
+        assert_eq!(INVALID_PARAMS, error.code);
+
+        let nft = json!({
+            "type": "ERC721",
+            "options": { "address": DAI, "tokenId": "42" },
+        });
+        let token = parse_token(&request(nft), 1).unwrap();
+        verify(&upstream, account, &token).await.unwrap();
+        let other = Address::from_low_u64_be(1);
+        assert!(verify(&upstream, other, &token).await.is_err());
+
+        let token = Token {
+            address: other,
+            ..token
+        };
+        assert!(verify(&upstream, account, &token).await.is_err());
+    }
+}
@@ -0,0 +1,578 @@
+//! Batches of calls using `wallet_sendCalls` (EIP-5792).
+//!
+//! Accounts are externally owned so a batch cannot be executed
+//! atomically; the owner approves the batch once and the calls
+//! are sent as a sequence of transactions. Each call is only sent
+//! after the previous one is included so that gas for later calls
+//! is estimated against the updated state. The status of every
+//! batch is kept in memory for the client that sent it until a
+//! day after the batch finished.
+use ethers_core::types::{Address, Bytes, H256, U256, U64};
+use ethers_signers::LocalWallet;
+use once_cell::sync::Lazy;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+
+use crate::helpers::format_address;
+use crate::upstream::Upstream;
+
+use super::{
+    accounts::accounts,
+    chain_id,
+    consent::{self, Approval},
+    send::fill_transaction,
+    transaction::{sign_params, TransactionParams},
+    wallet::{ensure_connected, parse_address, unlock},
+    Context, ProviderError, ProviderResult, Request, ATOMICITY_NOT_SUPPORTED,
+    BATCH_TOO_LARGE, DUPLICATE_ID, UNKNOWN_BUNDLE, UNSUPPORTED_CAPABILITY,
+    UNSUPPORTED_CHAIN,
+};
+
+/// Version of the calls API.
+const VERSION: &str = "2.0.0";
+
+/// Maximum number of calls in a batch.
+const MAX_CALLS: usize = 16;
+
+/// Longest batch identifier accepted from a client.
+const MAX_ID_LENGTH: usize = 4096;
+
+/// Interval between checks for a receipt.
+const POLL_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Time to wait for a transaction to be included.
+const RECEIPT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Time the status of a finished batch is kept.
+const BATCH_TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// Batch is being sent.
+const STATUS_PENDING: u16 = 100;
+/// Every call was included and succeeded.
+const STATUS_CONFIRMED: u16 = 200;
+/// No call was included.
+const STATUS_FAILED: u16 = 400;
+/// Every included call reverted.
+const STATUS_REVERTED: u16 = 500;
+/// Some calls were included before the batch failed.
+const STATUS_PARTIAL: u16 = 600;
+
+static BATCHES: Lazy<RwLock<HashMap<String, Batch>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Parameter for `wallet_sendCalls`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SendCallsParameter {
+    version: String,
+    #[serde(default)]
+    id: Option<String>,
+    #[serde(default)]
+    from: Option<Address>,
+    chain_id: U64,
+    #[serde(default)]
+    atomic_required: bool,
+    calls: Vec<Call>,
+    #[serde(default)]
+    capabilities: Map<String, Value>,
+}
+
+/// Call in a batch.
+#[derive(Debug, Deserialize)]
+struct Call {
+    #[serde(default)]
+    to: Option<Address>,
+    #[serde(default)]
+    data: Option<Bytes>,
+    #[serde(default)]
+    value: Option<U256>,
+    #[serde(default)]
+    capabilities: Map<String, Value>,
+}
+
+/// Batch of calls sent by a client.
+#[derive(Debug)]
+struct Batch {
+    client_id: String,
+    chain_id: u64,
+    status: u16,
+    /// Receipts of the included calls.
+    receipts: Vec<Value>,
+    /// When the last call was sent or the batch failed.
+    finished: Option<Instant>,
+}
+
+/// Remove batches that finished longer ago than the TTL.
+fn evict_finished(batches: &mut HashMap<String, Batch>, now: Instant) {
+    batches.retain(|_, batch| {
+        batch
+            .finished
+            .map_or(true, |finished| now.duration_since(finished) < BATCH_TTL)
+    });
+}
+
+/// Status of a finished batch from the number of calls that were
+/// included and that succeeded.
+fn batch_status(count: usize, included: usize, succeeded: usize) -> u16 {
+    if succeeded == count {
+        STATUS_CONFIRMED
+    } else if included == 0 {
+        STATUS_FAILED
+    } else if succeeded == 0 {
+        STATUS_REVERTED
+    } else {
+        STATUS_PARTIAL
+    }
+}
+
+/// Capabilities must be supported unless they are optional.
+///
+/// No capabilities are supported yet.
+fn ensure_capabilities(
+    capabilities: &Map<String, Value>,
+) -> ProviderResult<()> {
+    for (name, capability) in capabilities {
+        let optional = capability
+            .get("optional")
+            .and_then(Value::as_bool)
+            .unwrap_or(false);
+        if !optional {
+            return Err(ProviderError::new(
+                UNSUPPORTED_CAPABILITY,
+                format!("Unsupported capability {}.", name),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Check the parameter of `wallet_sendCalls` before asking the
+/// owner.
+fn check_calls(param: &SendCallsParameter) -> ProviderResult<()> {
+    if param.version != VERSION && param.version != "1.0" {
+        return Err(ProviderError::invalid_params(format!(
+            "unsupported version {}",
+            param.version
+        )));
+    }
+    if param.atomic_required {
+        return Err(ProviderError::new(
+            ATOMICITY_NOT_SUPPORTED,
+            "Calls cannot be executed atomically.",
+        ));
+    }
+    if param.calls.is_empty() {
+        return Err(ProviderError::invalid_params("calls must not be empty"));
+    }
+    if param.calls.len() > MAX_CALLS {
+        return Err(ProviderError::new(
+            BATCH_TOO_LARGE,
+            format!("A batch may contain at most {} calls.", MAX_CALLS),
+        ));
+    }
+    ensure_capabilities(&param.capabilities)?;
+    for call in &param.calls {
+        ensure_capabilities(&call.capabilities)?;
+        let has_data =
+            call.data.as_ref().map_or(false, |d| !d.as_ref().is_empty());
+        if call.to.is_none() && !has_data {
+            return Err(ProviderError::invalid_params(
+                "a call without a recipient must have data",
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Identifier for a new batch; a random identifier is used when
+/// the client does not give one.
+fn batch_id(id: Option<String>) -> ProviderResult<String> {
+    let id = match id {
+        Some(id) if id.is_empty() || id.len() > MAX_ID_LENGTH => {
+            return Err(ProviderError::invalid_params("id is invalid"));
+        }
+        Some(id) => id,
+        None => format!("0x{}", hex::encode(rand::random::<[u8; 32]>())),
+    };
+    if BATCHES.read().unwrap().contains_key(&id) {
+        return Err(ProviderError::new(
+            DUPLICATE_ID,
+            format!("Batch {} already exists.", id),
+        ));
+    }
+    Ok(id)
+}
+
+/// Get a batch sent by the client.
+fn with_batch<T>(
+    ctx: &Context,
+    id: &str,
+    func: impl FnOnce(&Batch) -> T,
+) -> ProviderResult<T> {
+    let batches = BATCHES.read().unwrap();
+    batches
+        .get(id)
+        .filter(|batch| batch.client_id == ctx.client_id)
+        .map(func)
+        .ok_or_else(|| {
+            ProviderError::new(UNKNOWN_BUNDLE, format!("Unknown batch {}.", id))
+        })
+}
+
+fn update_batch(id: &str, func: impl FnOnce(&mut Batch)) {
+    if let Some(batch) = BATCHES.write().unwrap().get_mut(id) {
+        func(batch);
+    }
+}
+
+/// Capabilities of the wallet using `wallet_getCapabilities`.
+///
+/// Parameters are `[address, chainIds]`; when no chains are given
+/// the chain of the client is used.
+pub fn get_capabilities(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let params: Vec<Value> = request.params()?;
+    let address = params
+        .get(0)
+        .and_then(Value::as_str)
+        .ok_or_else(|| ProviderError::invalid_params("address is required"))?;
+    ensure_connected(ctx, address)?;
+
+    let chains: Vec<U64> = match params.get(1) {
+        Some(chains) => serde_json::from_value(chains.clone())
+            .map_err(ProviderError::invalid_params)?,
+        None => vec![U64::from(chain_id(ctx))],
+    };
+    let capabilities: Map<String, Value> = chains
+        .into_iter()
+        .map(|id| {
+            let capabilities = json!({ "atomic": { "status": "unsupported" } });
+            (format!("{:#x}", id), capabilities)
+        })
+        .collect();
+    Ok(Value::Object(capabilities))
+}
+
+/// Ask the owner to send a batch of calls using
+/// `wallet_sendCalls`.
+///
+/// Returns the batch identifier once approved; the calls are
+/// sent in the background.
+pub async fn send_calls(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (param,): (SendCallsParameter,) = request.params()?;
+    check_calls(&param)?;
+
+    let chain_id = chain_id(ctx);
+    if param.chain_id.as_u64() != chain_id {
+        return Err(ProviderError::new(
+            UNSUPPORTED_CHAIN,
+            format!(
+                "Calls for chain {} cannot be sent while the client uses \
+                 chain {}.",
+                param.chain_id, chain_id
+            ),
+        ));
+    }
+
+    let from = match param.from {
+        Some(from) => from,
+        None => {
+            let account = accounts(ctx).into_iter().next();
+            parse_address(&account.ok_or_else(ProviderError::unauthorized)?)?
+        }
+    };
+    let account = format_address(from);
+    ensure_connected(ctx, &account)?;
+
+    let id = batch_id(param.id)?;
+
+    let transactions: Vec<TransactionParams> = param
+        .calls
+        .into_iter()
+        .map(|call| TransactionParams {
+            from: Some(from),
+            to: call.to,
+            value: call.value,
+            data: call.data,
+            ..Default::default()
+        })
+        .collect();
+
+    let mut summary = format!(
+        "Send {} calls on chain {} in order?",
+        transactions.len(),
+        chain_id
+    );
+    for (index, tx) in transactions.iter().enumerate() {
+        summary.push_str(&format!("\n\nCall {}\n{}", index + 1, tx.describe()));
+    }
+    let approval = Approval::new(request.method(), summary)
+        .account(&account)
+        .payload(json!({ "chainId": chain_id, "calls": transactions }))
+        .warning(
+            "The calls are sent as separate transactions. If a call \
+             fails the remaining calls are not sent.",
+        );
+    let passphrase = consent::approve(ctx, approval).await?;
+    let wallet = unlock(&account, passphrase).await?;
+    let upstream = Upstream::for_chain(chain_id)?;
+
+    {
+        let mut batches = BATCHES.write().unwrap();
+        evict_finished(&mut batches, Instant::now());
+        if batches.contains_key(&id) {
+            return Err(ProviderError::new(
+                DUPLICATE_ID,
+                format!("Batch {} already exists.", id),
+            ));
+        }
+        batches.insert(
+            id.clone(),
+            Batch {
+                client_id: ctx.client_id.clone(),
+                chain_id,
+                status: STATUS_PENDING,
+                receipts: vec![],
+                finished: None,
+            },
+        );
+    }
+    tokio::spawn(execute(id.clone(), upstream, wallet, transactions));
+
+    Ok(json!({ "id": id }))
+}
+
+/// Send the calls in a batch one after another.
+async fn execute(
+    id: String,
+    upstream: Upstream,
+    wallet: LocalWallet,
+    transactions: Vec<TransactionParams>,
+) {
+    let count = transactions.len();
+    let mut included = 0;
+    let mut succeeded = 0;
+    for mut tx in transactions {
+        match send_and_wait(&upstream, &wallet, &mut tx).await {
+            Ok(receipt) => {
+                included += 1;
+                let success = receipt.get("status") == Some(&json!("0x1"));
+                update_batch(&id, |batch| batch.receipts.push(receipt));
+                if !success {
+                    break;
+                }
+                succeeded += 1;
+            }
+            Err(e) => {
+                log::warn!("call in batch {} failed: {}", id, e.message);
+                break;
+            }
+        }
+    }
+
+    let status = batch_status(count, included, succeeded);
+    update_batch(&id, |batch| {
+        batch.status = status;
+        batch.finished = Some(Instant::now());
+    });
+}
+
+/// Send a transaction and wait for the receipt.
+async fn send_and_wait(
+    upstream: &Upstream,
+    wallet: &LocalWallet,
+    tx: &mut TransactionParams,
+) -> ProviderResult<Value> {
+    let reservation = fill_transaction(upstream, tx).await?;
+    let signed = sign_params(wallet.clone(), tx, upstream.chain_id())
+        .await
+        .map_err(ProviderError::invalid_params)?;
+    let hash: H256 = upstream
+        .call("eth_sendRawTransaction", json!([signed.raw]))
+        .await?;
+    if let Some(reservation) = reservation {
+        reservation.commit(hash);
+    }
+
+    let started = Instant::now();
+    while started.elapsed() < RECEIPT_TIMEOUT {
+        tokio::time::sleep(POLL_INTERVAL).await;
+        let receipt: Option<Value> = upstream
+            .call("eth_getTransactionReceipt", json!([hash]))
+            .await?;
+        if let Some(receipt) = receipt {
+            return Ok(json!({
+                "logs": receipt["logs"],
+                "status": receipt["status"],
+                "blockHash": receipt["blockHash"],
+                "blockNumber": receipt["blockNumber"],
+                "gasUsed": receipt["gasUsed"],
+                "transactionHash": receipt["transactionHash"],
+            }));
+        }
+    }
+    Err(ProviderError::internal(format!(
+        "transaction {:#x} was not included",
+        hash
+    )))
+}
+
+/// Status of a batch using `wallet_getCallsStatus`.
+pub fn get_calls_status(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (id,): (String,) = request.params()?;
+    with_batch(ctx, &id, |batch| {
+        json!({
+            "version": VERSION,
+            "id": id,
+            "chainId": format!("{:#x}", batch.chain_id),
+            "atomic": false,
+            "status": batch.status,
+            "receipts": batch.receipts,
+        })
+    })
+}
+
+/// Show the status of a batch to the owner using
+/// `wallet_showCallsStatus`.
+///
+/// Returns without waiting for the owner to dismiss the status.
+pub async fn show_calls_status(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (id,): (String,) = request.params()?;
+    let summary = with_batch(ctx, &id, |batch| {
+        let status = match batch.status {
+            STATUS_PENDING => "is being sent",
+            STATUS_CONFIRMED => "was confirmed",
+            STATUS_FAILED => "was not sent",
+            STATUS_REVERTED => "reverted",
+            _ => "was only partially sent",
+        };
+        format!(
+            "The batch {} {}; {} calls were included on chain {}.",
+            id,
+            status,
+            batch.receipts.len(),
+            batch.chain_id
+        )
+    })?;
+    consent::inform(ctx, Approval::new(request.method(), summary));
+    Ok(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::INVALID_PARAMS;
+
+    fn batch(finished: Option<Instant>) -> Batch {
+        Batch {
+            client_id: "test-client".to_string(),
+            chain_id: 1,
+            status: STATUS_PENDING,
+            receipts: vec![],
+            finished,
+        }
+    }
+
+    fn send_calls_value() -> Value {
+        json!({
+            "version": "2.0.0",
+            "chainId": "0x1",
+            "calls": [{
+                "to": "0x0000000000000000000000000000000000000001",
+                "value": "0x1",
+            }],
+        })
+    }
+
+    fn error(value: Value) -> i64 {
+        let param: SendCallsParameter = serde_json::from_value(value).unwrap();
+        check_calls(&param).unwrap_err().code
+    }
+
+    #[test]
+    fn validate_send_calls_parameter() {
+        let param = serde_json::from_value(send_calls_value()).unwrap();
+        assert!(check_calls(&param).is_ok());
+
+        let mut value = send_calls_value();
+        value["version"] = json!("1.0");
+        let param = serde_json::from_value(value.clone()).unwrap();
+        assert!(check_calls(&param).is_ok());
+        value["version"] = json!("3.0.0");
+        assert_eq!(INVALID_PARAMS, error(value));
+
+        let mut value = send_calls_value();
+        value["atomicRequired"] = json!(true);
+        assert_eq!(ATOMICITY_NOT_SUPPORTED, error(value));
+
+        let mut value = send_calls_value();
+        value["calls"] = json!([]);
+        assert_eq!(INVALID_PARAMS, error(value.clone()));
+        let call = send_calls_value()["calls"][0].clone();
+        value["calls"] = json!(vec![call; MAX_CALLS + 1]);
+        assert_eq!(BATCH_TOO_LARGE, error(value));
+
+        let mut value = send_calls_value();
+        value["calls"][0] = json!({ "value": "0x1" });
+        assert_eq!(INVALID_PARAMS, error(value));
+
+        // Only optional capabilities are accepted
+        let capability = json!({ "paymasterService": { "url": "https://" } });
+        let mut value = send_calls_value();
+        value["capabilities"] = capability.clone();
+        assert_eq!(UNSUPPORTED_CAPABILITY, error(value.clone()));
+        value["capabilities"]["paymasterService"]["optional"] = json!(true);
+        let param = serde_json::from_value(value).unwrap();
+        assert!(check_calls(&param).is_ok());
+        let mut value = send_calls_value();
+        value["calls"][0]["capabilities"] = capability;
+        assert_eq!(UNSUPPORTED_CAPABILITY, error(value));
+    }
+
+    #[test]
+    fn reject_invalid_batch_id() {
+        let id = "duplicate-batch-id".to_string();
+        BATCHES.write().unwrap().insert(id.clone(), batch(None));
+        assert_eq!(DUPLICATE_ID, batch_id(Some(id)).unwrap_err().code);
+        let error = batch_id(Some(String::new())).unwrap_err();
+        assert_eq!(INVALID_PARAMS, error.code);
+        let error = batch_id(Some("a".repeat(MAX_ID_LENGTH + 1))).unwrap_err();
+        assert_eq!(INVALID_PARAMS, error.code);
+        assert_eq!(66, batch_id(None).unwrap().len());
+    }
+
+    #[test]
+    fn map_batch_status() {
+        assert_eq!(STATUS_CONFIRMED, batch_status(3, 3, 3));
+        assert_eq!(STATUS_FAILED, batch_status(3, 0, 0));
+        assert_eq!(STATUS_REVERTED, batch_status(3, 1, 0));
+        assert_eq!(STATUS_PARTIAL, batch_status(3, 2, 1));
+    }
+
+    #[test]
+    fn evict_finished_batches() {
+        let now = Instant::now();
+        let mut batches = HashMap::new();
+        batches.insert("pending".to_string(), batch(None));
+        batches.insert("finished".to_string(), batch(Some(now)));
+        evict_finished(&mut batches, now + BATCH_TTL / 2);
+        assert_eq!(2, batches.len());
+        evict_finished(&mut batches, now + BATCH_TTL);
+        assert!(batches.contains_key("pending"));
+        assert!(!batches.contains_key("finished"));
+    }
+}
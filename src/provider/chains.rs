@@ -0,0 +1,322 @@
+//! Add and switch networks (EIP-3085 and EIP-3326).
+use serde::Deserialize;
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::network::{NativeCurrency, Network, NETWORK_REGISTRY};
+use crate::user::USER_DATA;
+
+use super::{
+    consent::{self, Approval},
+    Context, ProviderError, ProviderResult, Request, UNRECOGNIZED_CHAIN,
+};
+
+/// Parameter for `wallet_addEthereumChain`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AddEthereumChainParameter {
+    chain_id: String,
+    chain_name: String,
+    native_currency: NativeCurrency,
+    rpc_urls: Vec<String>,
+    #[serde(default)]
+    block_explorer_urls: Option<Vec<String>>,
+}
+
+impl AddEthereumChainParameter {
+    /// Convert to a validated network.
+    fn into_network(self) -> ProviderResult<Network> {
+        let parse_urls = |urls: Vec<String>| -> ProviderResult<Vec<Url>> {
+            urls.iter()
+                .map(|url| url.parse::<Url>())
+                .collect::<Result<_, _>>()
+                .map_err(ProviderError::invalid_params)
+        };
+        let network = Network {
+            chain_id: parse_chain_id(&self.chain_id)?,
+            name: self.chain_name,
+            native_currency: self.native_currency,
+            rpc_urls: parse_urls(self.rpc_urls)?,
+            block_explorer_urls: parse_urls(
+                self.block_explorer_urls.unwrap_or_default(),
+            )?,
+        };
+        network.validate().map_err(ProviderError::invalid_params)?;
+        Ok(network)
+    }
+}
+
+/// Parameter for `wallet_switchEthereumChain`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SwitchEthereumChainParameter {
+    chain_id: String,
+}
+
+/// Parse a chain identifier encoded as a hex string without
+/// leading zeros.
+fn parse_chain_id(value: &str) -> ProviderResult<u64> {
+    let digits = value.strip_prefix("0x").ok_or_else(|| {
+        ProviderError::invalid_params("chainId must be a hex string")
+    })?;
+    if digits.is_empty() || digits.starts_with('0') {
+        return Err(ProviderError::invalid_params(
+            "chainId must not contain leading zeros",
+        ));
+    }
+    u64::from_str_radix(digits, 16).map_err(ProviderError::invalid_params)
+}
+
+/// Consent required to switch a client to a chain.
+#[derive(Debug, PartialEq)]
+enum SwitchConsent {
+    /// The chain was granted with `endowment:permitted-chains`
+    /// and does not need to be confirmed again.
+    Permitted,
+    /// The owner must confirm the switch; clients holding the
+    /// grant are then permitted these chains.
+    Confirm(Option<Vec<u64>>),
+}
+
+/// Determine the consent required to switch to a chain given
+/// the chains permitted to the client.
+fn switch_consent(permitted: Vec<u64>, chain_id: u64) -> SwitchConsent {
+    if permitted.contains(&chain_id) {
+        SwitchConsent::Permitted
+    } else if permitted.is_empty() {
+        SwitchConsent::Confirm(None)
+    } else {
+        let mut chains = permitted;
+        chains.push(chain_id);
+        SwitchConsent::Confirm(Some(chains))
+    }
+}
+
+/// Only the owner of the grant may change the network.
+fn ensure_owner(ctx: &Context) -> ProviderResult<()> {
+    let user = USER_DATA.read().unwrap();
+    if user.owner_id().as_ref() != Some(&ctx.owner_id) {
+        return Err(ProviderError::unauthorized());
+    }
+    Ok(())
+}
+
+/// Select the chain for a client.
+fn set_chain(ctx: &Context, chain_id: u64) -> ProviderResult<()> {
+    let mut user = USER_DATA.write().unwrap();
+    Ok(user.switch_chain(&ctx.client_id, chain_id)?)
+}
+
+/// Add a network using `wallet_addEthereumChain` and switch
+/// the client to it.
+///
+/// Chains that are already known are not replaced; the client
+/// is switched to the known chain instead.
+pub async fn add_chain(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    ensure_owner(ctx)?;
+    let (param,): (AddEthereumChainParameter,) = request.params()?;
+    let network = param.into_network()?;
+
+    let known = NETWORK_REGISTRY
+        .read()
+        .unwrap()
+        .get(network.chain_id)
+        .is_some();
+    if known {
+        return switch_to(ctx, request.method(), network.chain_id).await;
+    }
+
+    network
+        .verify()
+        .await
+        .map_err(ProviderError::invalid_params)?;
+
+    let summary = format!(
+        "Add the network {} (chain {}) using {} and switch to it?",
+        network.name, network.chain_id, network.rpc_urls[0]
+    );
+    let approval = Approval::new(request.method(), summary)
+        .payload(json!(network))
+        .warning(
+            "A malicious network provider can lie about the state \
+             of the blockchain and record your network activity.",
+        );
+    consent::confirm(ctx, approval).await?;
+
+    let chain_id = network.chain_id;
+    NETWORK_REGISTRY.write().unwrap().add(network)?;
+    set_chain(ctx, chain_id)?;
+    Ok(Value::Null)
+}
+
+/// Switch the client to a known network using
+/// `wallet_switchEthereumChain`.
+pub async fn switch_chain(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    ensure_owner(ctx)?;
+    let (param,): (SwitchEthereumChainParameter,) = request.params()?;
+    let chain_id = parse_chain_id(&param.chain_id)?;
+    switch_to(ctx, request.method(), chain_id).await
+}
+
+/// Ask the owner to switch the client to a chain.
+async fn switch_to(
+    ctx: &Context,
+    method: &str,
+    chain_id: u64,
+) -> ProviderResult<Value> {
+    if chain_id == super::chain_id(ctx) {
+        return Ok(Value::Null);
+    }
+
+    let network = NETWORK_REGISTRY.read().unwrap().get(chain_id).cloned();
+    let network = network.ok_or_else(|| {
+        ProviderError::new(
+            UNRECOGNIZED_CHAIN,
+            format!(
+                "Unrecognized chain ID {:#x}. Add the chain using \
+                 wallet_addEthereumChain first.",
+                chain_id
+            ),
+        )
+    })?;
+
+    let permitted = USER_DATA.read().unwrap().permitted_chains(&ctx.client_id);
+    if let SwitchConsent::Confirm(grant) = switch_consent(permitted, chain_id) {
+        let summary = format!(
+            "Switch to the network {} (chain {})?",
+            network.name, network.chain_id
+        );
+        consent::confirm(ctx, Approval::new(method, summary)).await?;
+        if let Some(chains) = grant {
+            let mut user = USER_DATA.write().unwrap();
+            user.set_permitted_chains(&ctx.client_id, chains)?;
+        }
+    }
+    set_chain(ctx, chain_id)?;
+    Ok(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{mock::MockServer, INVALID_PARAMS};
+    use ethers_core::types::U64;
+
+    fn request(method: &str, param: Value) -> Request {
+        Request::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": [param],
+        }))
+        .unwrap()
+    }
+
+    fn add_chain_param(rpc_url: &str) -> Value {
+        json!({
+            "chainId": "0x2a15c308d",
+            "chainName": "Test Network",
+            "nativeCurrency": {
+                "name": "Test Ether",
+                "symbol": "TETH",
+                "decimals": 18,
+            },
+            "rpcUrls": [rpc_url],
+            "blockExplorerUrls": ["https://explorer.example.com"],
+        })
+    }
+
+    fn into_network(param: Value) -> ProviderResult<Network> {
+        let request = request("wallet_addEthereumChain", param);
+        let (param,): (AddEthereumChainParameter,) = request.params()?;
+        param.into_network()
+    }
+
+    #[test]
+    fn parse_chain_ids() {
+        assert_eq!(1, parse_chain_id("0x1").unwrap());
+        assert_eq!(137, parse_chain_id("0x89").unwrap());
+        for invalid in ["1", "0x", "0x01", "0x0", "0xzz", "0x1ffffffffffffffff"]
+        {
+            let error = parse_chain_id(invalid).unwrap_err();
+            assert_eq!(INVALID_PARAMS, error.code, "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn validate_add_chain_params() {
+        let network =
+            into_network(add_chain_param("https://rpc.example.com")).unwrap();
+        assert_eq!(11_297_108_109, network.chain_id);
+        assert_eq!("TETH", network.native_currency.symbol);
+        assert_eq!(1, network.block_explorer_urls.len());
+
+        let invalid = |f: fn(&mut Value)| {
+            let mut param = add_chain_param("https://rpc.example.com");
+            f(&mut param);
+            into_network(param).unwrap_err().code
+        };
+        assert_eq!(INVALID_PARAMS, invalid(|p| p["chainId"] = json!(1)));
+        assert_eq!(INVALID_PARAMS, invalid(|p| p["chainId"] = json!("0x0")));
+        assert_eq!(INVALID_PARAMS, invalid(|p| p["chainName"] = json!(" ")));
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| p["nativeCurrency"]["symbol"] = json!("T"))
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| p["nativeCurrency"]["decimals"] = json!(8))
+        );
+        assert_eq!(INVALID_PARAMS, invalid(|p| p["rpcUrls"] = json!([])));
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| p["rpcUrls"] = json!(["http://rpc.example.com"]))
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| p["rpcUrls"] = json!(["not a url"]))
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            invalid(|p| {
+                p["blockExplorerUrls"] = json!(["http://explorer.example.com"])
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn verify_declared_chain_id() {
+        let node = MockServer::start(|method, _| {
+            assert_eq!("eth_chainId", method);
+            Ok(json!(U64::from(137)))
+        });
+        let mut network =
+            into_network(add_chain_param(node.url().as_str())).unwrap();
+        assert!(network.verify().await.is_err());
+
+        network.chain_id = 137;
+        network.verify().await.unwrap();
+        assert_eq!(vec!["eth_chainId", "eth_chainId"], node.methods());
+    }
+
+    #[test]
+    fn switch_to_permitted_chains() {
+        // Chains granted with `endowment:permitted-chains`
+        assert_eq!(SwitchConsent::Permitted, switch_consent(vec![1, 10], 10));
+
+        // Clients holding the grant are permitted confirmed chains
+        assert_eq!(
+            SwitchConsent::Confirm(Some(vec![1, 10, 137])),
+            switch_consent(vec![1, 10], 137)
+        );
+
+        // Other clients confirm every switch
+        assert_eq!(SwitchConsent::Confirm(None), switch_consent(vec![], 137));
+    }
+}
@@ -0,0 +1,215 @@
+//! Ask the resource owner to consent to a provider request.
+//!
+//! Requests wait in the approval queue while the wallet window
+//! is open; otherwise native dialogs are shown. Native dialogs
+//! block so they are shown on a blocking thread.
+use serde_json::Value;
+use tinyfiledialogs::{
+    message_box_ok, message_box_yes_no, password_box, MessageBoxIcon, YesNo,
+};
+
+use super::{
+    approvals::{self, ApprovalKind},
+    Context, ProviderError, ProviderResult,
+};
+
+const TITLE: &str = "MetaMask";
+
+/// Request presented to the owner for approval.
+#[derive(Debug, Clone)]
+pub struct Approval {
+    /// Method that requires approval.
+    pub method: String,
+    /// Account that will be used.
+    pub account: Option<String>,
+    /// Human-readable description of the request.
+    pub summary: String,
+    /// Decoded request payload.
+    pub payload: Value,
+    /// Warnings the owner should read before approving.
+    pub warnings: Vec<String>,
+}
+
+impl Approval {
+    pub fn new<S: Into<String>>(method: &str, summary: S) -> Self {
+        Self {
+            method: method.to_string(),
+            account: None,
+            summary: summary.into(),
+            payload: Value::Null,
+            warnings: vec![],
+        }
+    }
+
+    pub fn account(mut self, account: &str) -> Self {
+        self.account = Some(account.to_string());
+        self
+    }
+
+    pub fn payload(mut self, payload: Value) -> Self {
+        self.payload = payload;
+        self
+    }
+
+    pub fn warning<S: Into<String>>(mut self, warning: S) -> Self {
+        self.warnings.push(warning.into());
+        self
+    }
+
+    /// Icon shown with the message.
+    fn icon(&self) -> MessageBoxIcon {
+        if self.warnings.is_empty() {
+            MessageBoxIcon::Question
+        } else {
+            MessageBoxIcon::Warning
+        }
+    }
+
+    /// Text shown to the owner.
+    fn message(&self, ctx: &Context) -> String {
+        let mut message =
+            format!("{} is requesting {}.\n\n", ctx.client_id, self.method);
+        if let Some(account) = &self.account {
+            message.push_str(&format!("Account: {}\n\n", account));
+        }
+        message.push_str(&self.summary);
+        for warning in &self.warnings {
+            message.push_str(&format!("\n\nWARNING: {}", warning));
+        }
+        message
+    }
+}
+
+/// Run a blocking dialog without stalling the async runtime.
+async fn blocking<F, T>(func: F) -> ProviderResult<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(func)
+        .await
+        .map_err(ProviderError::internal)
+}
+
+/// Ask the owner which accounts to expose to a client.
+///
+/// If no accounts are selected the request is rejected.
+pub async fn select_accounts(
+    ctx: &Context,
+    accounts: Vec<String>,
+) -> ProviderResult<Vec<String>> {
+    if approvals::is_enabled() {
+        let approval = Approval::new(
+            "eth_requestAccounts",
+            "Select the accounts to connect to your wallet.",
+        );
+        let answer =
+            approvals::request(ctx, ApprovalKind::Connect, &approval, accounts)
+                .await?;
+        return Ok(answer.accounts);
+    }
+
+    let client_id = ctx.client_id.clone();
+    let selected = blocking(move || {
+        accounts
+            .into_iter()
+            .filter(|address| {
+                let message = format!(
+                    "{} is requesting to connect to your wallet.\n\nAllow it to see the account {}?",
+                    client_id, address
+                );
+                let answer = message_box_yes_no(
+                    TITLE,
+                    &message,
+                    MessageBoxIcon::Question,
+                    YesNo::No,
+                );
+                answer == YesNo::Yes
+            })
+            .collect::<Vec<_>>()
+    })
+    .await?;
+
+    if selected.is_empty() {
+        return Err(ProviderError::user_rejected());
+    }
+    Ok(selected)
+}
+
+/// Ask the owner to approve a request that uses an account key.
+///
+/// Returns the passphrase used to decrypt the account keystore.
+pub async fn approve(
+    ctx: &Context,
+    approval: Approval,
+) -> ProviderResult<String> {
+    if approvals::is_enabled() {
+        let answer =
+            approvals::request(ctx, ApprovalKind::Sign, &approval, vec![])
+                .await?;
+        return answer.passphrase.ok_or_else(ProviderError::user_rejected);
+    }
+
+    let message = approval.message(ctx);
+    let icon = approval.icon();
+    let passphrase = blocking(move || {
+        let answer = message_box_yes_no(TITLE, &message, icon, YesNo::No);
+        if answer == YesNo::Yes {
+            password_box(TITLE, "Enter your account passphrase:")
+        } else {
+            None
+        }
+    })
+    .await?;
+    passphrase.ok_or_else(ProviderError::user_rejected)
+}
+
+/// Ask the owner to confirm a request that does not use an
+/// account key.
+pub async fn confirm(ctx: &Context, approval: Approval) -> ProviderResult<()> {
+    if approvals::is_enabled() {
+        approvals::request(ctx, ApprovalKind::Confirm, &approval, vec![])
+            .await?;
+        return Ok(());
+    }
+
+    let message = approval.message(ctx);
+    let icon = approval.icon();
+    let answer =
+        blocking(move || message_box_yes_no(TITLE, &message, icon, YesNo::No))
+            .await?;
+    if answer == YesNo::Yes {
+        Ok(())
+    } else {
+        Err(ProviderError::user_rejected())
+    }
+}
+
+/// Show information about a request to the owner.
+///
+/// The information is shown in the background so the request
+/// does not wait for the owner to dismiss it.
+pub fn inform(ctx: &Context, approval: Approval) {
+    if approvals::is_enabled() {
+        let ctx = ctx.clone();
+        tokio::spawn(async move {
+            // Dismissing the information does not fail the request
+            let _ = approvals::request(
+                &ctx,
+                ApprovalKind::Inform,
+                &approval,
+                vec![],
+            )
+            .await;
+        });
+        return;
+    }
+
+    let message = approval.message(ctx);
+    let icon = if approval.warnings.is_empty() {
+        MessageBoxIcon::Info
+    } else {
+        MessageBoxIcon::Warning
+    };
+    tokio::task::spawn_blocking(move || message_box_ok(TITLE, &message, icon));
+}
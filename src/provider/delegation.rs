@@ -0,0 +1,491 @@
+//! Delegate account code to a contract (EIP-7702).
+//!
+//! Accounts sign authorizations using `wallet_signAuthorization`
+//! and clients include them in set code transactions (type `0x04`)
+//! sent with `eth_signTransaction` or `eth_sendTransaction`. A
+//! delegated account is controlled by the contract so accounts may
+//! only delegate to known implementations or contracts the owner
+//! has added to the settings.
+use anyhow::{anyhow, bail, Result};
+use ethers_core::{
+    types::{
+        transaction::eip2930::AccessList, Address, Bytes, Signature, H256,
+        U256, U64,
+    },
+    utils::{keccak256, rlp::RlpStream},
+};
+use ethers_signers::LocalWallet;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::helpers::format_address;
+use crate::upstream::Upstream;
+use crate::user::USER_DATA;
+
+use super::{
+    chain_id,
+    consent::{self, Approval},
+    transaction::{
+        SignedTransaction, TransactionParams, MAX_GAS_LIMIT, MIN_GAS_LIMIT,
+    },
+    wallet::{ensure_connected, parse_address, unlock},
+    Context, ProviderError, ProviderResult, Request,
+};
+
+/// Prefix for the authorization signing hash.
+const MAGIC: u8 = 0x05;
+
+/// Envelope type of a set code transaction.
+pub const SET_CODE_TX_TYPE: u8 = 0x04;
+
+/// Delegate implementations accounts may use.
+const KNOWN_DELEGATES: &[(&str, &str)] = &[
+    (
+        "0x63c0c19a282a1B52b07dD5a65b58948A07DAE32B",
+        "MetaMask EIP-7702 Stateless DeleGator",
+    ),
+    (
+        "0x4Cd241E8d1510e30b2076397afc7508Ae59C66c9",
+        "ERC-4337 Simple7702Account v0.8",
+    ),
+    (
+        "0x000000009B1D0aF20D8C6d0A44e162d11F9b8f00",
+        "Uniswap Calibur",
+    ),
+];
+
+/// Authorization to delegate the code of an account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Authorization {
+    /// Chain the authorization is valid on; zero for every chain.
+    pub chain_id: U256,
+    /// Contract the account delegates to.
+    pub address: Address,
+    pub nonce: U64,
+}
+
+/// Authorization signed by an account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedAuthorization {
+    pub chain_id: U256,
+    pub address: Address,
+    pub nonce: U64,
+    pub y_parity: U64,
+    pub r: U256,
+    pub s: U256,
+}
+
+impl Authorization {
+    /// Hash signed by the account.
+    pub fn signing_hash(&self) -> H256 {
+        let mut rlp = RlpStream::new_list(3);
+        rlp.append(&self.chain_id);
+        rlp.append(&self.address);
+        rlp.append(&self.nonce);
+        let mut message = vec![MAGIC];
+        message.extend_from_slice(rlp.as_raw());
+        H256::from(keccak256(message))
+    }
+
+    /// Sign the authorization with a wallet.
+    pub fn sign(&self, wallet: &LocalWallet) -> SignedAuthorization {
+        let signature = wallet.sign_hash(self.signing_hash(), false);
+        SignedAuthorization {
+            chain_id: self.chain_id,
+            address: self.address,
+            nonce: self.nonce,
+            y_parity: U64::from(signature.v - 27),
+            r: signature.r,
+            s: signature.s,
+        }
+    }
+}
+
+impl SignedAuthorization {
+    /// Account that signed the authorization.
+    pub fn authority(&self) -> Result<Address> {
+        let authorization = Authorization {
+            chain_id: self.chain_id,
+            address: self.address,
+            nonce: self.nonce,
+        };
+        let signature = Signature {
+            r: self.r,
+            s: self.s,
+            v: self.y_parity.as_u64() + 27,
+        };
+        Ok(signature.recover(authorization.signing_hash())?)
+    }
+
+    fn rlp_append(&self, rlp: &mut RlpStream) {
+        rlp.begin_list(6);
+        rlp.append(&self.chain_id);
+        rlp.append(&self.address);
+        rlp.append(&self.nonce);
+        rlp.append(&self.y_parity);
+        rlp.append(&self.r);
+        rlp.append(&self.s);
+    }
+}
+
+/// Name of a delegate the owner allows accounts to use.
+fn delegate_name(address: Address) -> Option<String> {
+    let known = KNOWN_DELEGATES.iter().find(|(delegate, _)| {
+        delegate.parse::<Address>().ok() == Some(address)
+    });
+    if let Some((_, name)) = known {
+        return Some(name.to_string());
+    }
+    let user = USER_DATA.read().unwrap();
+    let settings = user.settings().ok()?;
+    settings
+        .delegates
+        .iter()
+        .any(|delegate| delegate.parse::<Address>().ok() == Some(address))
+        .then(|| "added in your settings".to_string())
+}
+
+/// Warning shown for the delegate of an authorization.
+fn delegate_warning(authority: &str, authorization: &Authorization) -> String {
+    let chains = if authorization.chain_id.is_zero() {
+        "on EVERY chain".to_string()
+    } else {
+        format!("on chain {}", authorization.chain_id)
+    };
+    if authorization.address.is_zero() {
+        return format!(
+            "This removes the delegation of {} {}; the account will \
+             behave as a plain account again.",
+            authority, chains
+        );
+    }
+    let name = delegate_name(authorization.address)
+        .unwrap_or_else(|| "UNKNOWN CONTRACT".to_string());
+    format!(
+        "{} WILL BE DELEGATED TO THE CONTRACT {} ({}) {}. The contract \
+         can move every asset held by the account. Only approve if you \
+         requested this delegation.",
+        authority,
+        format_address(authorization.address),
+        name,
+        chains
+    )
+}
+
+/// Parameter for `wallet_signAuthorization`.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AuthorizationParameter {
+    #[serde(default)]
+    chain_id: Option<U256>,
+    address: Address,
+    #[serde(default)]
+    nonce: Option<U64>,
+    /// Account that sends the set code transaction; `"self"` when
+    /// the account sends it.
+    #[serde(default)]
+    executor: Option<String>,
+}
+
+/// Ask the owner to sign an authorization using
+/// `wallet_signAuthorization`.
+///
+/// Parameters are `[address, authorization]`; the chain defaults
+/// to the chain of the client and the nonce to the next nonce of
+/// the account. An account that sends the set code transaction
+/// itself uses its nonce for the transaction first, so the nonce
+/// after it is used when the executor is the account.
+pub async fn sign_authorization(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (address, param): (String, AuthorizationParameter) =
+        request.params()?;
+    let account = parse_address(&address)?;
+    ensure_connected(ctx, &address)?;
+
+    let is_allowed =
+        param.address.is_zero() || delegate_name(param.address).is_some();
+    if !is_allowed {
+        return Err(ProviderError::invalid_params(format!(
+            "{} is not a known delegate; add it to the settings to use it",
+            format_address(param.address)
+        )));
+    }
+
+    let chain_id = chain_id(ctx);
+    if let Some(id) = param.chain_id {
+        if !id.is_zero() && id != U256::from(chain_id) {
+            return Err(ProviderError::invalid_params(format!(
+                "chain id {} does not match the active chain id {}",
+                id, chain_id
+            )));
+        }
+    }
+    let is_executor = param.executor.as_deref().map_or(false, |executor| {
+        executor == "self" || parse_address(executor).ok() == Some(account)
+    });
+    let nonce = match param.nonce {
+        Some(nonce) => nonce,
+        None => {
+            let upstream = Upstream::for_chain(chain_id)?;
+            let pending: U64 = upstream
+                .call("eth_getTransactionCount", json!([account, "pending"]))
+                .await?;
+            if is_executor {
+                pending + U64::one()
+            } else {
+                pending
+            }
+        }
+    };
+    let authorization = Authorization {
+        chain_id: param.chain_id.unwrap_or_else(|| U256::from(chain_id)),
+        address: param.address,
+        nonce,
+    };
+
+    let summary = format!(
+        "Sign an EIP-7702 authorization delegating the account to {} \
+         with nonce {}?",
+        format_address(authorization.address),
+        authorization.nonce
+    );
+    let approval = Approval::new(request.method(), summary)
+        .account(&address)
+        .payload(json!(authorization))
+        .warning(delegate_warning(&address, &authorization));
+    let passphrase = consent::approve(ctx, approval).await?;
+    let wallet = unlock(&address, passphrase).await?;
+    Ok(json!(authorization.sign(&wallet)))
+}
+
+/// Transaction that sets the code of accounts (type `0x04`).
+#[derive(Debug, Clone)]
+pub struct SetCodeTransaction {
+    pub chain_id: u64,
+    pub nonce: U256,
+    pub max_priority_fee_per_gas: U256,
+    pub max_fee_per_gas: U256,
+    pub gas: U256,
+    pub to: Address,
+    pub value: U256,
+    pub data: Bytes,
+    pub access_list: AccessList,
+    pub authorization_list: Vec<SignedAuthorization>,
+}
+
+impl SetCodeTransaction {
+    /// Validate the parameters and build a transaction for a chain.
+    pub fn build(params: &TransactionParams, chain_id: u64) -> Result<Self> {
+        if let Some(tx_chain_id) = params.chain_id {
+            if tx_chain_id.as_u64() != chain_id {
+                bail!(
+                    "chain id {} does not match the active chain id {}",
+                    tx_chain_id,
+                    chain_id
+                );
+            }
+        }
+        if params.gas_price.is_some() {
+            bail!("gasPrice is not allowed for set code transactions");
+        }
+        let to = params
+            .to
+            .ok_or_else(|| anyhow!("set code transactions require to"))?;
+        let authorization_list =
+            params.authorization_list.clone().unwrap_or_default();
+        if authorization_list.is_empty() {
+            bail!("authorizationList must not be empty");
+        }
+        let nonce = params.nonce.ok_or_else(|| anyhow!("nonce is required"))?;
+        // Nodes skip authorizations for other chains
+        for authorization in &authorization_list {
+            let id = authorization.chain_id;
+            if !id.is_zero() && id != U256::from(chain_id) {
+                bail!(
+                    "authorization for chain {} is not valid on chain {}",
+                    id,
+                    chain_id
+                );
+            }
+        }
+        // The sender nonce is incremented before the authorizations
+        // are processed so the sender must authorize the next nonce
+        if let Some(from) = params.from {
+            let expected = nonce + U256::one();
+            for authorization in &authorization_list {
+                let is_sender = authorization.authority().ok() == Some(from);
+                if is_sender
+                    && U256::from(authorization.nonce.as_u64()) != expected
+                {
+                    bail!(
+                        "authorization by the sender must use nonce {}",
+                        expected
+                    );
+                }
+            }
+        }
+        let gas = params.gas.ok_or_else(|| anyhow!("gas is required"))?;
+        if gas < U256::from(MIN_GAS_LIMIT) || gas > U256::from(MAX_GAS_LIMIT) {
+            bail!("gas limit {} is out of range", gas);
+        }
+        let max_fee_per_gas = params
+            .max_fee_per_gas
+            .ok_or_else(|| anyhow!("maxFeePerGas is required"))?;
+        let max_priority_fee_per_gas = params
+            .max_priority_fee_per_gas
+            .ok_or_else(|| anyhow!("maxPriorityFeePerGas is required"))?;
+        if max_priority_fee_per_gas > max_fee_per_gas {
+            bail!("maxPriorityFeePerGas exceeds maxFeePerGas");
+        }
+
+        Ok(Self {
+            chain_id,
+            nonce,
+            max_priority_fee_per_gas,
+            max_fee_per_gas,
+            gas,
+            to,
+            value: params.value.unwrap_or_default(),
+            data: params.call_data()?.unwrap_or_default(),
+            access_list: params.access_list.clone().unwrap_or_default(),
+            authorization_list,
+        })
+    }
+
+    fn rlp_fields(&self, rlp: &mut RlpStream) {
+        rlp.append(&self.chain_id);
+        rlp.append(&self.nonce);
+        rlp.append(&self.max_priority_fee_per_gas);
+        rlp.append(&self.max_fee_per_gas);
+        rlp.append(&self.gas);
+        rlp.append(&self.to);
+        rlp.append(&self.value);
+        rlp.append(&self.data.as_ref().to_vec());
+        rlp.append(&self.access_list);
+        rlp.begin_list(self.authorization_list.len());
+        for authorization in &self.authorization_list {
+            authorization.rlp_append(rlp);
+        }
+    }
+
+    /// Hash signed by the sender.
+    pub fn signing_hash(&self) -> H256 {
+        let mut rlp = RlpStream::new_list(10);
+        self.rlp_fields(&mut rlp);
+        let mut message = vec![SET_CODE_TX_TYPE];
+        message.extend_from_slice(rlp.as_raw());
+        H256::from(keccak256(message))
+    }
+
+    /// Sign the transaction with a wallet.
+    pub fn sign(&self, wallet: &LocalWallet) -> SignedTransaction {
+        let signature = wallet.sign_hash(self.signing_hash(), false);
+        let mut rlp = RlpStream::new_list(13);
+        self.rlp_fields(&mut rlp);
+        rlp.append(&(signature.v - 27));
+        rlp.append(&signature.r);
+        rlp.append(&signature.s);
+        let mut raw = vec![SET_CODE_TX_TYPE];
+        raw.extend_from_slice(rlp.as_raw());
+        let hash = format!("0x{}", hex::encode(keccak256(&raw)));
+        SignedTransaction {
+            raw: Bytes::from(raw),
+            hash,
+        }
+    }
+
+    /// Warnings for the authorizations in the transaction.
+    pub fn warnings(&self) -> Vec<String> {
+        self.authorization_list
+            .iter()
+            .map(|signed| {
+                let authority = match signed.authority() {
+                    Ok(authority) => format_address(authority),
+                    Err(_) => "AN INVALID SIGNATURE".to_string(),
+                };
+                let authorization = Authorization {
+                    chain_id: signed.chain_id,
+                    address: signed.address,
+                    nonce: signed.nonce,
+                };
+                delegate_warning(&authority, &authorization)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Authorization signing hash for chain 1, the first known
+    /// delegate and nonce 7, computed independently with alloy.
+    const AUTHORIZATION_HASH: &str =
+        "0xb5fd1cbf1c9031f8cd523819fd9fa7b455ed84b01c76e0ef022126f46707e6c5";
+
+    /// Set code transaction with the authorization above signed by
+    /// the same account, also computed with alloy.
+    const SET_CODE_TX: &str = concat!(
+        "0x04f8c90106843b9aca008477359400830186a0942c7536e3605d9c16a7a3d7",
+        "b1898e529396a65c238080c0f85cf85a019463c0c19a282a1b52b07dd5a65b58",
+        "948a07dae32b0701a0080af9d3eb54902575ca9583643b4620e63fe5a5f708ba",
+        "a7d721d5e08eb33a28a00e3f0397993db20d6254a0d3aa069c6a7291f885dc00",
+        "fb675097b6a39903851380a0c47f49ffd64506768af894b6ab38c939aeb51783",
+        "eb1c66c26ace299a686acadda02a844a4c56d1c8171af8d638ccdd992e90ef63",
+        "019345e5961f0d88a13aa81bc6",
+    );
+
+    const SET_CODE_TX_HASH: &str =
+        "0x6ff9ddb686f6a504d27e5e665dc83e88d2839f4acd6deedfbf1c7f9defa20913";
+
+    #[test]
+    fn sign_authorization_and_transaction() -> Result<()> {
+        let wallet: LocalWallet =
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+                .parse()?;
+        let authorization = Authorization {
+            chain_id: U256::from(1),
+            address: KNOWN_DELEGATES[0].0.parse()?,
+            nonce: U64::from(7),
+        };
+        assert_eq!(
+            AUTHORIZATION_HASH.parse::<H256>()?,
+            authorization.signing_hash()
+        );
+        let signed = authorization.sign(&wallet);
+        assert_eq!(
+            ethers_signers::Signer::address(&wallet),
+            signed.authority()?
+        );
+
+        let params = TransactionParams {
+            from: Some(ethers_signers::Signer::address(&wallet)),
+            to: Some(ethers_signers::Signer::address(&wallet)),
+            gas: Some(U256::from(100_000)),
+            max_fee_per_gas: Some(U256::from(2_000_000_000u64)),
+            max_priority_fee_per_gas: Some(U256::from(1_000_000_000u64)),
+            nonce: Some(U256::from(6)),
+            authorization_list: Some(vec![signed]),
+            ..Default::default()
+        };
+        let tx = SetCodeTransaction::build(&params, 1)?;
+        let signed = tx.sign(&wallet);
+        assert_eq!(SET_CODE_TX, format!("0x{}", hex::encode(&signed.raw)));
+        assert_eq!(SET_CODE_TX_HASH, signed.hash);
+        assert!(tx.warnings()[0].contains("MetaMask"));
+
+        assert!(SetCodeTransaction::build(&params, 5).is_err());
+
+        // The sender must authorize the nonce after its transaction
+        let mut params = params;
+        params.nonce = Some(U256::from(7));
+        assert!(SetCodeTransaction::build(&params, 1).is_err());
+        params.from = Some(Address::zero());
+        assert!(SetCodeTransaction::build(&params, 1).is_ok());
+        Ok(())
+    }
+}
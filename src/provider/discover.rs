@@ -0,0 +1,79 @@
+//! Service discovery using OpenRPC (`rpc.discover`).
+//!
+//! The document is bundled with the agent; the scope required
+//! by each method is added from the permissions so that the two
+//! cannot disagree.
+use once_cell::sync::Lazy;
+use serde_json::Value;
+
+use super::permissions::required_scope;
+
+/// OpenRPC document for the provider methods.
+const OPENRPC: &str = include_str!("openrpc.json");
+
+static DOCUMENT: Lazy<Value> = Lazy::new(|| {
+    let mut document: Value =
+        serde_json::from_str(OPENRPC).expect("bundled openrpc.json is invalid");
+    document["info"]["version"] = Value::from(env!("CARGO_PKG_VERSION"));
+    if let Some(methods) = document["methods"].as_array_mut() {
+        for method in methods {
+            let name = method["name"].as_str().unwrap_or_default();
+            method["x-scope"] = Value::from(required_scope(name));
+        }
+    }
+    document
+});
+
+/// OpenRPC document describing the provider methods.
+pub fn document() -> &'static Value {
+    &DOCUMENT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::passthrough::ALLOWED_METHODS;
+    use std::collections::HashSet;
+
+    /// Collect the schema references in a value.
+    fn refs<'a>(value: &'a Value, out: &mut Vec<&'a str>) {
+        match value {
+            Value::Object(map) => {
+                if let Some(Value::String(target)) = map.get("$ref") {
+                    out.push(target);
+                }
+                map.values().for_each(|v| refs(v, out));
+            }
+            Value::Array(values) => values.iter().for_each(|v| refs(v, out)),
+            _ => {}
+        }
+    }
+
+    #[test]
+    fn describe_methods() {
+        let document = document();
+        let methods = document["methods"].as_array().unwrap();
+        let names: HashSet<&str> = methods
+            .iter()
+            .map(|method| method["name"].as_str().unwrap())
+            .collect();
+        assert_eq!(methods.len(), names.len());
+        for method in ALLOWED_METHODS {
+            assert!(names.contains(method), "{} is not described", method);
+        }
+        for method in methods {
+            assert!(method["x-scope"].is_string());
+        }
+
+        let mut targets = Vec::new();
+        refs(document, &mut targets);
+        for target in targets {
+            let name = target.trim_start_matches("#/components/schemas/");
+            assert!(
+                document["components"]["schemas"].get(name).is_some(),
+                "{} is not defined",
+                target
+            );
+        }
+    }
+}
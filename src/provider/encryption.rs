@@ -0,0 +1,168 @@
+//! Encrypted messages using `eth_getEncryptionPublicKey` and
+//! `eth_decrypt`.
+//!
+//! Implements the `x25519-xsalsa20-poly1305` scheme used by
+//! `eth-sig-util`; the secret key of an account is used as an
+//! X25519 secret key to open a NaCl box.
+use crypto_box::{
+    aead::{generic_array::GenericArray, Aead},
+    PublicKey, SalsaBox, SecretKey,
+};
+use serde::Deserialize;
+use serde_json::Value;
+
+use super::{
+    consent::{self, Approval},
+    wallet::{ensure_connected, parse_address, unlock_key},
+    Context, ProviderError, ProviderResult, Request,
+};
+
+/// Version of the only supported encryption scheme.
+const VERSION: &str = "x25519-xsalsa20-poly1305";
+
+/// Message encrypted for an account.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedData {
+    pub version: String,
+    /// Base64 encoded 24 byte nonce.
+    pub nonce: String,
+    /// Base64 encoded public key of the sender.
+    pub ephem_public_key: String,
+    /// Base64 encoded ciphertext.
+    pub ciphertext: String,
+}
+
+/// Decode a base64 value of a fixed length.
+fn decode_fixed<const N: usize>(
+    field: &str,
+    value: &str,
+) -> ProviderResult<[u8; N]> {
+    let bytes = base64::decode(value).map_err(|e| {
+        ProviderError::invalid_params(format!("{} is invalid: {}", field, e))
+    })?;
+    bytes.try_into().map_err(|_| {
+        ProviderError::invalid_params(format!("{} must be {} bytes", field, N))
+    })
+}
+
+/// Base64 encoded encryption public key for a secret key.
+pub fn encryption_public_key(secret: &[u8; 32]) -> String {
+    let secret = SecretKey::from(*secret);
+    base64::encode(secret.public_key().as_bytes())
+}
+
+/// Decrypt a message with a secret key.
+pub fn decrypt(
+    secret: &[u8; 32],
+    data: &EncryptedData,
+) -> ProviderResult<String> {
+    if data.version != VERSION {
+        return Err(ProviderError::invalid_params(format!(
+            "unsupported encryption version {}",
+            data.version
+        )));
+    }
+    let nonce: [u8; 24] = decode_fixed("nonce", &data.nonce)?;
+    let sender: [u8; 32] =
+        decode_fixed("ephemPublicKey", &data.ephem_public_key)?;
+    let ciphertext = base64::decode(&data.ciphertext).map_err(|e| {
+        ProviderError::invalid_params(format!("ciphertext is invalid: {}", e))
+    })?;
+
+    let salsa_box =
+        SalsaBox::new(&PublicKey::from(sender), &SecretKey::from(*secret));
+    let message = salsa_box
+        .decrypt(GenericArray::from_slice(&nonce), ciphertext.as_slice())
+        .map_err(|_| ProviderError::invalid_params("decryption failed"))?;
+    String::from_utf8(message).map_err(ProviderError::invalid_params)
+}
+
+/// Ask the owner to share the encryption public key of an
+/// account using `eth_getEncryptionPublicKey`.
+pub async fn get_encryption_public_key(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (address,): (String,) = request.params()?;
+    parse_address(&address)?;
+    ensure_connected(ctx, &address)?;
+
+    let summary = format!(
+        "Share the encryption public key of {} with {}?",
+        address,
+        ctx.origin()
+    );
+    let approval = Approval::new(request.method(), summary).account(&address);
+    let passphrase = consent::approve(ctx, approval).await?;
+    let secret = unlock_key(&address, passphrase).await?;
+    Ok(Value::String(encryption_public_key(&secret)))
+}
+
+/// Ask the owner to decrypt a message using `eth_decrypt`.
+///
+/// Parameters are `[data, address]` where data is the hex
+/// encoded JSON of the encrypted message.
+pub async fn decrypt_message(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (data, address): (String, String) = request.params()?;
+    parse_address(&address)?;
+    ensure_connected(ctx, &address)?;
+
+    let json = data
+        .strip_prefix("0x")
+        .ok_or_else(|| ProviderError::invalid_params("data must be hex"))
+        .and_then(|hex_data| {
+            hex::decode(hex_data).map_err(ProviderError::invalid_params)
+        })?;
+    let encrypted: EncryptedData =
+        serde_json::from_slice(&json).map_err(ProviderError::invalid_params)?;
+
+    let summary =
+        format!("Decrypt a message for {} using {}?", ctx.origin(), address);
+    let approval = Approval::new(request.method(), summary).account(&address);
+    let passphrase = consent::approve(ctx, approval).await?;
+    let secret = unlock_key(&address, passphrase).await?;
+    Ok(Value::String(decrypt(&secret, &encrypted)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vector from eth-sig-util
+    const SECRET: &str =
+        "7e5374ec2ef0d91761a6e72fdf8f6ac665519bfdf6da0a2329cf0d804514b816";
+
+    fn secret() -> [u8; 32] {
+        hex::decode(SECRET).unwrap().try_into().unwrap()
+    }
+
+    #[test]
+    fn decrypt_eth_sig_util_message() {
+        let secret = secret();
+        assert_eq!(
+            "C5YMNdqE4kLgxQhJO1MfuQcHP5hjVSXzamzd/TxlR0U=",
+            encryption_public_key(&secret)
+        );
+
+        let mut data = EncryptedData {
+            version: VERSION.to_string(),
+            nonce: "1dvWO7uOnBnO7iNDJ9kO9pTasLuKNlej".to_string(),
+            ephem_public_key: "FBH1/pAEHOOW14Lu3FWkgV3qOEcuL78Zy+qW1RwzMXQ="
+                .to_string(),
+            ciphertext:
+                "f8kBcl/NCyf3sybfbwAKk/np2Bzt9lRVkZejr6uh5FgnNlH/ic62DZzy"
+                    .to_string(),
+        };
+        assert_eq!(
+            "My name is Satoshi Buterin",
+            decrypt(&secret, &data).unwrap()
+        );
+
+        data.ciphertext = data.ciphertext.replace('f', "g");
+        assert!(decrypt(&secret, &data).is_err());
+    }
+}
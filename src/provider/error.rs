@@ -0,0 +1,130 @@
+//! Errors returned to provider clients.
+//!
+//! Provider specific codes are defined by EIP-1193, protocol
+//! codes by the JSON-RPC 2.0 specification.
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fmt;
+
+/// The user rejected the request.
+pub const USER_REJECTED: i64 = 4001;
+/// The requested method and/or account has not been authorized.
+pub const UNAUTHORIZED: i64 = 4100;
+/// The provider does not support the requested method.
+pub const UNSUPPORTED_METHOD: i64 = 4200;
+/// The provider is disconnected from all chains.
+pub const DISCONNECTED: i64 = 4900;
+/// The provider is not connected to the requested chain.
+pub const CHAIN_DISCONNECTED: i64 = 4901;
+/// The requested chain has not been added (EIP-3326).
+pub const UNRECOGNIZED_CHAIN: i64 = 4902;
+
+/// A required capability is not supported (EIP-5792).
+pub const UNSUPPORTED_CAPABILITY: i64 = 5700;
+/// Calls cannot be sent on the requested chain (EIP-5792).
+pub const UNSUPPORTED_CHAIN: i64 = 5710;
+/// A batch with the same identifier already exists (EIP-5792).
+pub const DUPLICATE_ID: i64 = 5720;
+/// The batch identifier is not known (EIP-5792).
+pub const UNKNOWN_BUNDLE: i64 = 5730;
+/// The batch has too many calls (EIP-5792).
+pub const BATCH_TOO_LARGE: i64 = 5740;
+/// The wallet cannot execute the batch atomically (EIP-5792).
+pub const ATOMICITY_NOT_SUPPORTED: i64 = 5760;
+
+/// Invalid JSON was received.
+pub const PARSE_ERROR: i64 = -32700;
+/// The JSON sent is not a valid request object.
+pub const INVALID_REQUEST: i64 = -32600;
+/// The method does not exist or is not available.
+pub const METHOD_NOT_FOUND: i64 = -32601;
+/// Invalid method parameters.
+pub const INVALID_PARAMS: i64 = -32602;
+/// Internal JSON-RPC error.
+pub const INTERNAL_ERROR: i64 = -32603;
+/// Request exceeds a defined limit (EIP-1474).
+pub const LIMIT_EXCEEDED: i64 = -32005;
+
+/// Result type for provider methods.
+pub type ProviderResult<T> = std::result::Result<T, ProviderError>;
+
+/// Error object sent in a JSON-RPC response.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProviderError {
+    pub code: i64,
+    pub message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+}
+
+impl ProviderError {
+    /// Create a new error.
+    pub fn new<S: Into<String>>(code: i64, message: S) -> Self {
+        Self {
+            code,
+            message: message.into(),
+            data: None,
+        }
+    }
+
+    /// Attach additional data to this error.
+    pub fn with_data(mut self, data: Value) -> Self {
+        self.data = Some(data);
+        self
+    }
+
+    pub fn user_rejected() -> Self {
+        Self::new(USER_REJECTED, "User rejected the request.")
+    }
+
+    pub fn unauthorized() -> Self {
+        Self::new(
+            UNAUTHORIZED,
+            "The requested method and/or account has not been authorized.",
+        )
+    }
+
+    pub fn unsupported_method(method: &str) -> Self {
+        Self::new(
+            UNSUPPORTED_METHOD,
+            format!("The provider does not support the method {}.", method),
+        )
+    }
+
+    pub fn parse<E: fmt::Display>(e: E) -> Self {
+        Self::new(PARSE_ERROR, format!("Parse error: {}", e))
+    }
+
+    pub fn invalid_request<E: fmt::Display>(e: E) -> Self {
+        Self::new(INVALID_REQUEST, format!("Invalid request: {}", e))
+    }
+
+    pub fn invalid_params<E: fmt::Display>(e: E) -> Self {
+        Self::new(INVALID_PARAMS, format!("Invalid params: {}", e))
+    }
+
+    pub fn limit_exceeded<E: fmt::Display>(e: E) -> Self {
+        Self::new(LIMIT_EXCEEDED, format!("Limit exceeded: {}", e))
+    }
+
+    pub fn internal<E: fmt::Display>(e: E) -> Self {
+        Self::new(INTERNAL_ERROR, e.to_string())
+    }
+}
+
+impl fmt::Display for ProviderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (code: {})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for ProviderError {}
+
+impl From<anyhow::Error> for ProviderError {
+    fn from(e: anyhow::Error) -> Self {
+        match e.downcast::<ProviderError>() {
+            Ok(e) => e,
+            Err(e) => Self::internal(e),
+        }
+    }
+}
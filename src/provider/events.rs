@@ -0,0 +1,306 @@
+//! EIP-1193 events for a client.
+//!
+//! A listener keeps the state last reported to a client and
+//! emits `accountsChanged`, `chainChanged`, `connect` and
+//! `disconnect` when a wallet event changes that state.
+use serde_json::{json, Value};
+
+use crate::events::WalletEvent;
+use crate::upstream::health;
+
+use super::{
+    accounts::accounts, chain_id, Context, ProviderError, DISCONNECTED,
+};
+
+/// Event sent to a provider client.
+#[derive(Debug, Clone)]
+pub struct ProviderEvent {
+    /// Name of the event.
+    pub name: &'static str,
+    /// Event payload.
+    pub data: Value,
+}
+
+impl ProviderEvent {
+    fn new(name: &'static str, data: Value) -> Self {
+        Self { name, data }
+    }
+}
+
+/// State of the wallet as seen by a client.
+#[derive(Debug, Clone, PartialEq)]
+struct State {
+    accounts: Vec<String>,
+    chain_id: u64,
+    connected: bool,
+}
+
+impl State {
+    /// Read the current state for a client.
+    fn current(ctx: &Context) -> Self {
+        let chain_id = chain_id(ctx);
+        Self {
+            accounts: accounts(ctx),
+            chain_id,
+            connected: health::is_chain_connected(chain_id),
+        }
+    }
+}
+
+/// State reported to a client.
+pub struct Listener {
+    ctx: Context,
+    state: State,
+    /// Source of the current state.
+    read: fn(&Context) -> State,
+}
+
+impl Listener {
+    /// Create a listener and the events that describe the
+    /// initial state to the client.
+    pub fn new(ctx: Context) -> (Self, Vec<ProviderEvent>) {
+        Self::with_source(ctx, State::current)
+    }
+
+    fn with_source(
+        ctx: Context,
+        read: fn(&Context) -> State,
+    ) -> (Self, Vec<ProviderEvent>) {
+        let listener = Self {
+            state: read(&ctx),
+            ctx,
+            read,
+        };
+        let mut events = vec![listener.connection_event()];
+        events.push(ProviderEvent::new(
+            "accountsChanged",
+            json!(listener.state.accounts),
+        ));
+        (listener, events)
+    }
+
+    /// Events for a change to the wallet.
+    pub fn update(&mut self, event: &WalletEvent) -> Vec<ProviderEvent> {
+        if event.affects(&self.ctx.client_id) {
+            self.refresh()
+        } else {
+            vec![]
+        }
+    }
+
+    /// Compare the current state with the state last reported
+    /// and create events for the differences.
+    ///
+    /// Used directly when wallet events may have been missed.
+    pub fn refresh(&mut self) -> Vec<ProviderEvent> {
+        let mut events = Vec::new();
+        let current = (self.read)(&self.ctx);
+
+        if current.connected != self.state.connected {
+            self.state.connected = current.connected;
+            self.state.chain_id = current.chain_id;
+            events.push(self.connection_event());
+        } else if current.chain_id != self.state.chain_id {
+            self.state.chain_id = current.chain_id;
+            events.push(ProviderEvent::new(
+                "chainChanged",
+                json!(format!("{:#x}", current.chain_id)),
+            ));
+        }
+
+        if current.accounts != self.state.accounts {
+            self.state.accounts = current.accounts;
+            events.push(ProviderEvent::new(
+                "accountsChanged",
+                json!(self.state.accounts),
+            ));
+        }
+
+        events
+    }
+
+    /// Event describing whether the client chain is reachable.
+    fn connection_event(&self) -> ProviderEvent {
+        let chain_id = self.state.chain_id;
+        if self.state.connected {
+            ProviderEvent::new(
+                "connect",
+                json!({ "chainId": format!("{:#x}", chain_id) }),
+            )
+        } else {
+            let error = ProviderError::new(
+                DISCONNECTED,
+                format!(
+                    "The provider is disconnected from chain {}.",
+                    chain_id
+                ),
+            );
+            ProviderEvent::new("disconnect", json!(error))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use tokio::sync::broadcast::{self, error::TryRecvError};
+
+    const CLIENT: &str = "test-client";
+    const ACCOUNT: &str = "0x1111111111111111111111111111111111111111";
+
+    thread_local! {
+        static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+    }
+
+    fn read(_: &Context) -> State {
+        STATE.with(|state| state.borrow().clone().unwrap())
+    }
+
+    fn set<F: FnOnce(&mut State)>(f: F) {
+        STATE.with(|state| f(state.borrow_mut().as_mut().unwrap()))
+    }
+
+    /// Listen as a client with one account.
+    fn start(chain_id: u64) -> (Listener, Vec<ProviderEvent>) {
+        let state = State {
+            accounts: vec![ACCOUNT.to_string()],
+            chain_id,
+            connected: true,
+        };
+        STATE.with(|current| *current.borrow_mut() = Some(state));
+        let ctx = Context {
+            client_id: CLIENT.to_string(),
+            owner_id: "owner".to_string(),
+            scope: "read accounts".to_string(),
+            redirect_uri: "https://example.com/callback".parse().unwrap(),
+        };
+        Listener::with_source(ctx, read)
+    }
+
+    fn events(events: Vec<ProviderEvent>) -> Vec<(&'static str, Value)> {
+        events.into_iter().map(|e| (e.name, e.data)).collect()
+    }
+
+    #[test]
+    fn connect_and_disconnect() {
+        let (mut listener, initial) = start(1);
+        assert_eq!(
+            vec![
+                ("connect", json!({ "chainId": "0x1" })),
+                ("accountsChanged", json!([ACCOUNT])),
+            ],
+            events(initial)
+        );
+
+        set(|state| state.connected = false);
+        let disconnected = listener.update(&WalletEvent::ChainConnection {
+            chain_id: 1,
+            connected: false,
+        });
+        assert_eq!(1, disconnected.len());
+        assert_eq!("disconnect", disconnected[0].name);
+        assert_eq!(json!(DISCONNECTED), disconnected[0].data["code"]);
+
+        set(|state| state.connected = true);
+        let connected = listener.update(&WalletEvent::ChainConnection {
+            chain_id: 1,
+            connected: true,
+        });
+        assert_eq!(
+            vec![("connect", json!({ "chainId": "0x1" }))],
+            events(connected)
+        );
+    }
+
+    #[test]
+    fn chain_changed() {
+        let (mut listener, _) = start(1);
+        set(|state| state.chain_id = 137);
+
+        // Events for other clients are ignored
+        let other = WalletEvent::ChainChanged {
+            client_id: "other-client".to_string(),
+            chain_id: 137,
+        };
+        assert!(listener.update(&other).is_empty());
+        assert!(listener.update(&WalletEvent::ApprovalsChanged).is_empty());
+
+        let changed = listener.update(&WalletEvent::ChainChanged {
+            client_id: CLIENT.to_string(),
+            chain_id: 137,
+        });
+        assert_eq!(vec![("chainChanged", json!("0x89"))], events(changed));
+
+        // Nothing is reported twice
+        assert!(listener.refresh().is_empty());
+    }
+
+    #[test]
+    fn accounts_changed_on_lock() {
+        let (mut listener, _) = start(1);
+        let lock = WalletEvent::AccountsChanged { client_id: None };
+
+        set(|state| state.accounts.clear());
+        assert_eq!(
+            vec![("accountsChanged", json!([]))],
+            events(listener.update(&lock))
+        );
+        set(|state| state.accounts = vec![ACCOUNT.to_string()]);
+        assert_eq!(
+            vec![("accountsChanged", json!([ACCOUNT]))],
+            events(listener.update(&lock))
+        );
+    }
+
+    #[test]
+    fn accounts_changed_on_revoke() {
+        // Revoking also resets the chain of the client
+        let (mut listener, _) = start(137);
+        set(|state| {
+            state.accounts.clear();
+            state.chain_id = 1;
+        });
+        let revoked = listener.update(&WalletEvent::Revoked {
+            client_id: CLIENT.to_string(),
+        });
+        assert_eq!(
+            vec![
+                ("chainChanged", json!("0x1")),
+                ("accountsChanged", json!([])),
+            ],
+            events(revoked)
+        );
+    }
+
+    #[test]
+    fn resync_after_lag() {
+        let (mut listener, _) = start(1);
+        let (sender, mut receiver) = broadcast::channel(1);
+
+        set(|state| state.chain_id = 10);
+        sender
+            .send(WalletEvent::ChainChanged {
+                client_id: CLIENT.to_string(),
+                chain_id: 10,
+            })
+            .unwrap();
+        set(|state| state.accounts.clear());
+        sender
+            .send(WalletEvent::AccountsChanged { client_id: None })
+            .unwrap();
+
+        // The chain change was dropped; a refresh reports both
+        assert!(matches!(receiver.try_recv(), Err(TryRecvError::Lagged(1))));
+        assert_eq!(
+            vec![
+                ("chainChanged", json!("0xa")),
+                ("accountsChanged", json!([])),
+            ],
+            events(listener.refresh())
+        );
+
+        let event = receiver.try_recv().unwrap();
+        assert!(listener.update(&event).is_empty());
+    }
+}
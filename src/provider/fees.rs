@@ -0,0 +1,338 @@
+//! Fee suggestions for transactions.
+//!
+//! Priority fees at the slow, normal and fast speeds are the
+//! median of the 10th, 50th and 90th reward percentiles over the
+//! recent blocks reported by `eth_feeHistory`; empty blocks are
+//! ignored. The maximum fee adds the priority fee to the next
+//! base fee scaled to allow for the base fee rising while the
+//! transaction waits. Chains without a base fee use the gas price
+//! from `eth_gasPrice` instead.
+use anyhow::Result;
+use ethers_core::types::U256;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::helpers::format_units;
+use crate::network::NETWORK_REGISTRY;
+use crate::upstream::Upstream;
+
+use super::{transaction::MIN_GAS_LIMIT, ProviderResult, METHOD_NOT_FOUND};
+
+/// Number of blocks sampled from the fee history.
+const FEE_HISTORY_BLOCKS: u64 = 10;
+
+/// Reward percentiles for the slow, normal and fast speeds.
+const REWARD_PERCENTILES: [f64; 3] = [10.0, 50.0, 90.0];
+
+/// Percentage of the next base fee for the slow, normal and fast
+/// speeds; enough for about two, six and eight full blocks.
+const BASE_FEE_MULTIPLIERS: [u64; 3] = [125, 200, 250];
+
+/// Percentage of the gas price for the slow, normal and fast
+/// speeds on legacy chains.
+const GAS_PRICE_MULTIPLIERS: [u64; 3] = [90, 100, 125];
+
+/// Priority fee used when recent blocks are empty (1.5 gwei).
+const FALLBACK_PRIORITY_FEE: u64 = 1_500_000_000;
+
+/// Response to `eth_feeHistory`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeHistory {
+    pub oldest_block: U256,
+    /// Base fee of each block including the next block.
+    pub base_fee_per_gas: Vec<U256>,
+    pub gas_used_ratio: Vec<f64>,
+    /// Rewards of each block at the requested percentiles.
+    #[serde(default)]
+    pub reward: Vec<Vec<U256>>,
+}
+
+/// Suggested fees for one speed.
+///
+/// On legacy chains both fields are the gas price.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeSuggestion {
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+}
+
+/// Suggested fees for each speed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeSuggestions {
+    /// Base fee of the next block; `None` on legacy chains.
+    pub base_fee_per_gas: Option<U256>,
+    pub slow: FeeSuggestion,
+    pub normal: FeeSuggestion,
+    pub fast: FeeSuggestion,
+}
+
+impl FeeSuggestions {
+    /// Suggestions for each speed in order.
+    fn speeds(&self) -> [FeeSuggestion; 3] {
+        [self.slow, self.normal, self.fast]
+    }
+
+    /// Fee expected to be paid for each unit of gas.
+    fn expected_fee(&self, suggestion: &FeeSuggestion) -> U256 {
+        match self.base_fee_per_gas {
+            Some(base_fee) => (base_fee + suggestion.max_priority_fee_per_gas)
+                .min(suggestion.max_fee_per_gas),
+            None => suggestion.max_fee_per_gas,
+        }
+    }
+}
+
+/// Median of the values; the upper median for an even count.
+fn median(mut values: Vec<U256>) -> Option<U256> {
+    values.sort();
+    values.get(values.len() / 2).copied()
+}
+
+/// Compute fee suggestions from a fee history.
+///
+/// Returns `None` when the chain does not report a base fee.
+pub fn suggest_from_history(history: &FeeHistory) -> Option<FeeSuggestions> {
+    let base_fee = history
+        .base_fee_per_gas
+        .last()
+        .copied()
+        .filter(|base_fee| !base_fee.is_zero())?;
+
+    let mut priority_fees = [U256::from(FALLBACK_PRIORITY_FEE); 3];
+    for (index, priority_fee) in priority_fees.iter_mut().enumerate() {
+        let rewards: Vec<U256> = history
+            .reward
+            .iter()
+            .zip(&history.gas_used_ratio)
+            .filter(|(_, ratio)| **ratio > 0.0)
+            .filter_map(|(rewards, _)| rewards.get(index).copied())
+            .collect();
+        if let Some(reward) = median(rewards) {
+            *priority_fee = reward;
+        }
+    }
+    // Faster speeds never pay a lower priority fee
+    priority_fees[1] = priority_fees[1].max(priority_fees[0]);
+    priority_fees[2] = priority_fees[2].max(priority_fees[1]);
+
+    let [slow, normal, fast] = [0, 1, 2].map(|index| FeeSuggestion {
+        max_fee_per_gas: base_fee * BASE_FEE_MULTIPLIERS[index] / 100
+            + priority_fees[index],
+        max_priority_fee_per_gas: priority_fees[index],
+    });
+    Some(FeeSuggestions {
+        base_fee_per_gas: Some(base_fee),
+        slow,
+        normal,
+        fast,
+    })
+}
+
+/// Compute fee suggestions for a legacy chain from the gas price.
+pub fn suggest_from_gas_price(gas_price: U256) -> FeeSuggestions {
+    let [slow, normal, fast] = GAS_PRICE_MULTIPLIERS.map(|multiplier| {
+        let gas_price = gas_price * multiplier / 100;
+        FeeSuggestion {
+            max_fee_per_gas: gas_price,
+            max_priority_fee_per_gas: gas_price,
+        }
+    });
+    FeeSuggestions {
+        base_fee_per_gas: None,
+        slow,
+        normal,
+        fast,
+    }
+}
+
+/// Fee suggestions from the response to `eth_feeHistory`.
+///
+/// Returns `None` when the gas price should be used instead
+/// because the node does not support the method or the chain
+/// does not report a base fee; other errors are returned.
+fn suggest_from_response(
+    response: ProviderResult<FeeHistory>,
+) -> ProviderResult<Option<FeeSuggestions>> {
+    match response {
+        Ok(history) => Ok(suggest_from_history(&history)),
+        Err(e) if e.code == METHOD_NOT_FOUND => {
+            log::debug!("fee history not available: {}", e.message);
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Fetch fee suggestions from the upstream node.
+///
+/// Nodes that do not support `eth_feeHistory` are treated as
+/// legacy chains.
+pub async fn suggest_fees(
+    upstream: &Upstream,
+) -> ProviderResult<FeeSuggestions> {
+    let response = upstream
+        .call::<FeeHistory>(
+            "eth_feeHistory",
+            json!([
+                format!("{:#x}", FEE_HISTORY_BLOCKS),
+                "latest",
+                REWARD_PERCENTILES,
+            ]),
+        )
+        .await;
+    if let Some(suggestions) = suggest_from_response(response)? {
+        return Ok(suggestions);
+    }
+    let gas_price: U256 = upstream.call("eth_gasPrice", json!([])).await?;
+    Ok(suggest_from_gas_price(gas_price))
+}
+
+/// Estimated cost of a transaction in US dollars at each speed.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct FiatEstimates {
+    pub slow: f64,
+    pub normal: f64,
+    pub fast: f64,
+}
+
+/// Fee suggestions with fiat estimates for the wallet UI.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FeeEstimates {
+    pub chain_id: u64,
+    #[serde(flatten)]
+    pub fees: FeeSuggestions,
+    /// Gas limit used for the fiat estimates.
+    pub gas: U256,
+    /// Only available for chains that pay fees in ether.
+    pub usd: Option<FiatEstimates>,
+}
+
+/// Cost of a transaction in US dollars.
+fn usd_cost(gas: U256, fee_per_gas: U256, eth_usd: f64) -> Result<f64> {
+    let ether: f64 = format_units(gas * fee_per_gas, 18).parse()?;
+    Ok(ether * eth_usd)
+}
+
+fn fiat_estimates(
+    fees: &FeeSuggestions,
+    gas: U256,
+    eth_usd: f64,
+) -> Result<FiatEstimates> {
+    let [slow, normal, fast] = fees.speeds();
+    let cost = |suggestion: &FeeSuggestion| {
+        usd_cost(gas, fees.expected_fee(suggestion), eth_usd)
+    };
+    Ok(FiatEstimates {
+        slow: cost(&slow)?,
+        normal: cost(&normal)?,
+        fast: cost(&fast)?,
+    })
+}
+
+/// Suggest fees for a chain with fiat estimates for a gas limit.
+///
+/// The gas limit of a simple transfer is used when none is given.
+pub async fn estimate_fees(
+    chain_id: u64,
+    gas: Option<U256>,
+) -> Result<FeeEstimates> {
+    let upstream = Upstream::for_chain(chain_id)?;
+    let fees = suggest_fees(&upstream).await?;
+    let gas = gas.unwrap_or_else(|| U256::from(MIN_GAS_LIMIT));
+
+    let pays_ether = NETWORK_REGISTRY
+        .read()
+        .unwrap()
+        .get(chain_id)
+        .map(|network| network.native_currency.symbol == "ETH")
+        .unwrap_or(false);
+    let usd = if pays_ether {
+        match crate::rates::eth_usd().await {
+            Ok(rate) => Some(fiat_estimates(&fees, gas, rate)?),
+            Err(e) => {
+                log::warn!("could not fetch the ETH/USD rate: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    Ok(FeeEstimates {
+        chain_id,
+        fees,
+        gas,
+        usd,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{ProviderError, LIMIT_EXCEEDED};
+
+    const MAINNET: &str = include_str!("fixtures/fee_history_mainnet.json");
+    const LEGACY: &str = include_str!("fixtures/fee_history_legacy.json");
+
+    #[test]
+    fn suggest_fees_from_history() -> Result<()> {
+        let history: FeeHistory = serde_json::from_str(MAINNET)?;
+        let fees = suggest_from_history(&history).unwrap();
+        assert_eq!(Some(U256::from(13_125_929_314u64)), fees.base_fee_per_gas);
+        assert_eq!(
+            FeeSuggestion {
+                max_fee_per_gas: U256::from(16_437_411_642u64),
+                max_priority_fee_per_gas: U256::from(30_000_000u64),
+            },
+            fees.slow
+        );
+        assert_eq!(
+            FeeSuggestion {
+                max_fee_per_gas: U256::from(27_351_858_628u64),
+                max_priority_fee_per_gas: U256::from(1_100_000_000u64),
+            },
+            fees.normal
+        );
+        assert_eq!(
+            FeeSuggestion {
+                max_fee_per_gas: U256::from(36_714_823_285u64),
+                max_priority_fee_per_gas: U256::from(3_900_000_000u64),
+            },
+            fees.fast
+        );
+
+        // Only empty blocks use the fallback priority fee
+        let mut empty = history.clone();
+        empty.gas_used_ratio = vec![0.0; empty.gas_used_ratio.len()];
+        let fees = suggest_from_history(&empty).unwrap();
+        assert_eq!(
+            U256::from(FALLBACK_PRIORITY_FEE),
+            fees.fast.max_priority_fee_per_gas
+        );
+
+        // Chains without a base fee use the gas price
+        let history: FeeHistory = serde_json::from_str(LEGACY)?;
+        assert!(suggest_from_history(&history).is_none());
+        assert!(suggest_from_response(Ok(history))?.is_none());
+        let error = ProviderError::new(METHOD_NOT_FOUND, "not found");
+        assert!(suggest_from_response(Err(error))?.is_none());
+
+        // Other errors are not mistaken for a legacy chain
+        let error = ProviderError::new(LIMIT_EXCEEDED, "limit exceeded");
+        assert!(suggest_from_response(Err(error)).is_err());
+
+        let fees = suggest_from_gas_price(U256::from(20_000_000_000u64));
+        assert_eq!(None, fees.base_fee_per_gas);
+        assert_eq!(U256::from(18_000_000_000u64), fees.slow.max_fee_per_gas);
+        assert_eq!(U256::from(25_000_000_000u64), fees.fast.max_fee_per_gas);
+
+        // A transfer at 20 gwei costs 0.00042 ether
+        let usd = fiat_estimates(&fees, U256::from(MIN_GAS_LIMIT), 2000.0)?;
+        assert!((usd.normal - 0.84).abs() < 1e-9);
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+; Validate a signature without deploying anything (ERC-6492).
+;
+; Run as the init code of a contract creation in `eth_call` with
+; `abi.encode(address signer, bytes32 hash, bytes signature)`
+; appended. Returns the single byte 0x01 when the signature is
+; valid and 0x00 otherwise, like `ValidateSigOffchain`.
+;
+; * Signatures ending with the ERC-6492 magic suffix are unwrapped
+;   and the factory is called first when the signer has no code.
+; * Signers with code are asked with `isValidSignature` (EIP-1271).
+; * Other signatures must be 65 bytes and are checked with
+;   `ecrecover`.
+;
+; Memory holds the arguments from offset 0; calls are built at
+; MSIZE so they never overlap the arguments. Stack comments list
+; the stack from bottom to top.
+
+    ; copy the arguments to memory
+    PUSH2 END
+    CODESIZE
+    SUB
+    PUSH2 END
+    PUSH1 0x00
+    CODECOPY
+
+    PUSH1 0x40
+    MLOAD               ; sigOff
+    DUP1
+    MLOAD               ; sigOff sigLen
+    SWAP1
+    PUSH1 0x20
+    ADD                 ; sigLen sigData
+
+    ; signatures shorter than the suffix are not wrapped
+    PUSH1 0x20
+    DUP3
+    LT
+    PUSH2 NO_SUFFIX
+    JUMPI
+
+    DUP2
+    DUP2
+    ADD
+    PUSH1 0x20
+    SWAP1
+    SUB
+    MLOAD               ; last word of the signature
+    PUSH32 0x6492649264926492649264926492649264926492649264926492649264926492
+    EQ
+    ISZERO
+    PUSH2 NO_SUFFIX
+    JUMPI
+
+    ; wrapped = abi.encode(factory, factoryData, signature)
+    SWAP1
+    POP                 ; W
+    PUSH1 0x00
+    MLOAD
+    EXTCODESIZE
+    PUSH2 DEPLOYED
+    JUMPI
+
+    ; call(gas, factory, 0, factoryData, len, 0, 0)
+    PUSH1 0x00
+    PUSH1 0x00
+    DUP3
+    PUSH1 0x20
+    ADD
+    MLOAD
+    DUP4
+    ADD                 ; W 0 0 fdPtr
+    DUP1
+    MLOAD
+    SWAP1
+    PUSH1 0x20
+    ADD                 ; W 0 0 fdLen fdData
+    PUSH1 0x00
+    DUP6
+    MLOAD               ; factory
+    GAS
+    CALL
+    ISZERO
+    PUSH2 FAIL
+    JUMPI
+
+DEPLOYED:               ; W
+    DUP1
+    PUSH1 0x40
+    ADD
+    MLOAD
+    ADD                 ; innerPtr
+    DUP1
+    MLOAD
+    SWAP1
+    PUSH1 0x20
+    ADD                 ; innerLen innerData
+    PUSH2 ERC1271
+    JUMP
+
+NO_SUFFIX:              ; sigLen sigData
+    PUSH1 0x00
+    MLOAD
+    EXTCODESIZE
+    PUSH2 ERC1271
+    JUMPI
+
+    ; ecrecover(hash, v, r, s) == signer
+    PUSH1 0x41
+    DUP3
+    EQ
+    ISZERO
+    PUSH2 FAIL
+    JUMPI
+    MSIZE               ; len data P
+    PUSH1 0x20
+    MLOAD
+    DUP2
+    MSTORE              ; hash
+    DUP2
+    PUSH1 0x40
+    ADD
+    MLOAD
+    PUSH1 0x00
+    BYTE
+    DUP2
+    PUSH1 0x20
+    ADD
+    MSTORE              ; v
+    DUP2
+    MLOAD
+    DUP2
+    PUSH1 0x40
+    ADD
+    MSTORE              ; r
+    DUP2
+    PUSH1 0x20
+    ADD
+    MLOAD
+    DUP2
+    PUSH1 0x60
+    ADD
+    MSTORE              ; s
+    PUSH1 0x20
+    DUP2
+    PUSH1 0x80
+    DUP4
+    PUSH1 0x01
+    GAS
+    STATICCALL
+    POP
+    RETURNDATASIZE
+    PUSH1 0x20
+    EQ
+    ISZERO
+    PUSH2 FAIL
+    JUMPI
+    MLOAD
+    PUSH1 0x00
+    MLOAD
+    EQ                  ; len data valid
+    PUSH2 RESULT
+    JUMP
+
+ERC1271:                ; len data
+    MSIZE               ; len data P
+    PUSH4 0x1626ba7e
+    PUSH1 0xe0
+    SHL
+    DUP2
+    MSTORE              ; selector
+    PUSH1 0x20
+    MLOAD
+    DUP2
+    PUSH1 0x04
+    ADD
+    MSTORE              ; hash
+    PUSH1 0x40
+    DUP2
+    PUSH1 0x24
+    ADD
+    MSTORE              ; offset of the signature
+    DUP3
+    DUP2
+    PUSH1 0x44
+    ADD
+    MSTORE              ; length of the signature
+
+    ; copy the signature with the identity precompile
+    DUP3
+    DUP2
+    PUSH1 0x64
+    ADD
+    DUP5
+    DUP5
+    PUSH1 0x04
+    GAS
+    STATICCALL
+    POP
+
+    DUP3
+    PUSH1 0x1f
+    ADD
+    PUSH1 0x05
+    SHR
+    PUSH1 0x05
+    SHL
+    PUSH1 0x64
+    ADD                 ; len data P size
+
+    ; staticcall(gas, signer, P, size, P, 32)
+    PUSH1 0x20
+    DUP3
+    DUP3
+    DUP5
+    PUSH1 0x00
+    MLOAD
+    GAS
+    STATICCALL          ; len data P size success
+    RETURNDATASIZE
+    PUSH1 0x20
+    GT
+    ISZERO
+    AND
+    DUP3
+    MLOAD
+    PUSH1 0xe0
+    SHR
+    PUSH4 0x1626ba7e
+    EQ
+    AND
+    SWAP2
+    POP
+    POP                 ; len data valid
+
+RESULT:                 ; len data valid
+    PUSH1 0x00
+    MSTORE
+    PUSH1 0x01
+    PUSH1 0x1f
+    RETURN
+
+FAIL:
+    PUSH1 0x00
+    DUP1
+    REVERT
+
+END:
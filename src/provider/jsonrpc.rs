@@ -0,0 +1,117 @@
+//! JSON-RPC 2.0 request and response envelopes for the provider.
+//!
+//! We do not use the `json_rpc2` types here as the provider
+//! must be able to reply with the EIP-1193 error codes.
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use super::error::{ProviderError, ProviderResult};
+
+const VERSION: &str = "2.0";
+
+/// Preserve an explicit `null` identifier so that it is not
+/// mistaken for a notification.
+fn some_value<'de, D>(deserializer: D) -> Result<Option<Value>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Value::deserialize(deserializer).map(Some)
+}
+
+/// Request sent by a provider client.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Request {
+    jsonrpc: String,
+    #[serde(
+        default,
+        deserialize_with = "some_value",
+        skip_serializing_if = "Option::is_none"
+    )]
+    id: Option<Value>,
+    method: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    params: Option<Value>,
+}
+
+impl Request {
+    /// Parse and validate a request from a JSON value.
+    pub fn from_value(value: Value) -> ProviderResult<Self> {
+        let request: Request = serde_json::from_value(value)
+            .map_err(ProviderError::invalid_request)?;
+        if request.jsonrpc != VERSION {
+            return Err(ProviderError::invalid_request(
+                "jsonrpc version must be 2.0",
+            ));
+        }
+        Ok(request)
+    }
+
+    /// The method name.
+    pub fn method(&self) -> &str {
+        &self.method
+    }
+
+    /// The request identifier.
+    pub fn id(&self) -> Option<&Value> {
+        self.id.as_ref()
+    }
+
+    /// Determine if this request is a notification.
+    pub fn is_notification(&self) -> bool {
+        self.id.is_none()
+    }
+
+    /// The raw request parameters.
+    pub fn raw_params(&self) -> Value {
+        self.params.clone().unwrap_or_else(|| Value::Array(vec![]))
+    }
+
+    /// Deserialize the request parameters.
+    ///
+    /// Missing parameters are treated as an empty array.
+    pub fn params<T: DeserializeOwned>(&self) -> ProviderResult<T> {
+        serde_json::from_value(self.raw_params())
+            .map_err(ProviderError::invalid_params)
+    }
+}
+
+/// Response sent to a provider client.
+#[derive(Debug, Clone, Serialize)]
+pub struct Response {
+    jsonrpc: &'static str,
+    id: Value,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    result: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<ProviderError>,
+}
+
+impl Response {
+    /// Create a successful response.
+    pub fn result(id: Value, result: Value) -> Self {
+        Self {
+            jsonrpc: VERSION,
+            id,
+            result: Some(result),
+            error: None,
+        }
+    }
+
+    /// Create a response from the result of a method.
+    pub fn new(id: Value, result: ProviderResult<Value>) -> Self {
+        match result {
+            Ok(value) => Self::result(id, value),
+            Err(e) => Self::error(id, e),
+        }
+    }
+
+    /// Create an error response.
+    pub fn error(id: Value, error: ProviderError) -> Self {
+        Self {
+            jsonrpc: VERSION,
+            id,
+            result: None,
+            error: Some(error),
+        }
+    }
+}
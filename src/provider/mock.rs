@@ -0,0 +1,112 @@
+//! JSON-RPC server on the loopback interface for tests.
+//!
+//! Each connection carries a single HTTP request; the body is
+//! recorded and answered with the result of a handler so tests
+//! can stand in for upstream nodes and bundlers.
+use serde_json::{json, Value};
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::sync::{Arc, Mutex};
+use url::Url;
+
+use crate::upstream::{Endpoint, Upstream};
+
+use super::ProviderResult;
+
+/// Mock JSON-RPC server.
+pub struct MockServer {
+    url: Url,
+    requests: Arc<Mutex<Vec<Value>>>,
+}
+
+impl MockServer {
+    /// Serve requests until the test exits, answering each with
+    /// the outcome of `handler` for the method and parameters.
+    pub fn start<F>(handler: F) -> Self
+    where
+        F: Fn(&str, &Value) -> ProviderResult<Value> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&requests);
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let request = read_request(&mut stream);
+                received.lock().unwrap().push(request.clone());
+                let method = request["method"].as_str().unwrap_or_default();
+                let mut response = json!({
+                    "jsonrpc": "2.0",
+                    "id": request["id"],
+                });
+                match handler(method, &request["params"]) {
+                    Ok(result) => response["result"] = result,
+                    Err(error) => response["error"] = json!(error),
+                }
+                let body = response.to_string();
+                write!(
+                    stream,
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                     Connection: close\r\nContent-Length: {}\r\n\r\n{}",
+                    body.len(),
+                    body
+                )
+                .unwrap();
+            }
+        });
+        Self { url, requests }
+    }
+
+    /// URL of the server.
+    pub fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    /// Client that uses the server as the only upstream node.
+    pub fn upstream(&self, chain_id: u64) -> Upstream {
+        Upstream::new(chain_id, vec![Endpoint::new(self.url())])
+    }
+
+    /// Bodies of the requests received so far.
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    /// Methods of the requests received so far.
+    pub fn methods(&self) -> Vec<String> {
+        self.requests()
+            .iter()
+            .filter_map(|request| request["method"].as_str())
+            .map(String::from)
+            .collect()
+    }
+}
+
+/// Read one HTTP request and return its JSON body.
+fn read_request(stream: &mut impl Read) -> Value {
+    let mut request = String::new();
+    let mut buffer = [0u8; 4096];
+    loop {
+        let read = stream.read(&mut buffer).unwrap();
+        request.push_str(std::str::from_utf8(&buffer[..read]).unwrap());
+        if let Some((headers, body)) = request.split_once("\r\n\r\n") {
+            let len = headers
+                .lines()
+                .find_map(|line| {
+                    line.to_lowercase()
+                        .strip_prefix("content-length: ")
+                        .and_then(|len| len.parse::<usize>().ok())
+                })
+                .unwrap_or(0);
+            if body.len() >= len {
+                return serde_json::from_str(body).unwrap_or(Value::Null);
+            }
+        }
+        if read == 0 {
+            return Value::Null;
+        }
+    }
+}
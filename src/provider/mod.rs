@@ -0,0 +1,270 @@
+//! Ethereum provider exposed to OAuth clients.
+//!
+//! Requests are JSON-RPC 2.0 and errors use the codes
+//! defined by EIP-1193.
+use oxide_auth::primitives::grant::Grant;
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::helpers::run_ordered;
+use crate::user::USER_DATA;
+
+mod accounts;
+mod approvals;
+mod assets;
+mod calls;
+mod chains;
+mod consent;
+mod delegation;
+mod discover;
+mod encryption;
+mod error;
+mod events;
+mod fees;
+mod jsonrpc;
+#[cfg(test)]
+mod mock;
+mod nonce;
+mod passthrough;
+mod permissions;
+mod safe;
+mod send;
+mod sign;
+mod siwe;
+mod subscription;
+mod transaction;
+mod typed_data;
+mod user_operation;
+mod verify;
+mod wallet;
+
+pub use approvals::{
+    approve_request, enable_approvals, pending_approvals, reject_request,
+    ApprovalKind, PendingApproval,
+};
+pub use error::*;
+pub use events::{Listener, ProviderEvent};
+pub use fees::{estimate_fees, FeeEstimates, FeeSuggestions};
+pub use jsonrpc::{Request, Response};
+pub use nonce::{fill_nonce_gaps, nonce_status, reset_nonces, NonceStatus};
+pub use permissions::{requires_consent, READ_SCOPE, SCOPES};
+pub use safe::{
+    export_safe_transaction, import_safe_transaction, merge_safe_transactions,
+    sign_safe_transaction, SignedSafeTransaction,
+};
+pub use subscription::Subscription;
+pub use transaction::{sign_for_owner, TransactionParams};
+pub use user_operation::create_smart_account;
+pub use verify::{
+    verify_message, verify_signature, SignedMessage, Verification,
+    VerificationMethod,
+};
+
+/// Chain identifier used when a client has not selected a network.
+pub const DEFAULT_CHAIN_ID: u64 = 1;
+
+/// Maximum number of requests in a batch.
+pub const MAX_BATCH_SIZE: usize = 100;
+
+/// Information about the caller derived from an OAuth grant.
+#[derive(Debug, Clone)]
+pub struct Context {
+    /// Identifier of the registered client.
+    pub client_id: String,
+    /// Resource owner that authorized the client.
+    pub owner_id: String,
+    /// Scope granted to the client.
+    pub scope: String,
+    /// Redirect URI registered for the client.
+    pub redirect_uri: Url,
+}
+
+impl Context {
+    /// Determine if the client was granted a scope.
+    pub fn has_scope(&self, scope: &str) -> bool {
+        self.scope.split_whitespace().any(|s| s == scope)
+    }
+
+    /// Origin of the client.
+    pub fn origin(&self) -> String {
+        self.redirect_uri.origin().ascii_serialization()
+    }
+}
+
+impl From<&Grant> for Context {
+    fn from(grant: &Grant) -> Self {
+        Self {
+            client_id: grant.client_id.clone(),
+            owner_id: grant.owner_id.clone(),
+            scope: grant.scope.to_string(),
+            redirect_uri: grant.redirect_uri.clone(),
+        }
+    }
+}
+
+/// Handle a request and create a response.
+///
+/// No response is returned for notifications.
+pub async fn handle(ctx: &Context, request: &Request) -> Option<Response> {
+    let result = dispatch(ctx, request).await;
+    if request.is_notification() {
+        return None;
+    }
+    let id = request.id().cloned().unwrap_or(Value::Null);
+    Some(Response::new(id, result))
+}
+
+/// Handle a JSON-RPC message which may be a single request
+/// or a batch of requests.
+///
+/// Returns the serialized reply or `None` when no reply should
+/// be sent because the message only contained notifications.
+pub async fn handle_message(ctx: &Context, message: Value) -> Option<Value> {
+    match message {
+        Value::Array(requests) => {
+            let responses = handle_batch(ctx, requests).await;
+            match responses {
+                Ok(responses) if responses.is_empty() => None,
+                Ok(responses) => Some(json!(responses)),
+                Err(e) => Some(json!(Response::error(Value::Null, e))),
+            }
+        }
+        message => {
+            let response = match Request::from_value(message) {
+                Ok(request) => handle(ctx, &request).await,
+                Err(e) => Some(Response::error(Value::Null, e)),
+            };
+            response.map(|response| json!(response))
+        }
+    }
+}
+
+/// Handle a batch of requests.
+///
+/// Methods that only read state run concurrently; methods that
+/// prompt the user or change state run in the order they appear
+/// in the batch. Responses are returned in request order and
+/// notifications are omitted.
+async fn handle_batch(
+    ctx: &Context,
+    requests: Vec<Value>,
+) -> ProviderResult<Vec<Response>> {
+    if requests.is_empty() {
+        return Err(ProviderError::invalid_request("batch must not be empty"));
+    }
+    if requests.len() > MAX_BATCH_SIZE {
+        return Err(ProviderError::invalid_request(format!(
+            "batch must not exceed {} requests",
+            MAX_BATCH_SIZE
+        )));
+    }
+
+    let requests: Vec<ProviderResult<Request>> =
+        requests.into_iter().map(Request::from_value).collect();
+    let responses = run_ordered(
+        requests,
+        |request| match request {
+            Ok(request) => is_concurrent(request.method()),
+            Err(_) => true,
+        },
+        |request| async move {
+            match request {
+                Ok(request) => handle(ctx, &request).await,
+                Err(e) => Some(Response::error(Value::Null, e)),
+            }
+        },
+    )
+    .await;
+    Ok(responses.into_iter().flatten().collect())
+}
+
+/// Determine if a method can run concurrently with other
+/// requests in a batch.
+fn is_concurrent(method: &str) -> bool {
+    matches!(
+        method,
+        "eth_chainId"
+            | "net_version"
+            | "eth_accounts"
+            | "web3_clientVersion"
+            | "rpc.discover"
+    ) || passthrough::is_allowed(method)
+}
+
+/// Call the method for a request.
+async fn dispatch(ctx: &Context, request: &Request) -> ProviderResult<Value> {
+    if !ctx.has_scope(permissions::required_scope(request.method())) {
+        return Err(ProviderError::unauthorized());
+    }
+
+    match request.method() {
+        "eth_chainId" => Ok(Value::String(format!("{:#x}", chain_id(ctx)))),
+        "net_version" => Ok(Value::String(chain_id(ctx).to_string())),
+        "eth_accounts" => to_value(accounts::accounts(ctx)),
+        "eth_requestAccounts" => {
+            to_value(accounts::request_accounts(ctx).await?)
+        }
+        "personal_sign" => sign::personal_sign(ctx, request).await,
+        "eth_sign" => sign::eth_sign(ctx, request).await,
+        "wallet_signIntendedValidator" => {
+            sign::sign_intended_validator(ctx, request).await
+        }
+        "eth_signTypedData_v3" => {
+            sign::sign_typed_data(ctx, request, typed_data::Version::V3).await
+        }
+        "eth_signTypedData_v4" => {
+            sign::sign_typed_data(ctx, request, typed_data::Version::V4).await
+        }
+        "eth_getEncryptionPublicKey" => {
+            encryption::get_encryption_public_key(ctx, request).await
+        }
+        "eth_decrypt" => encryption::decrypt_message(ctx, request).await,
+        "wallet_signAuthorization" => {
+            delegation::sign_authorization(ctx, request).await
+        }
+        "eth_signTransaction" => {
+            transaction::sign_transaction(ctx, request).await
+        }
+        "eth_sendTransaction" => send::send_transaction(ctx, request).await,
+        "wallet_addEthereumChain" => chains::add_chain(ctx, request).await,
+        "wallet_switchEthereumChain" => {
+            chains::switch_chain(ctx, request).await
+        }
+        "wallet_getPermissions" => to_value(permissions::permissions(ctx)),
+        "wallet_requestPermissions" => {
+            permissions::request_permissions(ctx, request).await
+        }
+        "wallet_revokePermissions" => {
+            permissions::revoke_permissions(ctx, request)
+        }
+        "wallet_watchAsset" => assets::watch_asset(ctx, request).await,
+        "wallet_getCapabilities" => calls::get_capabilities(ctx, request),
+        "wallet_sendCalls" => calls::send_calls(ctx, request).await,
+        "wallet_getCallsStatus" => calls::get_calls_status(ctx, request),
+        "wallet_showCallsStatus" => {
+            calls::show_calls_status(ctx, request).await
+        }
+        "web3_clientVersion" => Ok(Value::String(format!(
+            "{}/v{}",
+            env!("CARGO_PKG_NAME"),
+            env!("CARGO_PKG_VERSION")
+        ))),
+        "rpc.discover" => to_value(discover::document()),
+        method if passthrough::is_allowed(method) => {
+            passthrough::forward(ctx, request).await
+        }
+        _ => Err(ProviderError::unsupported_method(request.method())),
+    }
+}
+
+/// Convert a method result to a JSON value.
+fn to_value<T: serde::Serialize>(value: T) -> ProviderResult<Value> {
+    serde_json::to_value(value).map_err(ProviderError::internal)
+}
+
+/// Chain identifier for a client.
+fn chain_id(ctx: &Context) -> u64 {
+    let user = USER_DATA.read().unwrap();
+    user.connected_chain(&ctx.client_id)
+        .unwrap_or(DEFAULT_CHAIN_ID)
+}
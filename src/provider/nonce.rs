@@ -0,0 +1,415 @@
+//! Nonces for transactions sent by the wallet.
+//!
+//! The next nonce for each account and chain is tracked locally
+//! so concurrent `eth_sendTransaction` requests never share a
+//! nonce. The local state is reconciled with the pending count
+//! reported by the upstream node whenever a nonce is handed out.
+//!
+//! A nonce that was handed out but never broadcast, for example
+//! because the owner rejected the transaction, leaves a gap that
+//! stalls every later transaction; gaps are reused by the next
+//! transaction and may be filled or reset by the owner. A
+//! broadcast transaction the node has not counted yet is only a
+//! gap once the node no longer knows its hash.
+use anyhow::{bail, Result};
+use ethers_core::types::{Address, H256, U256};
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Mutex;
+
+use crate::upstream::Upstream;
+use crate::user::USER_DATA;
+
+use super::{
+    send::fill_transaction,
+    transaction::{sign_params, TransactionParams},
+    wallet::{parse_address, unlock},
+    ProviderResult,
+};
+
+static NONCES: Lazy<Mutex<HashMap<NonceKey, Nonces>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Identifies the nonces of an account on a chain.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+struct NonceKey {
+    chain_id: u64,
+    address: Address,
+}
+
+/// Nonces handed out for an account on a chain.
+#[derive(Debug, Default)]
+struct Nonces {
+    /// Pending transaction count last reported by the node.
+    pending: U256,
+    /// Next nonce that has never been handed out.
+    next: U256,
+    /// Handed out but not broadcast yet.
+    in_flight: BTreeSet<U256>,
+    /// Hashes of transactions broadcast and not yet counted by
+    /// the node.
+    sent: BTreeMap<U256, H256>,
+    /// Handed out and given back without being broadcast.
+    released: BTreeSet<U256>,
+}
+
+impl Nonces {
+    /// Update the state with the pending transaction count from
+    /// the upstream node.
+    fn reconcile(&mut self, pending: U256) {
+        self.pending = pending;
+        self.next = self.next.max(pending);
+        self.sent = self.sent.split_off(&pending);
+        self.released = self.released.split_off(&pending);
+    }
+
+    /// Nonces that stall later transactions.
+    ///
+    /// Besides the released nonces, the pending count is a gap
+    /// when it is below the next nonce, not in flight and not
+    /// waiting to be counted; the node is missing that
+    /// transaction, most likely because it was dropped.
+    fn gaps(&self) -> Vec<U256> {
+        let mut gaps = self.released.clone();
+        let is_stalled = self.pending < self.next
+            && !self.in_flight.contains(&self.pending)
+            && !self.sent.contains_key(&self.pending);
+        if is_stalled {
+            gaps.insert(self.pending);
+        }
+        gaps.into_iter().collect()
+    }
+
+    /// Hash of the broadcast transaction that the node has not
+    /// counted yet, if it holds up later transactions.
+    fn uncounted(&self) -> Option<H256> {
+        self.sent.get(&self.pending).copied()
+    }
+
+    /// Forget a broadcast transaction that the node dropped.
+    fn forget(&mut self, nonce: U256, hash: H256) {
+        if self.sent.get(&nonce) == Some(&hash) {
+            self.sent.remove(&nonce);
+        }
+    }
+
+    /// Hand out the lowest released nonce or the next nonce.
+    fn take(&mut self) -> U256 {
+        let nonce = match self.released.iter().next().copied() {
+            Some(nonce) => {
+                self.released.remove(&nonce);
+                nonce
+            }
+            None => {
+                let nonce = self.next;
+                self.next += U256::one();
+                nonce
+            }
+        };
+        self.in_flight.insert(nonce);
+        nonce
+    }
+
+    /// Hand out a specific nonce to fill a gap.
+    ///
+    /// Nonces of broadcast transactions are never handed out again.
+    fn take_gap(&mut self, nonce: U256) -> bool {
+        if nonce < self.pending
+            || self.in_flight.contains(&nonce)
+            || self.sent.contains_key(&nonce)
+        {
+            return false;
+        }
+        self.released.remove(&nonce);
+        self.in_flight.insert(nonce);
+        self.next = self.next.max(nonce + U256::one());
+        true
+    }
+
+    /// Record that a nonce was broadcast.
+    fn commit(&mut self, nonce: U256, hash: H256) {
+        self.in_flight.remove(&nonce);
+        self.sent.insert(nonce, hash);
+    }
+
+    /// Give back a nonce that was not broadcast.
+    ///
+    /// Released nonces at the end are dropped so they do not
+    /// become gaps.
+    fn release(&mut self, nonce: U256) {
+        self.in_flight.remove(&nonce);
+        self.released.insert(nonce);
+        while self.next > self.pending {
+            let last = self.next - U256::one();
+            if !self.released.remove(&last) {
+                break;
+            }
+            self.next = last;
+        }
+    }
+}
+
+/// Nonce handed out for a transaction.
+///
+/// The nonce is returned when the reservation is dropped without
+/// being committed.
+#[derive(Debug)]
+pub struct Reservation {
+    key: NonceKey,
+    nonce: U256,
+    committed: bool,
+}
+
+impl Reservation {
+    /// The reserved nonce.
+    pub fn nonce(&self) -> U256 {
+        self.nonce
+    }
+
+    /// Mark the nonce as used once the transaction with a hash
+    /// was broadcast.
+    pub fn commit(mut self, hash: H256) {
+        self.committed = true;
+        let mut nonces = NONCES.lock().unwrap();
+        if let Some(state) = nonces.get_mut(&self.key) {
+            state.commit(self.nonce, hash);
+        }
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+        let mut nonces = NONCES.lock().unwrap();
+        if let Some(state) = nonces.get_mut(&self.key) {
+            state.release(self.nonce);
+        }
+    }
+}
+
+/// Pending transaction count from the upstream node.
+async fn pending_count(
+    upstream: &Upstream,
+    address: Address,
+) -> ProviderResult<U256> {
+    Ok(upstream
+        .call("eth_getTransactionCount", json!([address, "pending"]))
+        .await?)
+}
+
+/// Reserve the next nonce for an account.
+pub async fn reserve(
+    upstream: &Upstream,
+    address: Address,
+) -> ProviderResult<Reservation> {
+    let pending = pending_count(upstream, address).await?;
+    let key = NonceKey {
+        chain_id: upstream.chain_id(),
+        address,
+    };
+    let mut nonces = NONCES.lock().unwrap();
+    let state = nonces.entry(key).or_default();
+    state.reconcile(pending);
+    Ok(Reservation {
+        key,
+        nonce: state.take(),
+        committed: false,
+    })
+}
+
+/// Nonces of an account on a chain reported to the owner.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NonceStatus {
+    pub chain_id: u64,
+    pub address: Address,
+    /// Pending transaction count reported by the upstream node.
+    pub pending: U256,
+    /// Nonce for the next transaction.
+    pub next: U256,
+    /// Nonces of transactions waiting to be broadcast.
+    pub in_flight: Vec<U256>,
+    /// Nonces that stall later transactions.
+    pub gaps: Vec<U256>,
+}
+
+/// Reconcile and report the nonces of an account.
+pub async fn nonce_status(
+    chain_id: u64,
+    address: String,
+) -> Result<NonceStatus> {
+    let address = parse_address(&address)?;
+    let upstream = Upstream::for_chain(chain_id)?;
+    let pending = pending_count(&upstream, address).await?;
+
+    let key = NonceKey { chain_id, address };
+    let uncounted = {
+        let mut nonces = NONCES.lock().unwrap();
+        let state = nonces.entry(key).or_default();
+        state.reconcile(pending);
+        state.uncounted()
+    };
+    // The transaction holding up the account was dropped when the
+    // node no longer knows it
+    let mut dropped = None;
+    if let Some(hash) = uncounted {
+        let transaction: Option<Value> = upstream
+            .call("eth_getTransactionByHash", json!([hash]))
+            .await?;
+        if transaction.is_none() {
+            dropped = Some(hash);
+        }
+    }
+
+    let mut nonces = NONCES.lock().unwrap();
+    let state = nonces.entry(key).or_default();
+    if let Some(hash) = dropped {
+        state.forget(pending, hash);
+    }
+    Ok(NonceStatus {
+        chain_id,
+        address,
+        pending: state.pending,
+        next: state.next,
+        in_flight: state.in_flight.iter().copied().collect(),
+        gaps: state.gaps(),
+    })
+}
+
+/// Forget the nonces tracked for an account so the next
+/// transaction uses the pending count from the upstream node.
+pub fn reset_nonces(chain_id: u64, address: String) -> Result<()> {
+    let address = parse_address(&address)?;
+    NONCES
+        .lock()
+        .unwrap()
+        .remove(&NonceKey { chain_id, address });
+    Ok(())
+}
+
+/// Fill the gaps for an account with empty transactions to the
+/// account itself.
+///
+/// The owner has already approved the request in the UI and
+/// supplied the passphrase to unlock the account. Returns the
+/// hashes of the transactions that were sent.
+pub async fn fill_nonce_gaps(
+    chain_id: u64,
+    address: String,
+    passphrase: String,
+) -> Result<Vec<H256>> {
+    if USER_DATA.read().unwrap().smart_account(&address).is_some() {
+        bail!("smart accounts do not use transaction nonces");
+    }
+    let status = nonce_status(chain_id, address.clone()).await?;
+    if status.gaps.is_empty() {
+        return Ok(vec![]);
+    }
+
+    let wallet = unlock(&address, passphrase).await?;
+    let upstream = Upstream::for_chain(chain_id)?;
+    let key = NonceKey {
+        chain_id,
+        address: status.address,
+    };
+    let mut hashes = vec![];
+    for gap in status.gaps {
+        let reservation = {
+            let mut nonces = NONCES.lock().unwrap();
+            if !nonces.entry(key).or_default().take_gap(gap) {
+                continue;
+            }
+            Reservation {
+                key,
+                nonce: gap,
+                committed: false,
+            }
+        };
+
+        let mut params = TransactionParams {
+            from: Some(status.address),
+            to: Some(status.address),
+            value: Some(U256::zero()),
+            nonce: Some(gap),
+            ..Default::default()
+        };
+        fill_transaction(&upstream, &mut params).await?;
+        let signed = sign_params(wallet.clone(), &params, chain_id).await?;
+        let hash: H256 = upstream
+            .call("eth_sendRawTransaction", json!([signed.raw]))
+            .await?;
+        reservation.commit(hash);
+        hashes.push(hash);
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn nonces(values: &[u64]) -> Vec<U256> {
+        values.iter().copied().map(U256::from).collect()
+    }
+
+    fn hash(nonce: u64) -> H256 {
+        H256::from_low_u64_be(nonce)
+    }
+
+    #[test]
+    fn hand_out_nonces() {
+        let mut state = Nonces::default();
+        state.reconcile(U256::from(5));
+
+        // Concurrent transactions never share a nonce
+        let first = state.take();
+        let second = state.take();
+        let third = state.take();
+        assert_eq!(nonces(&[5, 6, 7]), vec![first, second, third]);
+
+        // A rejected transaction leaves a gap that is reused
+        state.commit(first, hash(5));
+        state.commit(third, hash(7));
+        state.release(second);
+        state.reconcile(U256::from(6));
+        assert_eq!(nonces(&[6]), state.gaps());
+        assert_eq!(U256::from(6), state.take());
+        assert!(state.gaps().is_empty());
+        state.commit(U256::from(6), hash(6));
+        state.reconcile(U256::from(8));
+        assert!(state.gaps().is_empty());
+
+        // Released nonces at the end are given back
+        let last = state.take();
+        assert_eq!(U256::from(8), last);
+        state.release(last);
+        assert_eq!(U256::from(8), state.next);
+        assert!(state.gaps().is_empty());
+
+        // Transactions sent elsewhere move the next nonce forward
+        state.reconcile(U256::from(10));
+        assert_eq!(U256::from(10), state.take());
+
+        // A broadcast transaction the node has not counted yet is
+        // not a gap and its nonce is never handed out again
+        state.commit(U256::from(10), hash(10));
+        state.reconcile(U256::from(10));
+        assert!(state.gaps().is_empty());
+        assert_eq!(Some(hash(10)), state.uncounted());
+        assert!(!state.take_gap(U256::from(10)));
+
+        // Once the node dropped it the gap is reported and filled
+        state.forget(U256::from(10), hash(11));
+        assert!(state.gaps().is_empty());
+        state.forget(U256::from(10), hash(10));
+        assert_eq!(nonces(&[10]), state.gaps());
+        assert!(state.take_gap(U256::from(10)));
+        assert!(!state.take_gap(U256::from(10)));
+        assert!(!state.take_gap(U256::from(9)));
+        assert!(state.gaps().is_empty());
+    }
+}
@@ -0,0 +1,51 @@
+//! Forward read-only methods to the upstream nodes.
+//!
+//! Only methods on the allowlist are forwarded so that clients
+//! cannot use the agent to reach arbitrary node APIs.
+use serde_json::Value;
+
+use crate::upstream::Upstream;
+
+use super::{chain_id, Context, ProviderResult, Request};
+
+/// Methods that may be forwarded to an upstream node.
+pub const ALLOWED_METHODS: &[&str] = &[
+    "eth_blockNumber",
+    "eth_call",
+    "eth_estimateGas",
+    "eth_feeHistory",
+    "eth_gasPrice",
+    "eth_getBalance",
+    "eth_getBlockByHash",
+    "eth_getBlockByNumber",
+    "eth_getBlockTransactionCountByHash",
+    "eth_getBlockTransactionCountByNumber",
+    "eth_getCode",
+    "eth_getLogs",
+    "eth_getProof",
+    "eth_getStorageAt",
+    "eth_getTransactionByBlockHashAndIndex",
+    "eth_getTransactionByBlockNumberAndIndex",
+    "eth_getTransactionByHash",
+    "eth_getTransactionCount",
+    "eth_getTransactionReceipt",
+    "eth_getUncleCountByBlockHash",
+    "eth_getUncleCountByBlockNumber",
+    "eth_maxPriorityFeePerGas",
+    "eth_syncing",
+    "net_listening",
+];
+
+/// Determine if a method may be forwarded.
+pub fn is_allowed(method: &str) -> bool {
+    ALLOWED_METHODS.contains(&method)
+}
+
+/// Forward a request to the upstream nodes for the client chain.
+pub async fn forward(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let upstream = Upstream::for_chain(chain_id(ctx))?;
+    upstream.call(request.method(), request.raw_params()).await
+}
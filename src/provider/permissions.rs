@@ -0,0 +1,443 @@
+//! Wallet permissions (EIP-2255).
+//!
+//! OAuth scopes decide which methods a client may call at all;
+//! permissions record what the owner granted within those scopes
+//! and are stored with the connection of the client.
+use ethers_core::types::Address;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::network::NETWORK_REGISTRY;
+use crate::user::USER_DATA;
+
+use super::{
+    accounts::{accounts, select_accounts},
+    chain_id,
+    consent::{self, Approval},
+    Context, ProviderError, ProviderResult, Request, UNRECOGNIZED_CHAIN,
+};
+
+/// Scope required to use the provider; allows reading chain data.
+pub const READ_SCOPE: &str = "read";
+/// Scope to request and list accounts.
+pub const ACCOUNTS_SCOPE: &str = "accounts";
+/// Scope to sign messages.
+pub const SIGN_SCOPE: &str = "sign";
+/// Scope to sign and send transactions.
+pub const TRANSACT_SCOPE: &str = "transact";
+/// Scope to add and switch networks.
+pub const NETWORK_SCOPE: &str = "network";
+
+/// Every scope that can be granted to a client.
+pub const SCOPES: &[&str] = &[
+    READ_SCOPE,
+    ACCOUNTS_SCOPE,
+    SIGN_SCOPE,
+    TRANSACT_SCOPE,
+    NETWORK_SCOPE,
+];
+
+/// Permission to see accounts.
+const ETH_ACCOUNTS: &str = "eth_accounts";
+/// Permission to use networks.
+const PERMITTED_CHAINS: &str = "endowment:permitted-chains";
+
+const RESTRICT_RETURNED_ACCOUNTS: &str = "restrictReturnedAccounts";
+const RESTRICT_NETWORK_SWITCHING: &str = "restrictNetworkSwitching";
+
+/// Scope required to call a method.
+pub fn required_scope(method: &str) -> &'static str {
+    match method {
+        "eth_accounts" | "eth_requestAccounts" | "wallet_watchAsset" => {
+            ACCOUNTS_SCOPE
+        }
+        "personal_sign"
+        | "eth_sign"
+        | "wallet_signIntendedValidator"
+        | "eth_signTypedData_v3"
+        | "eth_signTypedData_v4"
+        | "eth_getEncryptionPublicKey"
+        | "eth_decrypt"
+        | "wallet_signAuthorization" => SIGN_SCOPE,
+        "eth_signTransaction"
+        | "eth_sendTransaction"
+        | "wallet_sendCalls"
+        | "wallet_getCallsStatus"
+        | "wallet_showCallsStatus" => TRANSACT_SCOPE,
+        "wallet_addEthereumChain" | "wallet_switchEthereumChain" => {
+            NETWORK_SCOPE
+        }
+        _ => READ_SCOPE,
+    }
+}
+
+/// Determine if a method may ask the owner for consent.
+pub fn requires_consent(method: &str) -> bool {
+    match method {
+        "eth_requestAccounts"
+        | "wallet_requestPermissions"
+        | "wallet_watchAsset" => true,
+        "wallet_getCallsStatus" => false,
+        _ => matches!(
+            required_scope(method),
+            SIGN_SCOPE | TRANSACT_SCOPE | NETWORK_SCOPE
+        ),
+    }
+}
+
+/// Scope required to request a permission.
+fn permission_scope(name: &str) -> ProviderResult<&'static str> {
+    match name {
+        ETH_ACCOUNTS => Ok(ACCOUNTS_SCOPE),
+        PERMITTED_CHAINS => Ok(NETWORK_SCOPE),
+        _ => Err(ProviderError::invalid_params(format!(
+            "unknown permission {}",
+            name
+        ))),
+    }
+}
+
+/// Restriction of a permission.
+#[derive(Debug, Serialize)]
+pub struct Caveat {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    value: Value,
+}
+
+/// Permission granted to a client.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Permission {
+    invoker: String,
+    parent_capability: &'static str,
+    caveats: Vec<Caveat>,
+}
+
+/// Permissions granted to a client.
+pub fn permissions(ctx: &Context) -> Vec<Permission> {
+    let mut permissions = Vec::new();
+
+    let accounts = accounts(ctx);
+    if !accounts.is_empty() {
+        permissions.push(Permission {
+            invoker: ctx.origin(),
+            parent_capability: ETH_ACCOUNTS,
+            caveats: vec![Caveat {
+                kind: RESTRICT_RETURNED_ACCOUNTS,
+                value: json!(accounts),
+            }],
+        });
+    }
+
+    let chains = USER_DATA.read().unwrap().permitted_chains(&ctx.client_id);
+    if !chains.is_empty() {
+        let chains: Vec<String> =
+            chains.iter().map(|id| format!("{:#x}", id)).collect();
+        permissions.push(Permission {
+            invoker: ctx.origin(),
+            parent_capability: PERMITTED_CHAINS,
+            caveats: vec![Caveat {
+                kind: RESTRICT_NETWORK_SWITCHING,
+                value: json!(chains),
+            }],
+        });
+    }
+
+    permissions
+}
+
+/// Parse the permissions in a request.
+///
+/// Every permission must be known and allowed by the scope
+/// granted to the client.
+fn requested(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Map<String, Value>> {
+    let (requested,): (Map<String, Value>,) = request.params()?;
+    if requested.is_empty() {
+        return Err(ProviderError::invalid_params(
+            "at least one permission is required",
+        ));
+    }
+    for name in requested.keys() {
+        if !ctx.has_scope(permission_scope(name)?) {
+            return Err(ProviderError::unauthorized());
+        }
+    }
+    Ok(requested)
+}
+
+/// Value of a caveat in a requested permission.
+fn caveat<'a>(request: &'a Value, kind: &str) -> Option<&'a Value> {
+    request
+        .get("caveats")
+        .and_then(Value::as_array)
+        .and_then(|caveats| {
+            caveats.iter().find(|caveat| {
+                caveat.get("type").and_then(Value::as_str) == Some(kind)
+            })
+        })
+        .and_then(|caveat| caveat.get("value"))
+}
+
+/// Accounts requested by a `restrictReturnedAccounts` caveat.
+fn requested_accounts(request: &Value) -> ProviderResult<Option<Vec<String>>> {
+    match caveat(request, RESTRICT_RETURNED_ACCOUNTS) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .filter(|s| s.parse::<Address>().is_ok())
+                    .map(String::from)
+                    .ok_or_else(|| {
+                        ProviderError::invalid_params(format!(
+                            "invalid account {}",
+                            value
+                        ))
+                    })
+            })
+            .collect::<ProviderResult<Vec<String>>>()
+            .map(Some),
+        Some(_) => Err(ProviderError::invalid_params(
+            "caveat value must be a list of accounts",
+        )),
+        None => Ok(None),
+    }
+}
+
+/// Chains requested by a `restrictNetworkSwitching` caveat.
+///
+/// Defaults to the chain currently used by the client.
+fn requested_chains(
+    ctx: &Context,
+    request: &Value,
+) -> ProviderResult<Vec<u64>> {
+    let chains = match caveat(request, RESTRICT_NETWORK_SWITCHING) {
+        Some(Value::Array(values)) => values
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .and_then(|s| s.strip_prefix("0x"))
+                    .and_then(|s| u64::from_str_radix(s, 16).ok())
+                    .ok_or_else(|| {
+                        ProviderError::invalid_params(format!(
+                            "invalid chain id {}",
+                            value
+                        ))
+                    })
+            })
+            .collect::<ProviderResult<Vec<u64>>>()?,
+        Some(_) => {
+            return Err(ProviderError::invalid_params(
+                "caveat value must be a list of chain ids",
+            ))
+        }
+        None => vec![chain_id(ctx)],
+    };
+
+    let registry = NETWORK_REGISTRY.read().unwrap();
+    for chain_id in &chains {
+        if registry.get(*chain_id).is_none() {
+            return Err(ProviderError::new(
+                UNRECOGNIZED_CHAIN,
+                format!("Unrecognized chain ID {:#x}.", chain_id),
+            ));
+        }
+    }
+    Ok(chains)
+}
+
+/// Ask the owner to grant permissions using
+/// `wallet_requestPermissions`.
+///
+/// Returns the granted permissions that were requested.
+pub async fn request_permissions(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let requested = requested(ctx, request)?;
+    // Check every caveat before asking the owner
+    let restrict = match requested.get(ETH_ACCOUNTS) {
+        Some(permission) => requested_accounts(permission)?,
+        None => None,
+    };
+    let chains = requested
+        .get(PERMITTED_CHAINS)
+        .map(|permission| requested_chains(ctx, permission))
+        .transpose()?;
+
+    if requested.contains_key(ETH_ACCOUNTS) {
+        select_accounts(ctx, restrict.as_deref()).await?;
+    }
+
+    if let Some(chains) = chains {
+        let names: Vec<String> = {
+            let registry = NETWORK_REGISTRY.read().unwrap();
+            chains
+                .iter()
+                .filter_map(|id| registry.get(*id))
+                .map(|network| network.name.clone())
+                .collect()
+        };
+        let summary =
+            format!("Allow the use of these networks: {}?", names.join(", "));
+        consent::confirm(ctx, Approval::new(request.method(), summary)).await?;
+        let mut user = USER_DATA.write().unwrap();
+        if user.owner_id().as_ref() != Some(&ctx.owner_id) {
+            return Err(ProviderError::unauthorized());
+        }
+        user.set_permitted_chains(&ctx.client_id, chains)?;
+    }
+
+    let granted: Vec<Permission> = permissions(ctx)
+        .into_iter()
+        .filter(|p| requested.contains_key(p.parent_capability))
+        .collect();
+    Ok(json!(granted))
+}
+
+/// Revoke permissions using `wallet_revokePermissions`.
+pub fn revoke_permissions(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (revoked,): (Map<String, Value>,) = request.params()?;
+    for name in revoked.keys() {
+        permission_scope(name)?;
+    }
+
+    let mut user = USER_DATA.write().unwrap();
+    if user.owner_id().as_ref() != Some(&ctx.owner_id) {
+        return Err(ProviderError::unauthorized());
+    }
+    if revoked.contains_key(ETH_ACCOUNTS) {
+        user.connect_accounts(&ctx.client_id, vec![])?;
+    }
+    if revoked.contains_key(PERMITTED_CHAINS) {
+        user.set_permitted_chains(&ctx.client_id, vec![])?;
+    }
+    Ok(Value::Null)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{INVALID_PARAMS, UNAUTHORIZED};
+
+    fn context(scope: &str) -> Context {
+        Context {
+            client_id: "test-client".to_string(),
+            owner_id: "owner".to_string(),
+            scope: scope.to_string(),
+            redirect_uri: "https://example.com/callback".parse().unwrap(),
+        }
+    }
+
+    fn request(permissions: Value) -> Request {
+        Request::from_value(json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": "wallet_requestPermissions",
+            "params": [permissions],
+        }))
+        .unwrap()
+    }
+
+    fn error_code<T: std::fmt::Debug>(result: ProviderResult<T>) -> i64 {
+        result.unwrap_err().code
+    }
+
+    #[test]
+    fn map_methods_to_scopes() {
+        assert_eq!(READ_SCOPE, required_scope("eth_blockNumber"));
+        assert_eq!(ACCOUNTS_SCOPE, required_scope("eth_requestAccounts"));
+        assert_eq!(SIGN_SCOPE, required_scope("eth_signTypedData_v4"));
+        assert_eq!(TRANSACT_SCOPE, required_scope("eth_sendTransaction"));
+        assert_eq!(NETWORK_SCOPE, required_scope("wallet_switchEthereumChain"));
+
+        assert!(requires_consent("eth_requestAccounts"));
+        assert!(requires_consent("wallet_requestPermissions"));
+        assert!(requires_consent("personal_sign"));
+        assert!(requires_consent("wallet_addEthereumChain"));
+        assert!(!requires_consent("wallet_getCallsStatus"));
+        assert!(!requires_consent("eth_accounts"));
+        assert!(!requires_consent("eth_call"));
+    }
+
+    #[test]
+    fn check_requested_permissions() {
+        let ctx = context("read accounts");
+        let requested =
+            requested(&ctx, &request(json!({ "eth_accounts": {} }))).unwrap();
+        assert!(requested.contains_key(ETH_ACCOUNTS));
+
+        let chains = request(json!({ PERMITTED_CHAINS: {} }));
+        assert_eq!(UNAUTHORIZED, error_code(requested(&ctx, &chains)));
+        assert!(requested(&context("read network"), &chains).is_ok());
+
+        let unknown = request(json!({ "eth_foo": {} }));
+        assert_eq!(INVALID_PARAMS, error_code(requested(&ctx, &unknown)));
+        assert_eq!(
+            INVALID_PARAMS,
+            error_code(requested(&ctx, &request(json!({}))))
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            error_code(revoke_permissions(&ctx, &unknown))
+        );
+    }
+
+    #[test]
+    fn parse_caveats() {
+        let account = "0x9406Cc6185a346906296840746125a0E44976454";
+        let permission = json!({
+            "caveats": [{
+                "type": RESTRICT_RETURNED_ACCOUNTS,
+                "value": [account],
+            }],
+        });
+        assert_eq!(
+            Some(vec![account.to_string()]),
+            requested_accounts(&permission).unwrap()
+        );
+        assert_eq!(None, requested_accounts(&json!({})).unwrap());
+        for value in [json!(["0x01"]), json!(account)] {
+            let permission = json!({
+                "caveats": [{
+                    "type": RESTRICT_RETURNED_ACCOUNTS,
+                    "value": value,
+                }],
+            });
+            assert_eq!(
+                INVALID_PARAMS,
+                error_code(requested_accounts(&permission))
+            );
+        }
+
+        let ctx = context("read network");
+        let chains = |value: Value| {
+            json!({
+                "caveats": [{
+                    "type": RESTRICT_NETWORK_SWITCHING,
+                    "value": value,
+                }],
+            })
+        };
+        assert_eq!(
+            vec![1],
+            requested_chains(&ctx, &chains(json!(["0x1"]))).unwrap()
+        );
+        assert_eq!(
+            INVALID_PARAMS,
+            error_code(requested_chains(&ctx, &chains(json!(["1"]))))
+        );
+        assert_eq!(
+            UNRECOGNIZED_CHAIN,
+            error_code(requested_chains(&ctx, &chains(json!(["0xffffffff"]))))
+        );
+    }
+}
@@ -0,0 +1,336 @@
+//! Transactions for Safe multisig accounts.
+//!
+//! A Safe transaction is signed as EIP-712 typed data by each
+//! owner; once enough owners have signed, the signatures are
+//! concatenated in ascending order of the owner addresses and
+//! passed to `execTransaction` on the Safe. Partially signed
+//! transactions are exchanged between co-signers as JSON.
+use anyhow::{anyhow, bail, Result};
+use ethers_core::{
+    abi::{self, Token as AbiToken},
+    types::{Address, Bytes, Signature, H256, U256},
+};
+use ethers_signers::LocalWallet;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::helpers::format_address;
+
+use super::{
+    typed_data::{TypedData, Version},
+    wallet::{parse_address, unlock},
+};
+
+/// Contract version assumed when none is given.
+const DEFAULT_VERSION: &str = "1.3.0";
+
+/// Selector for `execTransaction(address,uint256,bytes,uint8,uint256,
+/// uint256,uint256,address,address,bytes)`.
+const EXEC_TRANSACTION: [u8; 4] = [0x6a, 0x76, 0x12, 0x02];
+
+fn default_version() -> String {
+    DEFAULT_VERSION.to_string()
+}
+
+/// Transaction executed by a Safe.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct SafeTransaction {
+    pub to: Address,
+    pub value: U256,
+    pub data: Bytes,
+    /// Zero for a call, one for a delegate call.
+    pub operation: u8,
+    pub safe_tx_gas: U256,
+    pub base_gas: U256,
+    pub gas_price: U256,
+    pub gas_token: Address,
+    pub refund_receiver: Address,
+    pub nonce: U256,
+}
+
+/// Safe transaction with the owner signatures collected so far.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SignedSafeTransaction {
+    /// Address of the Safe.
+    pub safe: Address,
+    pub chain_id: u64,
+    /// Version of the Safe contract.
+    #[serde(default = "default_version")]
+    pub version: String,
+    pub transaction: SafeTransaction,
+    /// Map owner addresses to signatures; ordered by address as
+    /// required by the Safe contract.
+    #[serde(default)]
+    pub signatures: BTreeMap<Address, Bytes>,
+}
+
+impl SignedSafeTransaction {
+    /// Determine if the domain includes the chain identifier
+    /// which was added in version 1.3.0.
+    fn domain_has_chain_id(&self) -> Result<bool> {
+        let mut parts = self.version.split('.').map(|part| part.parse::<u32>());
+        let major = parts.next().transpose()?.unwrap_or_default();
+        let minor = parts.next().transpose()?.unwrap_or_default();
+        if major == 0 {
+            bail!("unsupported Safe version {}", self.version);
+        }
+        Ok((major, minor) >= (1, 3))
+    }
+
+    /// Typed data signed by the owners.
+    pub fn typed_data(&self) -> Result<Value> {
+        let tx = &self.transaction;
+        if tx.operation > 1 {
+            bail!("unknown operation {}", tx.operation);
+        }
+
+        let mut domain_type = vec![];
+        let mut domain = json!({ "verifyingContract": self.safe });
+        if self.domain_has_chain_id()? {
+            domain_type.push(json!({ "name": "chainId", "type": "uint256" }));
+            domain["chainId"] = json!(self.chain_id);
+        }
+        domain_type
+            .push(json!({ "name": "verifyingContract", "type": "address" }));
+
+        Ok(json!({
+            "types": {
+                "EIP712Domain": domain_type,
+                "SafeTx": [
+                    { "name": "to", "type": "address" },
+                    { "name": "value", "type": "uint256" },
+                    { "name": "data", "type": "bytes" },
+                    { "name": "operation", "type": "uint8" },
+                    { "name": "safeTxGas", "type": "uint256" },
+                    { "name": "baseGas", "type": "uint256" },
+                    { "name": "gasPrice", "type": "uint256" },
+                    { "name": "gasToken", "type": "address" },
+                    { "name": "refundReceiver", "type": "address" },
+                    { "name": "nonce", "type": "uint256" },
+                ],
+            },
+            "primaryType": "SafeTx",
+            "domain": domain,
+            "message": tx,
+        }))
+    }
+
+    /// The `safeTxHash` signed by the owners.
+    pub fn hash(&self) -> Result<H256> {
+        TypedData::from_value(self.typed_data()?)?.sign_hash(Version::V4)
+    }
+
+    /// Sign the transaction with the wallet of an owner.
+    pub fn sign(&mut self, owner: Address, wallet: &LocalWallet) -> Result<()> {
+        let hash = self.hash()?;
+        let signature = wallet.sign_hash(hash, false);
+        if signature.recover(hash)? != owner {
+            bail!("wallet does not belong to {}", format_address(owner));
+        }
+        self.signatures
+            .insert(owner, Bytes::from(signature.to_vec()));
+        Ok(())
+    }
+
+    /// Ensure every signature was made by the owner it is
+    /// stored for.
+    pub fn verify(&self) -> Result<()> {
+        let hash = self.hash()?;
+        for (owner, signature) in &self.signatures {
+            let signature = Signature::try_from(signature.as_ref())?;
+            if signature.recover(hash)? != *owner {
+                bail!("invalid signature for {}", format_address(*owner));
+            }
+        }
+        Ok(())
+    }
+
+    /// Add the signatures of co-signers for the same transaction.
+    pub fn merge(&mut self, other: SignedSafeTransaction) -> Result<()> {
+        if other.hash()? != self.hash()? {
+            bail!("cannot merge signatures for a different transaction");
+        }
+        other.verify()?;
+        self.signatures.extend(other.signatures);
+        Ok(())
+    }
+
+    /// Signatures concatenated in ascending order of the owners.
+    pub fn signatures(&self) -> Bytes {
+        let signatures: Vec<u8> = self
+            .signatures
+            .values()
+            .flat_map(|signature| signature.as_ref().to_vec())
+            .collect();
+        Bytes::from(signatures)
+    }
+
+    /// Call data for `execTransaction` on the Safe.
+    pub fn exec_transaction_data(&self) -> Bytes {
+        let tx = &self.transaction;
+        let mut data = EXEC_TRANSACTION.to_vec();
+        data.extend(abi::encode(&[
+            AbiToken::Address(tx.to),
+            AbiToken::Uint(tx.value),
+            AbiToken::Bytes(tx.data.as_ref().to_vec()),
+            AbiToken::Uint(U256::from(tx.operation)),
+            AbiToken::Uint(tx.safe_tx_gas),
+            AbiToken::Uint(tx.base_gas),
+            AbiToken::Uint(tx.gas_price),
+            AbiToken::Address(tx.gas_token),
+            AbiToken::Address(tx.refund_receiver),
+            AbiToken::Bytes(self.signatures().as_ref().to_vec()),
+        ]));
+        Bytes::from(data)
+    }
+}
+
+/// Import a Safe transaction shared by a co-signer.
+pub fn import_safe_transaction(json: &str) -> Result<SignedSafeTransaction> {
+    let tx: SignedSafeTransaction = serde_json::from_str(json)?;
+    tx.verify()?;
+    Ok(tx)
+}
+
+/// Export a Safe transaction to share with co-signers.
+pub fn export_safe_transaction(tx: &SignedSafeTransaction) -> Result<String> {
+    Ok(serde_json::to_string_pretty(tx)?)
+}
+
+/// Merge Safe transactions signed by different owners.
+pub fn merge_safe_transactions(
+    transactions: Vec<SignedSafeTransaction>,
+) -> Result<SignedSafeTransaction> {
+    let mut transactions = transactions.into_iter();
+    let mut merged = transactions
+        .next()
+        .ok_or_else(|| anyhow!("at least one transaction is required"))?;
+    merged.verify()?;
+    for tx in transactions {
+        merged.merge(tx)?;
+    }
+    Ok(merged)
+}
+
+/// Sign a Safe transaction with an owner account for the
+/// wallet UI.
+///
+/// The owner has already approved the request in the UI and
+/// supplied the passphrase to unlock the account.
+pub async fn sign_safe_transaction(
+    mut tx: SignedSafeTransaction,
+    owner: String,
+    passphrase: String,
+) -> Result<SignedSafeTransaction> {
+    let address = parse_address(&owner)?;
+    let wallet = unlock(&owner, passphrase).await?;
+    tx.sign(address, &wallet)?;
+    Ok(tx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::utils::keccak256;
+    use ethers_signers::Signer;
+
+    const SAFE_TX_TYPE: &str = "SafeTx(address to,uint256 value,bytes data,\
+        uint8 operation,uint256 safeTxGas,uint256 baseGas,uint256 gasPrice,\
+        address gasToken,address refundReceiver,uint256 nonce)";
+
+    fn safe_transaction() -> SignedSafeTransaction {
+        SignedSafeTransaction {
+            safe: "0x1c8b9B78e3085866521FE206fa4c1a67F49f153A"
+                .parse()
+                .unwrap(),
+            chain_id: 5,
+            version: default_version(),
+            transaction: SafeTransaction {
+                to: "0x2B5AD5c4795c026514f8317c7a215E218DcCD6cF"
+                    .parse()
+                    .unwrap(),
+                value: U256::exp10(16),
+                data: Bytes::from(vec![0xde, 0xad, 0xbe, 0xef]),
+                nonce: U256::from(7),
+                ..Default::default()
+            },
+            signatures: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn hash_and_sign_safe_transaction() -> Result<()> {
+        let mut tx = safe_transaction();
+
+        // Hash the transaction as the Safe contract does
+        let safe_tx = &tx.transaction;
+        let struct_hash = keccak256(abi::encode(&[
+            AbiToken::FixedBytes(keccak256(SAFE_TX_TYPE).to_vec()),
+            AbiToken::Address(safe_tx.to),
+            AbiToken::Uint(safe_tx.value),
+            AbiToken::FixedBytes(keccak256(&safe_tx.data).to_vec()),
+            AbiToken::Uint(U256::zero()),
+            AbiToken::Uint(U256::zero()),
+            AbiToken::Uint(U256::zero()),
+            AbiToken::Uint(U256::zero()),
+            AbiToken::Address(Address::zero()),
+            AbiToken::Address(Address::zero()),
+            AbiToken::Uint(safe_tx.nonce),
+        ]));
+        let domain_separator = keccak256(abi::encode(&[
+            AbiToken::FixedBytes(
+                keccak256(
+                    "EIP712Domain(uint256 chainId,address verifyingContract)",
+                )
+                .to_vec(),
+            ),
+            AbiToken::Uint(U256::from(tx.chain_id)),
+            AbiToken::Address(tx.safe),
+        ]));
+        let mut encoded = vec![0x19, 0x01];
+        encoded.extend_from_slice(&domain_separator);
+        encoded.extend_from_slice(&struct_hash);
+        assert_eq!(H256::from(keccak256(encoded)), tx.hash()?);
+
+        // Older Safes do not include the chain in the domain
+        let mut legacy = tx.clone();
+        legacy.version = "1.2.0".to_string();
+        assert_ne!(tx.hash()?, legacy.hash()?);
+
+        let first: LocalWallet =
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+                .parse()?;
+        let second: LocalWallet =
+            "0000000000000000000000000000000000000000000000000000000000000001"
+                .parse()?;
+        let mut cosigned = tx.clone();
+        tx.sign(first.address(), &first)?;
+        cosigned.sign(second.address(), &second)?;
+        assert!(tx.sign(second.address(), &first).is_err());
+
+        let exported = export_safe_transaction(&cosigned)?;
+        let merged = merge_safe_transactions(vec![
+            tx,
+            import_safe_transaction(&exported)?,
+        ])?;
+        assert_eq!(2, merged.signatures.len());
+
+        // Signatures are ordered by owner address
+        let owners: Vec<Address> = merged.signatures.keys().copied().collect();
+        assert!(owners[0] < owners[1]);
+        let blob = merged.signatures();
+        assert_eq!(130, blob.as_ref().len());
+        assert_eq!(
+            merged.signatures[&owners[0]].as_ref(),
+            &blob.as_ref()[..65]
+        );
+
+        let mut forged = merged.clone();
+        forged.transaction.nonce = U256::from(8);
+        assert!(forged.verify().is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,274 @@
+//! Send transactions through the upstream node.
+//!
+//! Missing fields are filled from the upstream node, the
+//! transaction is signed locally and then broadcast using
+//! `eth_sendRawTransaction`. Nonces are handed out by the nonce
+//! manager so concurrent transactions never collide. Transactions
+//! from smart accounts are sent as user operations instead.
+use ethers_core::types::{H256, U256, U64};
+use serde_json::{json, Value};
+
+use crate::helpers::format_address;
+use crate::upstream::Upstream;
+use crate::user::USER_DATA;
+
+use super::{
+    chain_id,
+    fees::suggest_fees,
+    nonce::{self, Reservation},
+    transaction::{approve_and_sign, TransactionKind, TransactionParams},
+    user_operation,
+    wallet::ensure_connected,
+    Context, ProviderError, ProviderResult, Request, CHAIN_DISCONNECTED,
+};
+
+/// Ensure the upstream node is on the chain the transaction is
+/// signed for so it is never broadcast to another chain.
+fn check_chain_id(expected: u64, reported: U64) -> ProviderResult<()> {
+    if reported != U64::from(expected) {
+        return Err(ProviderError::new(
+            CHAIN_DISCONNECTED,
+            format!(
+                "upstream node is on chain {} instead of chain {}",
+                reported, expected
+            ),
+        ));
+    }
+    Ok(())
+}
+
+/// Fill the nonce, gas limit and fees from the upstream node.
+///
+/// Fails when the node reports a different chain id than the
+/// chain it is configured for. When no nonce was given one is
+/// reserved for the sender; the reservation must be committed
+/// once the transaction has been broadcast.
+pub async fn fill_transaction(
+    upstream: &Upstream,
+    params: &mut TransactionParams,
+) -> ProviderResult<Option<Reservation>> {
+    let from = params
+        .from
+        .ok_or_else(|| ProviderError::invalid_params("from is required"))?;
+
+    let node_chain_id: U64 = upstream.call("eth_chainId", json!([])).await?;
+    check_chain_id(upstream.chain_id(), node_chain_id)?;
+    if params.chain_id.is_none() {
+        params.chain_id = Some(U64::from(upstream.chain_id()));
+    }
+
+    let reservation = match params.nonce {
+        Some(_) => None,
+        None => {
+            let reservation = nonce::reserve(upstream, from).await?;
+            params.nonce = Some(reservation.nonce());
+            Some(reservation)
+        }
+    };
+
+    if params.gas_price.is_none() {
+        fill_fees(upstream, params).await?;
+    }
+
+    if params.gas.is_none() {
+        let mut call = json!({
+            "from": from,
+            "to": params.to,
+            "value": params.value,
+            "data": params.call_data().map_err(ProviderError::invalid_params)?,
+        });
+        if let Some(authorization_list) = &params.authorization_list {
+            call["authorizationList"] = json!(authorization_list);
+        }
+        let gas: U256 = upstream.call("eth_estimateGas", json!([call])).await?;
+        params.gas = Some(gas);
+    }
+
+    Ok(reservation)
+}
+
+/// Fill the fee fields using the normal fee suggestion.
+///
+/// Chains that report a base fee use dynamic fees, otherwise the
+/// legacy gas price is used. Transactions that explicitly request
+/// a legacy or access list envelope always use the gas price.
+pub async fn fill_fees(
+    upstream: &Upstream,
+    params: &mut TransactionParams,
+) -> ProviderResult<()> {
+    let kind = params
+        .transaction_kind()
+        .map_err(ProviderError::invalid_params)?;
+    let gas_price_only = params.kind.is_some()
+        && matches!(
+            kind,
+            TransactionKind::Legacy | TransactionKind::AccessList
+        );
+
+    let fees = suggest_fees(upstream).await?;
+    let normal = fees.normal;
+
+    match fees.base_fee_per_gas {
+        Some(_) if !gas_price_only => {
+            let priority_fee = params
+                .max_priority_fee_per_gas
+                .unwrap_or(normal.max_priority_fee_per_gas);
+            // Keep the allowance for a rising base fee
+            let max_fee = params.max_fee_per_gas.unwrap_or_else(|| {
+                normal.max_fee_per_gas - normal.max_priority_fee_per_gas
+                    + priority_fee
+            });
+            params.max_priority_fee_per_gas = Some(priority_fee.min(max_fee));
+            params.max_fee_per_gas = Some(max_fee);
+        }
+        _ => {
+            if params.max_fee_per_gas.is_some()
+                || params.max_priority_fee_per_gas.is_some()
+            {
+                return Err(ProviderError::invalid_params(
+                    "transaction does not support dynamic fees",
+                ));
+            }
+            params.gas_price = Some(normal.max_fee_per_gas);
+        }
+    }
+
+    Ok(())
+}
+
+/// Send a transaction using `eth_sendTransaction`.
+///
+/// Returns the transaction hash.
+pub async fn send_transaction(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (mut params,): (TransactionParams,) = request.params()?;
+    let from = params
+        .from
+        .map(format_address)
+        .ok_or_else(|| ProviderError::invalid_params("from is required"))?;
+    ensure_connected(ctx, &from)?;
+
+    let smart_account = USER_DATA.read().unwrap().smart_account(&from);
+    if let Some(account) = smart_account {
+        return user_operation::send_transaction(
+            ctx,
+            request.method(),
+            &params,
+            account,
+        )
+        .await;
+    }
+
+    let chain_id = chain_id(ctx);
+    let upstream = Upstream::for_chain(chain_id)?;
+    let reservation = fill_transaction(&upstream, &mut params).await?;
+
+    let signed =
+        approve_and_sign(ctx, request.method(), &params, chain_id).await?;
+    let hash: H256 = upstream
+        .call("eth_sendRawTransaction", json!([signed.raw]))
+        .await?;
+    if let Some(reservation) = reservation {
+        reservation.commit(hash);
+    }
+    Ok(json!(hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{mock::MockServer, INVALID_PARAMS};
+
+    const FEE_HISTORY: &str = include_str!("fixtures/fee_history_mainnet.json");
+
+    /// Serve a node that reports a chain id and answers the calls
+    /// made to fill a transaction.
+    fn mock_node(chain_id: u64) -> Upstream {
+        MockServer::start(move |method, _| {
+            Ok(match method {
+                "eth_chainId" => json!(U64::from(chain_id)),
+                "eth_feeHistory" => serde_json::from_str(FEE_HISTORY).unwrap(),
+                "eth_estimateGas" => json!(U256::from(21_000)),
+                _ => Value::Null,
+            })
+        })
+        .upstream(1)
+    }
+
+    fn params() -> TransactionParams {
+        TransactionParams {
+            from: Some(
+                "0x9406Cc6185a346906296840746125a0E44976454"
+                    .parse()
+                    .unwrap(),
+            ),
+            to: Some(
+                "0x0000000000000000000000000000000000000001"
+                    .parse()
+                    .unwrap(),
+            ),
+            value: Some(U256::from(1)),
+            nonce: Some(U256::from(7)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn compare_chain_ids() {
+        assert!(check_chain_id(1, U64::from(1)).is_ok());
+        let error = check_chain_id(1, U64::from(5)).unwrap_err();
+        assert_eq!(CHAIN_DISCONNECTED, error.code);
+    }
+
+    #[tokio::test]
+    async fn fill_from_upstream_node() {
+        let upstream = mock_node(1);
+        let mut params = params();
+        let reservation =
+            fill_transaction(&upstream, &mut params).await.unwrap();
+        assert!(reservation.is_none());
+        assert_eq!(Some(U64::from(1)), params.chain_id);
+        assert_eq!(Some(U256::from(7)), params.nonce);
+        assert_eq!(Some(U256::from(21_000)), params.gas);
+        assert_eq!(
+            Some(U256::from(1_100_000_000u64)),
+            params.max_priority_fee_per_gas
+        );
+        assert_eq!(Some(U256::from(27_351_858_628u64)), params.max_fee_per_gas);
+        assert!(params.build(1).is_ok());
+    }
+
+    #[tokio::test]
+    async fn fill_gas_price_for_legacy_kinds() {
+        let upstream = mock_node(1);
+        for kind in [0u64, 1] {
+            let mut params = params();
+            params.kind = Some(U64::from(kind));
+            fill_transaction(&upstream, &mut params).await.unwrap();
+            assert_eq!(Some(U256::from(27_351_858_628u64)), params.gas_price);
+            assert_eq!(None, params.max_fee_per_gas);
+            assert_eq!(None, params.max_priority_fee_per_gas);
+            assert!(params.build(1).is_ok());
+        }
+
+        let mut params = params();
+        params.kind = Some(U64::from(0));
+        params.max_priority_fee_per_gas = Some(U256::exp10(9));
+        let error = fill_transaction(&upstream, &mut params).await.unwrap_err();
+        assert_eq!(INVALID_PARAMS, error.code);
+    }
+
+    #[tokio::test]
+    async fn reject_node_on_another_chain() {
+        let upstream = mock_node(5);
+        let mut params = params();
+        params.nonce = None;
+        let error = fill_transaction(&upstream, &mut params).await.unwrap_err();
+        assert_eq!(CHAIN_DISCONNECTED, error.code);
+        // Nothing is filled and no nonce is reserved
+        assert_eq!(None, params.chain_id);
+        assert_eq!(None, params.nonce);
+    }
+}
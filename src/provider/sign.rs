@@ -0,0 +1,293 @@
+//! EIP-191 signed data.
+//!
+//! Supports version `0x45` (personal messages), version `0x00`
+//! (data with an intended validator), version `0x01` (EIP-712
+//! typed data) and the legacy `eth_sign` method which signs a
+//! raw hash.
+use chrono::Utc;
+use ethers_core::{
+    types::{Address, H256},
+    utils::{hash_message, keccak256},
+};
+use serde_json::Value;
+
+use crate::user::{Settings, USER_DATA};
+
+use super::{
+    chain_id,
+    consent::{self, Approval},
+    siwe::{self, SiweMessage},
+    typed_data::{describe_text, TypedData, Version},
+    wallet::{ensure_connected, parse_address, unlock},
+    Context, ProviderError, ProviderResult, Request, CHAIN_DISCONNECTED,
+};
+
+/// Decode message data.
+///
+/// Data prefixed with `0x` is hex encoded, otherwise it is
+/// treated as UTF-8 text.
+pub fn decode_data(data: &str) -> ProviderResult<Vec<u8>> {
+    if let Some(hex_data) = data.strip_prefix("0x") {
+        hex::decode(hex_data).map_err(ProviderError::invalid_params)
+    } else {
+        Ok(data.as_bytes().to_vec())
+    }
+}
+
+/// Readable version of message data for the owner.
+fn display_data(data: &[u8]) -> String {
+    match std::str::from_utf8(data) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!("0x{}", hex::encode(data)),
+    }
+}
+
+fn is_address(value: &str) -> bool {
+    value.len() == 42 && parse_address(value).is_ok()
+}
+
+/// Data and address parameters for `personal_sign`.
+///
+/// Parameters are `[data, address]`, some clients send them
+/// the other way around so we swap them when the first parameter
+/// is an address and the second is not.
+fn personal_sign_params(params: &[String]) -> ProviderResult<(&str, &str)> {
+    let (data, address) = match params {
+        [data, address, ..] => (data.as_str(), address.as_str()),
+        _ => {
+            return Err(ProviderError::invalid_params(
+                "expected data and address",
+            ))
+        }
+    };
+    if is_address(data) && !is_address(address) {
+        Ok((address, data))
+    } else {
+        Ok((data, address))
+    }
+}
+
+/// Ensure the legacy `eth_sign` method is enabled in the settings.
+fn ensure_eth_sign_enabled(
+    settings: Option<&Settings>,
+    method: &str,
+) -> ProviderResult<()> {
+    if settings.map(|s| s.eth_sign).unwrap_or(false) {
+        Ok(())
+    } else {
+        Err(ProviderError::unsupported_method(method))
+    }
+}
+
+/// Hash for EIP-191 version `0x00` data with an intended validator.
+pub fn intended_validator_hash(validator: Address, data: &[u8]) -> H256 {
+    let mut message = Vec::with_capacity(22 + data.len());
+    message.extend_from_slice(&[0x19, 0x00]);
+    message.extend_from_slice(validator.as_bytes());
+    message.extend_from_slice(data);
+    H256::from(keccak256(message))
+}
+
+/// Check a sign-in message against the client.
+fn sign_in_warnings(ctx: &Context, text: &str, address: &str) -> Vec<String> {
+    match SiweMessage::parse(text) {
+        Ok(message) => {
+            message.check(&ctx.redirect_uri, address, chain_id(ctx), Utc::now())
+        }
+        Err(e) => vec![format!(
+            "The sign-in message is malformed ({}). This is likely a \
+             phishing attempt.",
+            e
+        )],
+    }
+}
+
+/// Ask the owner for approval and sign a hash with an account.
+async fn sign_hash(
+    ctx: &Context,
+    address: &str,
+    hash: H256,
+    approval: Approval,
+) -> ProviderResult<Value> {
+    let passphrase = consent::approve(ctx, approval.account(address)).await?;
+    let wallet = unlock(address, passphrase).await?;
+    let signature = wallet.sign_hash(hash, false);
+    Ok(Value::String(format!("0x{}", signature)))
+}
+
+/// Sign a message using `personal_sign`.
+pub async fn personal_sign(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let params: Vec<String> = request.params()?;
+    let (data, address) = personal_sign_params(&params)?;
+
+    parse_address(address)?;
+    ensure_connected(ctx, address)?;
+
+    let message = decode_data(data)?;
+    let mut approval = Approval::new(request.method(), display_data(&message))
+        .payload(Value::String(data.to_string()));
+    if let Ok(text) = std::str::from_utf8(&message) {
+        if siwe::is_sign_in(text) {
+            for warning in sign_in_warnings(ctx, text, address) {
+                approval = approval.warning(warning);
+            }
+        }
+    }
+    sign_hash(ctx, address, hash_message(&message), approval).await
+}
+
+/// Sign a hash using the legacy `eth_sign` method.
+///
+/// This is disabled unless enabled in the user settings as it
+/// can be used to sign transactions.
+pub async fn eth_sign(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    {
+        let user = USER_DATA.read().unwrap();
+        ensure_eth_sign_enabled(user.settings().ok(), request.method())?;
+    }
+
+    let (address, data): (String, String) = request.params()?;
+    parse_address(&address)?;
+    ensure_connected(ctx, &address)?;
+
+    let hash = decode_data(&data)?;
+    if hash.len() != 32 {
+        return Err(ProviderError::invalid_params(
+            "data must be a 32 byte hash",
+        ));
+    }
+
+    let approval = Approval::new(request.method(), data.clone())
+        .payload(Value::String(data))
+        .warning(
+            "Signing an arbitrary hash can authorize any action for this account, including transactions.",
+        );
+    sign_hash(ctx, &address, H256::from_slice(&hash), approval).await
+}
+
+/// Sign EIP-191 version `0x00` data for an intended validator.
+///
+/// Parameters are `[validator, data, address]`.
+pub async fn sign_intended_validator(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (validator, data, address): (String, String, String) =
+        request.params()?;
+    let validator_address = parse_address(&validator)?;
+    parse_address(&address)?;
+    ensure_connected(ctx, &address)?;
+
+    let data = decode_data(&data)?;
+    let hash = intended_validator_hash(validator_address, &data);
+    let summary =
+        format!("Validator: {}\n\n{}", validator, display_data(&data));
+    let approval =
+        Approval::new(request.method(), summary).payload(serde_json::json!({
+            "validator": validator,
+            "data": format!("0x{}", hex::encode(&data)),
+        }));
+    sign_hash(ctx, &address, hash, approval).await
+}
+
+/// Sign EIP-712 typed data using `eth_signTypedData_v3` or
+/// `eth_signTypedData_v4`.
+///
+/// Parameters are `[address, typedData]`.
+pub async fn sign_typed_data(
+    ctx: &Context,
+    request: &Request,
+    version: Version,
+) -> ProviderResult<Value> {
+    let (address, typed_data): (String, Value) = request.params()?;
+    parse_address(&address)?;
+    ensure_connected(ctx, &address)?;
+
+    let typed_data = TypedData::from_value(typed_data)
+        .map_err(ProviderError::invalid_params)?;
+    typed_data
+        .validate(version)
+        .map_err(ProviderError::invalid_params)?;
+
+    let chain_id = chain_id(ctx);
+    match typed_data
+        .chain_id()
+        .map_err(ProviderError::invalid_params)?
+    {
+        Some(domain_chain_id) if domain_chain_id != chain_id => {
+            return Err(ProviderError::new(
+                CHAIN_DISCONNECTED,
+                format!(
+                    "domain chain id {} does not match the active chain id {}",
+                    domain_chain_id, chain_id
+                ),
+            ));
+        }
+        _ => {}
+    }
+
+    let hash = typed_data
+        .sign_hash(version)
+        .map_err(ProviderError::invalid_params)?;
+    let description = typed_data.describe_message();
+    let approval = Approval::new(request.method(), describe_text(&description))
+        .payload(description);
+    sign_hash(ctx, &address, hash, approval).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{INVALID_PARAMS, UNSUPPORTED_METHOD};
+
+    const ADDRESS: &str = "0x2b5ad5c4795c026514f8317c7a215e218dccd6cf";
+
+    #[test]
+    fn detect_personal_sign_parameter_order() {
+        let params = vec!["0x68656c6c6f".to_string(), ADDRESS.to_string()];
+        assert_eq!(
+            ("0x68656c6c6f", ADDRESS),
+            personal_sign_params(&params).unwrap()
+        );
+
+        let swapped = vec![ADDRESS.to_string(), "0x68656c6c6f".to_string()];
+        assert_eq!(
+            ("0x68656c6c6f", ADDRESS),
+            personal_sign_params(&swapped).unwrap()
+        );
+
+        // A message that is itself an address keeps the order
+        let both = vec![ADDRESS.to_string(), ADDRESS.to_string()];
+        assert_eq!((ADDRESS, ADDRESS), personal_sign_params(&both).unwrap());
+
+        let error = personal_sign_params(&params[..1]).unwrap_err();
+        assert_eq!(INVALID_PARAMS, error.code);
+    }
+
+    #[test]
+    fn eth_sign_requires_setting() {
+        let error = ensure_eth_sign_enabled(None, "eth_sign").unwrap_err();
+        assert_eq!(UNSUPPORTED_METHOD, error.code);
+
+        let mut settings = Settings::default();
+        assert!(ensure_eth_sign_enabled(Some(&settings), "eth_sign").is_err());
+        settings.eth_sign = true;
+        assert!(ensure_eth_sign_enabled(Some(&settings), "eth_sign").is_ok());
+    }
+
+    #[test]
+    fn hash_intended_validator_data() {
+        let validator = Address::repeat_byte(0x11);
+        let hash = intended_validator_hash(validator, b"hello");
+        assert_eq!(
+            "0x1efa12191222b5b77d265ea71874ca96f256435e0d9340fc2bc2fc8710a8cc61",
+            format!("{:#x}", hash)
+        );
+    }
+}
@@ -0,0 +1,309 @@
+//! Sign-In With Ethereum messages (EIP-4361).
+//!
+//! A `personal_sign` message that looks like a sign-in request
+//! is parsed and checked against the redirect URI registered for
+//! the OAuth client; anything that does not match is shown to the
+//! owner as a warning because it is likely a phishing attempt.
+use anyhow::{anyhow, bail, Result};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use url::Url;
+
+const HEADER_SUFFIX: &str = " wants you to sign in with your Ethereum account:";
+
+/// Shortest nonce accepted by the specification.
+const MIN_NONCE_LENGTH: usize = 8;
+
+/// Allowed clock skew for the issued at time.
+const MAX_CLOCK_SKEW_MINUTES: i64 = 5;
+
+/// Parsed sign-in message.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SiweMessage {
+    pub scheme: Option<String>,
+    pub domain: String,
+    pub address: String,
+    pub statement: Option<String>,
+    pub uri: String,
+    pub version: String,
+    pub chain_id: u64,
+    pub nonce: String,
+    pub issued_at: DateTime<FixedOffset>,
+    pub expiration_time: Option<DateTime<FixedOffset>>,
+    pub not_before: Option<DateTime<FixedOffset>>,
+    pub request_id: Option<String>,
+    pub resources: Vec<String>,
+}
+
+/// Determine if a message is a sign-in request.
+pub fn is_sign_in(message: &str) -> bool {
+    message
+        .lines()
+        .next()
+        .map(|line| line.ends_with(HEADER_SUFFIX))
+        .unwrap_or(false)
+}
+
+fn parse_time(field: &str, value: &str) -> Result<DateTime<FixedOffset>> {
+    DateTime::parse_from_rfc3339(value)
+        .map_err(|e| anyhow!("{} is not a valid timestamp: {}", field, e))
+}
+
+impl SiweMessage {
+    /// Parse a sign-in message.
+    pub fn parse(message: &str) -> Result<Self> {
+        let mut lines = message.lines().peekable();
+
+        let header = lines.next().unwrap_or_default();
+        let origin = header
+            .strip_suffix(HEADER_SUFFIX)
+            .ok_or_else(|| anyhow!("message is not a sign-in request"))?;
+        let (scheme, domain) = match origin.split_once("://") {
+            Some((scheme, domain)) => (Some(scheme.to_string()), domain),
+            None => (None, origin),
+        };
+        if domain.is_empty() || domain.contains(char::is_whitespace) {
+            bail!("domain is invalid");
+        }
+
+        let address = lines
+            .next()
+            .ok_or_else(|| anyhow!("address is required"))?
+            .to_string();
+
+        // Optional statement between empty lines
+        let mut statement = None;
+        while let Some(line) = lines.peek() {
+            if line.starts_with("URI: ") {
+                break;
+            }
+            if !line.is_empty() {
+                if statement.is_some() {
+                    bail!("statement must be a single line");
+                }
+                statement = Some(line.to_string());
+            }
+            lines.next();
+        }
+
+        let mut field =
+            |name: &str, required: bool| -> Result<Option<String>> {
+                let prefix = format!("{}: ", name);
+                match lines.peek().and_then(|line| line.strip_prefix(&prefix)) {
+                    Some(value) => {
+                        let value = value.to_string();
+                        lines.next();
+                        Ok(Some(value))
+                    }
+                    None if required => bail!("{} is required", name),
+                    None => Ok(None),
+                }
+            };
+
+        let uri = field("URI", true)?.unwrap_or_default();
+        let version = field("Version", true)?.unwrap_or_default();
+        let chain_id = field("Chain ID", true)?
+            .unwrap_or_default()
+            .parse::<u64>()
+            .map_err(|e| anyhow!("Chain ID is invalid: {}", e))?;
+        let nonce = field("Nonce", true)?.unwrap_or_default();
+        let issued_at =
+            parse_time("Issued At", &field("Issued At", true)?.unwrap())?;
+        let expiration_time = field("Expiration Time", false)?
+            .map(|value| parse_time("Expiration Time", &value))
+            .transpose()?;
+        let not_before = field("Not Before", false)?
+            .map(|value| parse_time("Not Before", &value))
+            .transpose()?;
+        let request_id = field("Request ID", false)?;
+
+        let mut resources = Vec::new();
+        if lines.peek() == Some(&"Resources:") {
+            lines.next();
+            while let Some(resource) =
+                lines.peek().and_then(|line| line.strip_prefix("- "))
+            {
+                resources.push(resource.to_string());
+                lines.next();
+            }
+        }
+        if let Some(line) = lines.find(|line| !line.is_empty()) {
+            bail!("unexpected line {:?}", line);
+        }
+
+        Ok(Self {
+            scheme,
+            domain: domain.to_string(),
+            address,
+            statement,
+            uri,
+            version,
+            chain_id,
+            nonce,
+            issued_at,
+            expiration_time,
+            not_before,
+            request_id,
+            resources,
+        })
+    }
+
+    /// Check the message for a client.
+    ///
+    /// Returns warnings for every value that does not match the
+    /// redirect URI of the client, the signing account or the
+    /// client chain and for times that are not currently valid.
+    pub fn check(
+        &self,
+        redirect_uri: &Url,
+        address: &str,
+        chain_id: u64,
+        now: DateTime<Utc>,
+    ) -> Vec<String> {
+        let mut warnings = Vec::new();
+
+        let mut authority =
+            redirect_uri.host_str().unwrap_or_default().to_string();
+        if let Some(port) = redirect_uri.port() {
+            authority.push_str(&format!(":{}", port));
+        }
+        if !self.domain.eq_ignore_ascii_case(&authority) {
+            warnings.push(format!(
+                "The message asks you to sign in to {} but the request \
+                 comes from {}. This is likely a phishing attempt.",
+                self.domain, authority
+            ));
+        }
+        if let Some(scheme) = &self.scheme {
+            if scheme != redirect_uri.scheme() {
+                warnings.push(format!(
+                    "The message uses the {} scheme but the client uses {}.",
+                    scheme,
+                    redirect_uri.scheme()
+                ));
+            }
+        }
+
+        match self.uri.parse::<Url>() {
+            Ok(uri) if uri.origin() == redirect_uri.origin() => {}
+            Ok(uri) => warnings.push(format!(
+                "The message URI {} does not belong to {}. This is likely \
+                 a phishing attempt.",
+                uri,
+                redirect_uri.origin().ascii_serialization()
+            )),
+            Err(_) => warnings
+                .push(format!("The message URI {} is invalid.", self.uri)),
+        }
+
+        if !self.address.eq_ignore_ascii_case(address) {
+            warnings.push(format!(
+                "The message is for the account {} but will be signed \
+                 by {}.",
+                self.address, address
+            ));
+        }
+        if self.version != "1" {
+            warnings.push(format!(
+                "The message version {} is not supported.",
+                self.version
+            ));
+        }
+        if self.chain_id != chain_id {
+            warnings.push(format!(
+                "The message is for chain {} but the client is using \
+                 chain {}.",
+                self.chain_id, chain_id
+            ));
+        }
+        let valid_nonce = self.nonce.len() >= MIN_NONCE_LENGTH
+            && self.nonce.chars().all(|c| c.is_ascii_alphanumeric());
+        if !valid_nonce {
+            warnings.push(format!(
+                "The message nonce must be at least {} letters or digits.",
+                MIN_NONCE_LENGTH
+            ));
+        }
+
+        let skew = Duration::minutes(MAX_CLOCK_SKEW_MINUTES);
+        if self.issued_at > now + skew {
+            warnings.push("The message was issued in the future.".to_string());
+        }
+        if let Some(expiration_time) = self.expiration_time {
+            if expiration_time <= now {
+                warnings.push("The message has expired.".to_string());
+            } else if expiration_time <= self.issued_at {
+                warnings.push(
+                    "The message expires before it was issued.".to_string(),
+                );
+            }
+        }
+        if let Some(not_before) = self.not_before {
+            if not_before > now {
+                warnings.push(format!(
+                    "The message is not valid until {}.",
+                    not_before
+                ));
+            }
+        }
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDRESS: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+
+    fn message(domain: &str, uri: &str) -> String {
+        format!(
+            "{} wants you to sign in with your Ethereum account:\n\
+             {}\n\
+             \n\
+             I accept the Terms of Service\n\
+             \n\
+             URI: {}\n\
+             Version: 1\n\
+             Chain ID: 1\n\
+             Nonce: 32891756\n\
+             Issued At: 2022-02-01T16:25:24Z\n\
+             Expiration Time: 2022-02-02T16:25:24Z\n\
+             Resources:\n\
+             - ipfs://bafybeiemxf5abjwjbikoz4mc3a3dla6ual3jsgpdr4cjr3oz3evfyavhwq/\n\
+             - https://example.com/my-web2-claim.json",
+            domain, ADDRESS, uri
+        )
+    }
+
+    #[test]
+    fn check_sign_in_message() -> Result<()> {
+        let redirect_uri: Url = "http://localhost:7778/".parse()?;
+        let now = DateTime::parse_from_rfc3339("2022-02-01T17:00:00Z")?
+            .with_timezone(&Utc);
+
+        let text = message("localhost:7778", "http://localhost:7778/login");
+        assert!(is_sign_in(&text));
+        let siwe = SiweMessage::parse(&text)?;
+        assert_eq!(
+            Some("I accept the Terms of Service"),
+            siwe.statement.as_deref()
+        );
+        assert_eq!(2, siwe.resources.len());
+        assert!(siwe.check(&redirect_uri, ADDRESS, 1, now).is_empty());
+
+        let text = message("example.com", "https://example.com/login");
+        let siwe = SiweMessage::parse(&text)?;
+        let warnings = siwe.check(&redirect_uri, ADDRESS, 5, now);
+        assert_eq!(3, warnings.len());
+
+        let later = now + Duration::days(2);
+        let text = message("localhost:7778", "http://localhost:7778/login");
+        let siwe = SiweMessage::parse(&text)?;
+        assert_eq!(1, siwe.check(&redirect_uri, ADDRESS, 1, later).len());
+
+        assert!(!is_sign_in("Hello world"));
+        assert!(SiweMessage::parse(&text.replace("Nonce: ", "")).is_err());
+        Ok(())
+    }
+}
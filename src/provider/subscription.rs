@@ -0,0 +1,609 @@
+//! Subscriptions to chain events for persistent connections.
+//!
+//! When an upstream endpoint has a WebSocket URL the subscription
+//! is relayed from the node. Otherwise, or when the connection is
+//! lost, events are collected over HTTP using filters
+//! (`eth_newBlockFilter`, `eth_newFilter` and
+//! `eth_newPendingTransactionFilter`); when a node does not
+//! support filters the chain is polled instead.
+use ethers_core::types::U64;
+use serde_json::{json, Map, Value};
+use std::collections::HashSet;
+use std::time::Duration;
+use tokio::sync::oneshot;
+
+use crate::upstream::{socket::UpstreamSubscription, Upstream};
+
+use super::{
+    chain_id, Context, ProviderError, ProviderResult, Request,
+    CHAIN_DISCONNECTED,
+};
+
+/// Interval between requests to the upstream node.
+const POLL_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Maximum number of blocks to catch up on when polling.
+const MAX_CATCH_UP: u64 = 16;
+
+/// Events a client can subscribe to.
+#[derive(Debug, Clone)]
+enum SubscriptionKind {
+    /// Headers of new blocks.
+    NewHeads,
+    /// Logs matching a filter.
+    Logs(Value),
+    /// Hashes of transactions added to the pending state.
+    NewPendingTransactions,
+}
+
+impl SubscriptionKind {
+    /// Parse the parameters of an `eth_subscribe` request.
+    fn from_params(params: &[Value]) -> ProviderResult<Self> {
+        let name = params.first().and_then(Value::as_str).ok_or_else(|| {
+            ProviderError::invalid_params("subscription name is required")
+        })?;
+        match (name, params.get(1)) {
+            ("newHeads", None) => Ok(Self::NewHeads),
+            ("newPendingTransactions", None) => {
+                Ok(Self::NewPendingTransactions)
+            }
+            ("logs", None) => Ok(Self::Logs(json!({}))),
+            ("logs", Some(Value::Object(filter))) => {
+                Ok(Self::Logs(log_filter(filter)))
+            }
+            ("logs", Some(_)) => Err(ProviderError::invalid_params(
+                "logs filter must be an object",
+            )),
+            (name, _) => Err(ProviderError::invalid_params(format!(
+                "unsupported subscription {}",
+                name
+            ))),
+        }
+    }
+
+    /// Parameters of the `eth_subscribe` request for the node.
+    fn params(&self) -> Value {
+        match self {
+            Self::NewHeads => json!(["newHeads"]),
+            Self::Logs(filter) => json!(["logs", filter]),
+            Self::NewPendingTransactions => json!(["newPendingTransactions"]),
+        }
+    }
+}
+
+/// Keep the address and topics of a logs filter; the block
+/// range is managed by the subscription.
+fn log_filter(filter: &Map<String, Value>) -> Value {
+    let filter: Map<String, Value> = filter
+        .iter()
+        .filter(|(key, _)| matches!(key.as_str(), "address" | "topics"))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    Value::Object(filter)
+}
+
+/// Remove the transactions from a block so that it only
+/// contains the header fields.
+fn block_header(mut block: Value) -> Value {
+    if let Value::Object(map) = &mut block {
+        map.remove("transactions");
+    }
+    block
+}
+
+/// Subscription created by `eth_subscribe`.
+pub struct Subscription {
+    id: String,
+    kind: SubscriptionKind,
+    upstream: Upstream,
+    /// Identifier of the upstream filter.
+    filter: Option<String>,
+    /// Whether the upstream node supports filters.
+    use_filters: bool,
+    /// Last block seen when polling.
+    last_block: Option<u64>,
+    /// Pending transactions seen when polling.
+    pending: HashSet<String>,
+}
+
+impl Subscription {
+    /// Create a subscription from an `eth_subscribe` request.
+    pub fn new(ctx: &Context, request: &Request) -> ProviderResult<Self> {
+        let params: Vec<Value> = request.params()?;
+        let kind = SubscriptionKind::from_params(&params)?;
+        let upstream = Upstream::for_chain(chain_id(ctx))?;
+        Ok(Self::with_upstream(kind, upstream))
+    }
+
+    fn with_upstream(kind: SubscriptionKind, upstream: Upstream) -> Self {
+        Self {
+            id: format!("0x{}", hex::encode(rand::random::<[u8; 16]>())),
+            kind,
+            upstream,
+            filter: None,
+            use_filters: true,
+            last_block: None,
+            pending: HashSet::new(),
+        }
+    }
+
+    /// The subscription identifier.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// Relay events until the subscription is cancelled or
+    /// `notify` returns `false`.
+    ///
+    /// Events are relayed from a WebSocket endpoint when possible
+    /// and polled otherwise. The upstream subscription or filter
+    /// is removed before returning.
+    pub async fn run<F>(mut self, mut cancel: oneshot::Receiver<()>, notify: F)
+    where
+        F: Fn(Value) -> bool,
+    {
+        if let Some(mut relay) = self.relay().await {
+            let finished = loop {
+                tokio::select! {
+                    _ = &mut cancel => break true,
+                    event = relay.next() => match event {
+                        Some(event) => {
+                            if !notify(event) {
+                                break true;
+                            }
+                        }
+                        None => break false,
+                    },
+                }
+            };
+            relay.unsubscribe().await;
+            if finished {
+                return;
+            }
+            log::warn!("subscription {} lost its relay, polling", self.id);
+        }
+
+        let mut interval = tokio::time::interval(POLL_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = &mut cancel => break,
+                _ = interval.tick() => {
+                    match self.poll().await {
+                        Ok(events) => {
+                            if !events.into_iter().all(&notify) {
+                                break;
+                            }
+                        }
+                        Err(e) => {
+                            log::warn!("subscription {} failed: {}", self.id, e)
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(filter) = self.filter.take() {
+            let _ = self
+                .upstream
+                .call::<bool>("eth_uninstallFilter", json!([filter]))
+                .await;
+        }
+    }
+
+    /// Subscribe on the first WebSocket endpoint that accepts the
+    /// subscription.
+    async fn relay(&self) -> Option<UpstreamSubscription> {
+        for url in self.upstream.ws_urls() {
+            match UpstreamSubscription::subscribe(&url, self.kind.params())
+                .await
+            {
+                Ok(relay) => return Some(relay),
+                Err(e) => {
+                    log::warn!("could not relay subscription: {}", e)
+                }
+            }
+        }
+        None
+    }
+
+    /// Collect the events since the last poll.
+    async fn poll(&mut self) -> ProviderResult<Vec<Value>> {
+        if self.use_filters {
+            if let Some(events) = self.poll_filter().await? {
+                return Ok(events);
+            }
+            self.use_filters = false;
+        }
+        self.poll_chain().await
+    }
+
+    /// Collect the events from the upstream filter.
+    ///
+    /// Returns `None` when the upstream node does not support
+    /// filters.
+    async fn poll_filter(&mut self) -> ProviderResult<Option<Vec<Value>>> {
+        let filter = match &self.filter {
+            Some(filter) => filter.clone(),
+            None => {
+                let result: ProviderResult<String> = match &self.kind {
+                    SubscriptionKind::NewHeads => {
+                        self.upstream
+                            .call("eth_newBlockFilter", json!([]))
+                            .await
+                    }
+                    SubscriptionKind::Logs(filter) => {
+                        self.upstream
+                            .call("eth_newFilter", json!([filter]))
+                            .await
+                    }
+                    SubscriptionKind::NewPendingTransactions => {
+                        self.upstream
+                            .call("eth_newPendingTransactionFilter", json!([]))
+                            .await
+                    }
+                };
+                match result {
+                    Ok(filter) => {
+                        self.filter = Some(filter.clone());
+                        filter
+                    }
+                    Err(e) if e.code == CHAIN_DISCONNECTED => return Err(e),
+                    Err(e) => {
+                        log::debug!("filters not supported, polling: {}", e);
+                        return Ok(None);
+                    }
+                }
+            }
+        };
+
+        let changes: Vec<Value> = match self
+            .upstream
+            .call("eth_getFilterChanges", json!([filter]))
+            .await
+        {
+            Ok(changes) => changes,
+            Err(e) if e.code == CHAIN_DISCONNECTED => return Err(e),
+            Err(_) => {
+                // Nodes remove filters that are not polled in time
+                // so install a new filter on the next poll
+                self.filter = None;
+                return Ok(Some(vec![]));
+            }
+        };
+
+        match self.kind {
+            SubscriptionKind::NewHeads => {
+                let mut headers = Vec::with_capacity(changes.len());
+                for hash in changes {
+                    let block: Option<Value> = self
+                        .upstream
+                        .call("eth_getBlockByHash", json!([hash, false]))
+                        .await?;
+                    headers.extend(block.map(block_header));
+                }
+                Ok(Some(headers))
+            }
+            _ => Ok(Some(changes)),
+        }
+    }
+
+    /// Collect the events by polling the chain.
+    async fn poll_chain(&mut self) -> ProviderResult<Vec<Value>> {
+        if let SubscriptionKind::NewPendingTransactions = self.kind {
+            let block: Option<Value> = self
+                .upstream
+                .call("eth_getBlockByNumber", json!(["pending", false]))
+                .await?;
+            let hashes: HashSet<String> = block
+                .as_ref()
+                .and_then(|block| block.get("transactions"))
+                .and_then(Value::as_array)
+                .map(|hashes| {
+                    hashes
+                        .iter()
+                        .filter_map(Value::as_str)
+                        .map(String::from)
+                        .collect()
+                })
+                .unwrap_or_default();
+            let events = hashes
+                .difference(&self.pending)
+                .map(|hash| json!(hash))
+                .collect();
+            self.pending = hashes;
+            return Ok(events);
+        }
+
+        let latest: U64 =
+            self.upstream.call("eth_blockNumber", json!([])).await?;
+        let latest = latest.as_u64();
+        let from = match self.last_block {
+            Some(last) if latest <= last => return Ok(vec![]),
+            Some(last) => {
+                (last + 1).max(latest.saturating_sub(MAX_CATCH_UP - 1))
+            }
+            None => {
+                // Only report events after the subscription was created
+                self.last_block = Some(latest);
+                return Ok(vec![]);
+            }
+        };
+        self.last_block = Some(latest);
+
+        match &self.kind {
+            SubscriptionKind::Logs(filter) => {
+                let mut filter = filter.clone();
+                filter["fromBlock"] = json!(U64::from(from));
+                filter["toBlock"] = json!(U64::from(latest));
+                self.upstream.call("eth_getLogs", json!([filter])).await
+            }
+            _ => {
+                let mut headers = Vec::new();
+                for number in from..=latest {
+                    let block: Option<Value> = self
+                        .upstream
+                        .call(
+                            "eth_getBlockByNumber",
+                            json!([U64::from(number), false]),
+                        )
+                        .await?;
+                    headers.extend(block.map(block_header));
+                }
+                Ok(headers)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::{mock::MockServer, METHOD_NOT_FOUND};
+    use crate::upstream::Endpoint;
+    use futures::{SinkExt, StreamExt};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::{Arc, Mutex};
+    use tokio_tungstenite::tungstenite::Message;
+    use url::Url;
+
+    fn block(number: u64) -> Value {
+        json!({
+            "number": U64::from(number),
+            "hash": format!("0x{:064x}", number),
+            "transactions": [format!("0x{:064x}", number + 100)],
+        })
+    }
+
+    fn log(number: u64) -> Value {
+        json!({
+            "address": "0x0000000000000000000000000000000000000001",
+            "blockNumber": U64::from(number),
+            "topics": [],
+            "data": "0x",
+        })
+    }
+
+    fn header(number: u64) -> Value {
+        block_header(block(number))
+    }
+
+    /// Node without filter support whose chain advances by two
+    /// blocks on every call to `eth_blockNumber`.
+    fn polled_node() -> MockServer {
+        let latest = AtomicU64::new(10);
+        MockServer::start(move |method, params| match method {
+            "eth_blockNumber" => {
+                Ok(json!(U64::from(latest.fetch_add(2, Ordering::SeqCst))))
+            }
+            "eth_getBlockByNumber" => {
+                let number: U64 =
+                    serde_json::from_value(params[0].clone()).unwrap();
+                Ok(block(number.as_u64()))
+            }
+            "eth_getLogs" => Ok(json!([log(11), log(12)])),
+            _ => Err(ProviderError::new(METHOD_NOT_FOUND, "not found")),
+        })
+    }
+
+    #[test]
+    fn parse_subscription_params() {
+        let kind = SubscriptionKind::from_params(&[
+            json!("logs"),
+            json!({ "address": "0x01", "topics": [], "fromBlock": "0x1" }),
+        ])
+        .unwrap();
+        assert_eq!(
+            json!(["logs", { "address": "0x01", "topics": [] }]),
+            kind.params()
+        );
+        assert_eq!(
+            json!(["newHeads"]),
+            SubscriptionKind::from_params(&[json!("newHeads")])
+                .unwrap()
+                .params()
+        );
+        assert!(SubscriptionKind::from_params(&[]).is_err());
+        assert!(
+            SubscriptionKind::from_params(&[json!("logs"), json!([])]).is_err()
+        );
+        assert!(SubscriptionKind::from_params(&[json!("syncing")]).is_err());
+    }
+
+    #[tokio::test]
+    async fn relay_filter_changes() {
+        let server = MockServer::start(|method, params| {
+            Ok(match method {
+                "eth_newBlockFilter" => json!("0xb1"),
+                "eth_newFilter" => json!("0xf1"),
+                "eth_getFilterChanges" if params[0] == "0xb1" => {
+                    json!([format!("0x{:064x}", 11)])
+                }
+                "eth_getFilterChanges" => json!([log(11)]),
+                "eth_getBlockByHash" => block(11),
+                _ => Value::Null,
+            })
+        });
+
+        let mut heads = Subscription::with_upstream(
+            SubscriptionKind::NewHeads,
+            server.upstream(1),
+        );
+        assert_eq!(vec![header(11)], heads.poll().await.unwrap());
+        assert!(header(11).get("transactions").is_none());
+        assert_eq!(Some("0xb1".to_string()), heads.filter);
+
+        let filter = json!({ "topics": [] });
+        let mut logs = Subscription::with_upstream(
+            SubscriptionKind::Logs(filter.clone()),
+            server.upstream(1),
+        );
+        assert_eq!(vec![log(11)], logs.poll().await.unwrap());
+        assert_eq!(Some("0xf1".to_string()), logs.filter);
+
+        let new_filter = server
+            .requests()
+            .into_iter()
+            .find(|request| request["method"] == "eth_newFilter")
+            .unwrap();
+        assert_eq!(json!([filter]), new_filter["params"]);
+    }
+
+    #[tokio::test]
+    async fn poll_without_filters() {
+        let server = polled_node();
+        let mut heads = Subscription::with_upstream(
+            SubscriptionKind::NewHeads,
+            server.upstream(1),
+        );
+        // The first poll only records the latest block
+        assert!(heads.poll().await.unwrap().is_empty());
+        assert!(!heads.use_filters);
+        assert_eq!(vec![header(11), header(12)], heads.poll().await.unwrap());
+
+        let mut logs = Subscription::with_upstream(
+            SubscriptionKind::Logs(json!({})),
+            server.upstream(1),
+        );
+        assert!(logs.poll().await.unwrap().is_empty());
+        assert_eq!(vec![log(11), log(12)], logs.poll().await.unwrap());
+
+        let get_logs = server
+            .requests()
+            .into_iter()
+            .find(|request| request["method"] == "eth_getLogs")
+            .unwrap();
+        let from: U64 =
+            serde_json::from_value(get_logs["params"][0]["fromBlock"].clone())
+                .unwrap();
+        let to: U64 =
+            serde_json::from_value(get_logs["params"][0]["toBlock"].clone())
+                .unwrap();
+        assert_eq!(to.as_u64() - 1, from.as_u64());
+    }
+
+    #[tokio::test]
+    async fn uninstall_filter_on_unsubscribe() {
+        let server = MockServer::start(|method, _| {
+            Ok(match method {
+                "eth_newPendingTransactionFilter" => json!("0xp1"),
+                "eth_getFilterChanges" => json!([]),
+                "eth_uninstallFilter" => json!(true),
+                _ => Value::Null,
+            })
+        });
+        let subscription = Subscription::with_upstream(
+            SubscriptionKind::NewPendingTransactions,
+            server.upstream(1),
+        );
+        let (cancel, cancelled) = oneshot::channel();
+        let task = tokio::spawn(subscription.run(cancelled, |_| true));
+        while !server
+            .methods()
+            .contains(&"eth_getFilterChanges".to_string())
+        {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        drop(cancel);
+        task.await.unwrap();
+
+        let uninstall = server.requests().pop().unwrap();
+        assert_eq!("eth_uninstallFilter", uninstall["method"]);
+        assert_eq!(json!(["0xp1"]), uninstall["params"]);
+    }
+
+    #[tokio::test]
+    async fn relay_from_websocket() {
+        let listener =
+            tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let ws_url: Url = format!("ws://{}", listener.local_addr().unwrap())
+            .parse()
+            .unwrap();
+        let received = Arc::new(Mutex::new(Vec::new()));
+        let requests = Arc::clone(&received);
+        let node = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket =
+                tokio_tungstenite::accept_async(stream).await.unwrap();
+            while let Some(Ok(Message::Text(text))) = socket.next().await {
+                let request: Value = serde_json::from_str(&text).unwrap();
+                requests.lock().unwrap().push(request.clone());
+                if request["method"] != "eth_subscribe" {
+                    continue;
+                }
+                let notification = |id: &str, result: Value| {
+                    json!({
+                        "jsonrpc": "2.0",
+                        "method": "eth_subscription",
+                        "params": { "subscription": id, "result": result },
+                    })
+                };
+                let replies = [
+                    json!({
+                        "jsonrpc": "2.0",
+                        "id": request["id"],
+                        "result": "0xs1",
+                    }),
+                    notification("0xs0", header(1)),
+                    notification("0xs1", header(2)),
+                ];
+                for reply in replies {
+                    socket
+                        .send(Message::Text(reply.to_string()))
+                        .await
+                        .unwrap();
+                }
+            }
+        });
+
+        let server = polled_node();
+        let mut endpoint = Endpoint::new(server.url());
+        endpoint.ws_url = Some(ws_url);
+        let subscription = Subscription::with_upstream(
+            SubscriptionKind::NewHeads,
+            Upstream::new(1, vec![endpoint]),
+        );
+        let (_cancel, cancelled) = oneshot::channel();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let notified = Arc::clone(&events);
+        subscription
+            .run(cancelled, move |event| {
+                notified.lock().unwrap().push(event);
+                false
+            })
+            .await;
+        node.await.unwrap();
+
+        // Only events for the subscription are relayed and the
+        // node is not polled
+        assert_eq!(vec![header(2)], *events.lock().unwrap());
+        assert!(server.requests().is_empty());
+        let methods: Vec<Value> = received
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|request| request["method"].clone())
+            .collect();
+        assert_eq!(json!(["eth_subscribe", "eth_unsubscribe"]), json!(methods));
+        assert_eq!(json!(["newHeads"]), received.lock().unwrap()[0]["params"]);
+    }
+}
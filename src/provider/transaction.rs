@@ -0,0 +1,630 @@
+//! Build and sign transactions.
+//!
+//! Legacy, EIP-2930 (access list) and EIP-1559 (dynamic fee)
+//! transactions are supported. Signing never touches the network
+//! so every field required to sign must be supplied.
+use anyhow::{anyhow, bail, Result};
+use ethers_core::{
+    types::{
+        transaction::{eip2718::TypedTransaction, eip2930::AccessList},
+        Address, Bytes, Eip1559TransactionRequest, Eip2930TransactionRequest,
+        TransactionRequest, U256, U64,
+    },
+    utils::keccak256,
+};
+use ethers_signers::{LocalWallet, Signer};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::helpers::{format_address, format_units};
+
+use super::{
+    chain_id,
+    consent::{self, Approval},
+    delegation::{SetCodeTransaction, SignedAuthorization},
+    wallet::{ensure_connected, unlock},
+    Context, ProviderError, ProviderResult, Request,
+};
+
+/// Gas used by a simple value transfer.
+pub const MIN_GAS_LIMIT: u64 = 21_000;
+/// Upper bound for the gas limit of a single transaction.
+pub const MAX_GAS_LIMIT: u64 = 30_000_000;
+
+/// Envelope type of a transaction.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum TransactionKind {
+    Legacy,
+    AccessList,
+    DynamicFee,
+    SetCode,
+}
+
+/// Transaction parameters sent by a client.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TransactionParams {
+    pub from: Option<Address>,
+    pub to: Option<Address>,
+    pub gas: Option<U256>,
+    pub gas_price: Option<U256>,
+    pub max_fee_per_gas: Option<U256>,
+    pub max_priority_fee_per_gas: Option<U256>,
+    pub value: Option<U256>,
+    pub data: Option<Bytes>,
+    pub input: Option<Bytes>,
+    pub nonce: Option<U256>,
+    pub chain_id: Option<U64>,
+    #[serde(rename = "type")]
+    pub kind: Option<U64>,
+    pub access_list: Option<AccessList>,
+    pub authorization_list: Option<Vec<SignedAuthorization>>,
+}
+
+/// A signed transaction ready to be broadcast.
+#[derive(Debug, Clone, Serialize)]
+pub struct SignedTransaction {
+    /// RLP encoded signed transaction.
+    pub raw: Bytes,
+    /// Transaction hash.
+    pub hash: String,
+}
+
+impl TransactionParams {
+    /// Call data for the transaction.
+    ///
+    /// Clients may send either `data` or `input`; when both are
+    /// given they must be equal.
+    pub fn call_data(&self) -> Result<Option<Bytes>> {
+        match (&self.data, &self.input) {
+            (Some(data), Some(input)) if data != input => {
+                bail!("data and input fields do not match")
+            }
+            (Some(data), _) => Ok(Some(data.clone())),
+            (None, input) => Ok(input.clone()),
+        }
+    }
+
+    /// Determine the envelope type.
+    pub fn transaction_kind(&self) -> Result<TransactionKind> {
+        match self.kind.map(|kind| kind.as_u64()) {
+            Some(0) => Ok(TransactionKind::Legacy),
+            Some(1) => Ok(TransactionKind::AccessList),
+            Some(2) => Ok(TransactionKind::DynamicFee),
+            Some(4) => Ok(TransactionKind::SetCode),
+            Some(kind) => bail!("unsupported transaction type {}", kind),
+            None if self.authorization_list.is_some() => {
+                Ok(TransactionKind::SetCode)
+            }
+            None if self.max_fee_per_gas.is_some()
+                || self.max_priority_fee_per_gas.is_some() =>
+            {
+                Ok(TransactionKind::DynamicFee)
+            }
+            None if self.access_list.is_some() => {
+                Ok(TransactionKind::AccessList)
+            }
+            None => Ok(TransactionKind::Legacy),
+        }
+    }
+
+    /// Validate the parameters and build a transaction for a chain.
+    pub fn build(&self, chain_id: u64) -> Result<TypedTransaction> {
+        let from = self.from.ok_or_else(|| anyhow!("from is required"))?;
+
+        if let Some(tx_chain_id) = self.chain_id {
+            if tx_chain_id.as_u64() != chain_id {
+                bail!(
+                    "chain id {} does not match the active chain id {}",
+                    tx_chain_id,
+                    chain_id
+                );
+            }
+        }
+
+        let nonce = self.nonce.ok_or_else(|| anyhow!("nonce is required"))?;
+        let gas = self.gas.ok_or_else(|| anyhow!("gas is required"))?;
+        if gas < U256::from(MIN_GAS_LIMIT) {
+            bail!("gas limit {} is below the minimum {}", gas, MIN_GAS_LIMIT);
+        }
+        if gas > U256::from(MAX_GAS_LIMIT) {
+            bail!("gas limit {} exceeds the maximum {}", gas, MAX_GAS_LIMIT);
+        }
+
+        let data = self.call_data()?;
+        if self.to.is_none()
+            && data.as_ref().map_or(true, |d| d.as_ref().is_empty())
+        {
+            bail!("contract creation requires data");
+        }
+        let value = self.value.unwrap_or_default();
+
+        let kind = self.transaction_kind()?;
+        let tx = match kind {
+            TransactionKind::Legacy | TransactionKind::AccessList => {
+                if self.max_fee_per_gas.is_some()
+                    || self.max_priority_fee_per_gas.is_some()
+                {
+                    bail!("dynamic fee fields are not allowed for this transaction type");
+                }
+                let gas_price = self
+                    .gas_price
+                    .ok_or_else(|| anyhow!("gasPrice is required"))?;
+                if gas_price.is_zero() {
+                    bail!("gasPrice must be greater than zero");
+                }
+
+                let mut tx = TransactionRequest::new()
+                    .from(from)
+                    .gas(gas)
+                    .gas_price(gas_price)
+                    .value(value)
+                    .nonce(nonce);
+                if let Some(to) = self.to {
+                    tx = tx.to(to);
+                }
+                if let Some(data) = data {
+                    tx = tx.data(data);
+                }
+
+                if kind == TransactionKind::Legacy {
+                    if self.access_list.is_some() {
+                        bail!("access list is not allowed for legacy transactions");
+                    }
+                    TypedTransaction::Legacy(tx)
+                } else {
+                    let access_list =
+                        self.access_list.clone().unwrap_or_default();
+                    TypedTransaction::Eip2930(Eip2930TransactionRequest::new(
+                        tx,
+                        access_list,
+                    ))
+                }
+            }
+            TransactionKind::DynamicFee => {
+                if self.gas_price.is_some() {
+                    bail!(
+                        "gasPrice is not allowed for dynamic fee transactions"
+                    );
+                }
+                let max_fee = self
+                    .max_fee_per_gas
+                    .ok_or_else(|| anyhow!("maxFeePerGas is required"))?;
+                let max_priority_fee =
+                    self.max_priority_fee_per_gas.ok_or_else(|| {
+                        anyhow!("maxPriorityFeePerGas is required")
+                    })?;
+                if max_fee.is_zero() {
+                    bail!("maxFeePerGas must be greater than zero");
+                }
+                if max_priority_fee > max_fee {
+                    bail!("maxPriorityFeePerGas exceeds maxFeePerGas");
+                }
+
+                let mut tx = Eip1559TransactionRequest::new()
+                    .from(from)
+                    .gas(gas)
+                    .max_fee_per_gas(max_fee)
+                    .max_priority_fee_per_gas(max_priority_fee)
+                    .value(value)
+                    .nonce(nonce)
+                    .access_list(self.access_list.clone().unwrap_or_default());
+                if let Some(to) = self.to {
+                    tx = tx.to(to);
+                }
+                if let Some(data) = data {
+                    tx = tx.data(data);
+                }
+                TypedTransaction::Eip1559(tx)
+            }
+            TransactionKind::SetCode => {
+                bail!("set code transactions are built by SetCodeTransaction")
+            }
+        };
+
+        Ok(tx)
+    }
+
+    /// Human-readable description of the transaction.
+    pub fn describe(&self) -> String {
+        let gwei = |amount: U256| format!("{} gwei", format_units(amount, 9));
+        let mut lines = vec![];
+        match self.to {
+            Some(to) => lines.push(format!("To: {}", format_address(to))),
+            None => lines.push("To: (contract creation)".to_string()),
+        }
+        lines.push(format!(
+            "Value: {} ETH",
+            format_units(self.value.unwrap_or_default(), 18)
+        ));
+        if let Some(gas) = self.gas {
+            lines.push(format!("Gas limit: {}", gas));
+        }
+        if let Some(gas_price) = self.gas_price {
+            lines.push(format!("Gas price: {}", gwei(gas_price)));
+        }
+        if let Some(max_fee) = self.max_fee_per_gas {
+            lines.push(format!("Max fee: {}", gwei(max_fee)));
+        }
+        if let Some(max_priority_fee) = self.max_priority_fee_per_gas {
+            lines.push(format!("Max priority fee: {}", gwei(max_priority_fee)));
+        }
+        if let Some(nonce) = self.nonce {
+            lines.push(format!("Nonce: {}", nonce));
+        }
+        if let Ok(Some(data)) = self.call_data() {
+            let data: &[u8] = data.as_ref();
+            if !data.is_empty() {
+                lines.push(format!(
+                    "Data: 0x{} ({} bytes)",
+                    hex::encode(data),
+                    data.len()
+                ));
+            }
+        }
+        for authorization in self.authorization_list.iter().flatten() {
+            lines.push(format!(
+                "Delegate to: {} (chain {}, nonce {})",
+                format_address(authorization.address),
+                authorization.chain_id,
+                authorization.nonce
+            ));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Sign a transaction with a wallet.
+pub async fn sign_with(
+    wallet: LocalWallet,
+    tx: &TypedTransaction,
+    chain_id: u64,
+) -> Result<SignedTransaction> {
+    let wallet = wallet.with_chain_id(chain_id);
+    let signature = wallet.sign_transaction(tx).await?;
+    let raw = tx.rlp_signed(chain_id, &signature);
+    let hash = format!("0x{}", hex::encode(keccak256(raw.as_ref())));
+    Ok(SignedTransaction { raw, hash })
+}
+
+/// Build and sign transaction parameters with a wallet.
+pub async fn sign_params(
+    wallet: LocalWallet,
+    params: &TransactionParams,
+    chain_id: u64,
+) -> Result<SignedTransaction> {
+    if params.transaction_kind()? == TransactionKind::SetCode {
+        let tx = SetCodeTransaction::build(params, chain_id)?;
+        return Ok(tx.sign(&wallet));
+    }
+    sign_with(wallet, &params.build(chain_id)?, chain_id).await
+}
+
+/// Validate, approve and sign transaction parameters.
+pub async fn approve_and_sign(
+    ctx: &Context,
+    method: &str,
+    params: &TransactionParams,
+    chain_id: u64,
+) -> ProviderResult<SignedTransaction> {
+    let from = params
+        .from
+        .map(format_address)
+        .ok_or_else(|| ProviderError::invalid_params("from is required"))?;
+    ensure_connected(ctx, &from)?;
+
+    let kind = params
+        .transaction_kind()
+        .map_err(ProviderError::invalid_params)?;
+    let warnings = if kind == TransactionKind::SetCode {
+        SetCodeTransaction::build(params, chain_id)
+            .map_err(ProviderError::invalid_params)?
+            .warnings()
+    } else {
+        params
+            .build(chain_id)
+            .map_err(ProviderError::invalid_params)?;
+        vec![]
+    };
+
+    let payload =
+        serde_json::to_value(params).map_err(ProviderError::internal)?;
+    let mut approval = Approval::new(method, params.describe())
+        .payload(json!({ "chainId": chain_id, "transaction": payload }));
+    for warning in warnings {
+        approval = approval.warning(warning);
+    }
+    let passphrase = consent::approve(ctx, approval.account(&from)).await?;
+    let wallet = unlock(&from, passphrase).await?;
+    Ok(sign_params(wallet, params, chain_id).await?)
+}
+
+/// Sign a transaction using `eth_signTransaction`.
+///
+/// Returns the RLP encoded signed transaction.
+pub async fn sign_transaction(
+    ctx: &Context,
+    request: &Request,
+) -> ProviderResult<Value> {
+    let (params,): (TransactionParams,) = request.params()?;
+    let signed =
+        approve_and_sign(ctx, request.method(), &params, chain_id(ctx)).await?;
+    Ok(serde_json::to_value(signed.raw).map_err(ProviderError::internal)?)
+}
+
+/// Sign a transaction for the wallet UI.
+///
+/// The owner has already approved the request in the UI and
+/// supplied the passphrase to unlock the account.
+pub async fn sign_for_owner(
+    params: TransactionParams,
+    chain_id: u64,
+    passphrase: String,
+) -> Result<SignedTransaction> {
+    let from = params
+        .from
+        .map(format_address)
+        .ok_or_else(|| anyhow!("from is required"))?;
+    let wallet = unlock(&from, passphrase).await?;
+    sign_params(wallet, &params, chain_id).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::{transaction::eip2930::AccessListItem, H256};
+
+    const CHAIN_ID: u64 = 1;
+
+    /// Transactions signed with the key in `sign_each_transaction_type`
+    /// on chain 1, computed independently with alloy.
+    const LEGACY_TX: &str = concat!(
+        "0xf86b80843b9aca008252089402020202020202020202020202020202020202",
+        "02880de0b6b3a76400008026a0b0225751e7f767d87835c40a23ef1dab1be775",
+        "d0a059cdad1d93c37fdc3aaf27a00e3c18278724e67629f40f8c3d52c662a0c2",
+        "37b5c975c6995eb6796ce074b8a6",
+    );
+    const LEGACY_TX_HASH: &str =
+        "0xca276e5b7fd3add09a12cd7bffee530a745a1f9916662d763c6d99fec173073f";
+
+    const ACCESS_LIST_TX: &str = concat!(
+        "0x01f8a60180843b9aca00827530940202020202020202020202020202020202",
+        "020202880de0b6b3a764000080f838f794030303030303030303030303030303",
+        "0303030303e1a000000000000000000000000000000000000000000000000000",
+        "0000000000000080a0a68f5abf79846e4c9f02691b5ee73c90bae850dd1343d5",
+        "029b89ca9454e2f05ba06bc8c514afacad67d951429a30cc526cba56bfa1a1ba",
+        "1f5cd37695895a1b3e0f",
+    );
+    const ACCESS_LIST_TX_HASH: &str =
+        "0x35fae289e6f290c37bc554c47cd929de03172e5d8a2329c905b169dede84b5f8";
+
+    const DYNAMIC_FEE_TX: &str = concat!(
+        "0x02f8770180843b9aca008502540be400825208940202020202020202020202",
+        "020202020202020202880de0b6b3a76400008412345678c080a05fc7bd75c166",
+        "580dddd0df946c5de78084c6ed0201f458e7aecfdb4828cbd6dda04f1430af81",
+        "473e1e94fd6e1a998be8dd0eb0015444c01897686b114d228ea6d2",
+    );
+    const DYNAMIC_FEE_TX_HASH: &str =
+        "0x4c2dde8147536fbc320b19afbc2c57d50d07865d7d16f6cea76c9d1407699d05";
+
+    fn params() -> TransactionParams {
+        TransactionParams {
+            from: Some(Address::repeat_byte(0x01)),
+            to: Some(Address::repeat_byte(0x02)),
+            gas: Some(U256::from(MIN_GAS_LIMIT)),
+            value: Some(U256::exp10(18)),
+            nonce: Some(U256::zero()),
+            ..Default::default()
+        }
+    }
+
+    fn legacy() -> TransactionParams {
+        TransactionParams {
+            gas_price: Some(U256::exp10(9)),
+            ..params()
+        }
+    }
+
+    fn dynamic_fee() -> TransactionParams {
+        TransactionParams {
+            max_fee_per_gas: Some(U256::exp10(10)),
+            max_priority_fee_per_gas: Some(U256::exp10(9)),
+            ..params()
+        }
+    }
+
+    fn error(params: &TransactionParams) -> String {
+        params.build(CHAIN_ID).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn build_each_transaction_type() -> Result<()> {
+        assert!(matches!(
+            legacy().build(CHAIN_ID)?,
+            TypedTransaction::Legacy(_)
+        ));
+
+        let access_list = TransactionParams {
+            access_list: Some(AccessList::default()),
+            ..legacy()
+        };
+        assert_eq!(
+            TransactionKind::AccessList,
+            access_list.transaction_kind()?
+        );
+        assert!(matches!(
+            access_list.build(CHAIN_ID)?,
+            TypedTransaction::Eip2930(_)
+        ));
+
+        assert_eq!(
+            TransactionKind::DynamicFee,
+            dynamic_fee().transaction_kind()?
+        );
+        assert!(matches!(
+            dynamic_fee().build(CHAIN_ID)?,
+            TypedTransaction::Eip1559(_)
+        ));
+
+        let set_code = TransactionParams {
+            authorization_list: Some(vec![]),
+            ..dynamic_fee()
+        };
+        assert_eq!(TransactionKind::SetCode, set_code.transaction_kind()?);
+        assert!(set_code.build(CHAIN_ID).is_err());
+
+        let unknown = TransactionParams {
+            kind: Some(U64::from(3)),
+            ..legacy()
+        };
+        assert_eq!("unsupported transaction type 3", error(&unknown));
+        Ok(())
+    }
+
+    #[test]
+    fn reject_gas_out_of_bounds() {
+        let low = TransactionParams {
+            gas: Some(U256::from(MIN_GAS_LIMIT - 1)),
+            ..legacy()
+        };
+        assert!(error(&low).contains("below the minimum"));
+
+        let high = TransactionParams {
+            gas: Some(U256::from(MAX_GAS_LIMIT + 1)),
+            ..legacy()
+        };
+        assert!(error(&high).contains("exceeds the maximum"));
+
+        let missing = TransactionParams {
+            gas: None,
+            ..legacy()
+        };
+        assert_eq!("gas is required", error(&missing));
+    }
+
+    #[test]
+    fn reject_invalid_fees() {
+        let mixed = TransactionParams {
+            gas_price: Some(U256::exp10(9)),
+            ..dynamic_fee()
+        };
+        assert_eq!(
+            "gasPrice is not allowed for dynamic fee transactions",
+            error(&mixed)
+        );
+
+        let typed_legacy = TransactionParams {
+            kind: Some(U64::zero()),
+            ..dynamic_fee()
+        };
+        assert_eq!(
+            "dynamic fee fields are not allowed for this transaction type",
+            error(&typed_legacy)
+        );
+
+        let priority_too_high = TransactionParams {
+            max_priority_fee_per_gas: Some(U256::exp10(11)),
+            ..dynamic_fee()
+        };
+        assert_eq!(
+            "maxPriorityFeePerGas exceeds maxFeePerGas",
+            error(&priority_too_high)
+        );
+
+        let zero_gas_price = TransactionParams {
+            gas_price: Some(U256::zero()),
+            ..params()
+        };
+        assert_eq!(
+            "gasPrice must be greater than zero",
+            error(&zero_gas_price)
+        );
+
+        let missing_priority = TransactionParams {
+            max_priority_fee_per_gas: None,
+            ..dynamic_fee()
+        };
+        assert_eq!(
+            "maxPriorityFeePerGas is required",
+            error(&missing_priority)
+        );
+    }
+
+    #[test]
+    fn reject_invalid_fields() {
+        let other_chain = TransactionParams {
+            chain_id: Some(U64::from(5)),
+            ..legacy()
+        };
+        assert_eq!(
+            "chain id 5 does not match the active chain id 1",
+            error(&other_chain)
+        );
+
+        let legacy_access_list = TransactionParams {
+            kind: Some(U64::zero()),
+            access_list: Some(AccessList::default()),
+            ..legacy()
+        };
+        assert_eq!(
+            "access list is not allowed for legacy transactions",
+            error(&legacy_access_list)
+        );
+
+        let create_without_data = TransactionParams {
+            to: None,
+            ..legacy()
+        };
+        assert_eq!(
+            "contract creation requires data",
+            error(&create_without_data)
+        );
+
+        let mismatched_data = TransactionParams {
+            data: Some(Bytes::from(vec![0x01])),
+            input: Some(Bytes::from(vec![0x02])),
+            ..legacy()
+        };
+        assert_eq!(
+            "data and input fields do not match",
+            error(&mismatched_data)
+        );
+
+        let missing_nonce = TransactionParams {
+            nonce: None,
+            ..legacy()
+        };
+        assert_eq!("nonce is required", error(&missing_nonce));
+    }
+
+    #[tokio::test]
+    async fn sign_each_transaction_type() -> Result<()> {
+        let wallet: LocalWallet =
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+                .parse()?;
+        let access_list = TransactionParams {
+            gas: Some(U256::from(30_000)),
+            access_list: Some(AccessList(vec![AccessListItem {
+                address: Address::repeat_byte(0x03),
+                storage_keys: vec![H256::zero()],
+            }])),
+            ..legacy()
+        };
+        let dynamic_fee = TransactionParams {
+            data: Some(Bytes::from(vec![0x12, 0x34, 0x56, 0x78])),
+            ..dynamic_fee()
+        };
+        let expected = [
+            (legacy(), LEGACY_TX, LEGACY_TX_HASH),
+            (access_list, ACCESS_LIST_TX, ACCESS_LIST_TX_HASH),
+            (dynamic_fee, DYNAMIC_FEE_TX, DYNAMIC_FEE_TX_HASH),
+        ];
+        for (params, raw, hash) in expected {
+            let tx = params.build(CHAIN_ID)?;
+            let signed = sign_with(wallet.clone(), &tx, CHAIN_ID).await?;
+            assert_eq!(raw, format!("0x{}", hex::encode(&signed.raw)));
+            assert_eq!(hash, signed.hash);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,607 @@
+//! EIP-712 typed structured data.
+//!
+//! Encoding follows the `eth-sig-util` implementation for
+//! versions 3 and 4; version 4 adds support for arrays and
+//! allows missing struct values which are encoded as zero.
+use anyhow::{anyhow, bail, Result};
+use ethers_core::{
+    types::{Address, H256, I256, U256},
+    utils::keccak256,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+
+const DOMAIN_TYPE: &str = "EIP712Domain";
+
+/// Fields permitted in the domain with their types.
+const DOMAIN_FIELDS: [(&str, &str); 5] = [
+    ("name", "string"),
+    ("version", "string"),
+    ("chainId", "uint256"),
+    ("verifyingContract", "address"),
+    ("salt", "bytes32"),
+];
+
+/// Version of the typed data encoding.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Version {
+    V3,
+    V4,
+}
+
+/// Member of a struct type.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TypedField {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+}
+
+/// Typed data sent by a client for signing.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TypedData {
+    pub types: BTreeMap<String, Vec<TypedField>>,
+    pub primary_type: String,
+    #[serde(default)]
+    pub domain: Map<String, Value>,
+    #[serde(default)]
+    pub message: Value,
+}
+
+/// Strip any array suffix from a type name.
+fn base_type(kind: &str) -> &str {
+    match kind.find('[') {
+        Some(index) => &kind[..index],
+        None => kind,
+    }
+}
+
+/// Split an array type into the element type and optional length.
+fn array_type(kind: &str) -> Option<(&str, Option<usize>)> {
+    if !kind.ends_with(']') {
+        return None;
+    }
+    let index = kind.rfind('[')?;
+    let length = &kind[index + 1..kind.len() - 1];
+    Some((&kind[..index], length.parse().ok()))
+}
+
+/// Parse the bit size for an integer type, eg: `uint256`.
+fn int_size(kind: &str, prefix: &str) -> Option<usize> {
+    let size = kind.strip_prefix(prefix)?;
+    if size.is_empty() {
+        return Some(256);
+    }
+    size.parse::<usize>()
+        .ok()
+        .filter(|size| *size > 0 && *size <= 256 && size % 8 == 0)
+}
+
+/// Parse the length of a fixed size byte array, eg: `bytes32`.
+fn bytes_size(kind: &str) -> Option<usize> {
+    kind.strip_prefix("bytes")?
+        .parse::<usize>()
+        .ok()
+        .filter(|size| *size > 0 && *size <= 32)
+}
+
+fn is_atomic(kind: &str) -> bool {
+    matches!(kind, "address" | "bool" | "string" | "bytes")
+        || int_size(kind, "uint").is_some()
+        || int_size(kind, "int").is_some()
+        || bytes_size(kind).is_some()
+}
+
+/// Decode bytes from a hex string.
+fn decode_hex(value: &str) -> Result<Vec<u8>> {
+    let value = value
+        .strip_prefix("0x")
+        .ok_or_else(|| anyhow!("expected hex string, got {}", value))?;
+    if value.len() % 2 == 1 {
+        Ok(hex::decode(format!("0{}", value))?)
+    } else {
+        Ok(hex::decode(value)?)
+    }
+}
+
+/// Parse an unsigned integer from a number or string.
+fn parse_uint(value: &Value) -> Result<U256> {
+    match value {
+        Value::Number(number) => number
+            .as_u64()
+            .map(U256::from)
+            .ok_or_else(|| anyhow!("invalid unsigned integer {}", number)),
+        Value::String(text) if text.starts_with("0x") => {
+            Ok(U256::from_str_radix(&text[2..], 16)?)
+        }
+        Value::String(text) => Ok(U256::from_dec_str(text)?),
+        _ => bail!("invalid unsigned integer {}", value),
+    }
+}
+
+/// Parse a signed integer from a number or string.
+fn parse_int(value: &Value) -> Result<I256> {
+    match value {
+        Value::Number(number) => number
+            .as_i64()
+            .map(I256::from)
+            .ok_or_else(|| anyhow!("invalid integer {}", number)),
+        Value::String(text) if text.starts_with("0x") => {
+            Ok(I256::from_raw(U256::from_str_radix(&text[2..], 16)?))
+        }
+        Value::String(text) => Ok(I256::from_dec_str(text)?),
+        _ => bail!("invalid integer {}", value),
+    }
+}
+
+/// Parse the value for a `bytes` or `string` field as bytes.
+fn dynamic_bytes(kind: &str, value: &Value) -> Result<Vec<u8>> {
+    match value {
+        Value::String(text) if kind == "bytes" && text.starts_with("0x") => {
+            decode_hex(text)
+        }
+        Value::String(text) => Ok(text.as_bytes().to_vec()),
+        Value::Number(number) if kind == "bytes" => {
+            let number = number
+                .as_u64()
+                .ok_or_else(|| anyhow!("invalid bytes {}", number))?;
+            let bytes = number.to_be_bytes();
+            let start = bytes.iter().position(|b| *b != 0).unwrap_or(7);
+            Ok(bytes[start..].to_vec())
+        }
+        Value::Number(number) => Ok(number.to_string().into_bytes()),
+        Value::Null if kind == "string" => Ok(vec![]),
+        _ => bail!("invalid {} value {}", kind, value),
+    }
+}
+
+/// ABI encode an atomic value into a single word.
+fn encode_atomic(kind: &str, value: &Value) -> Result<[u8; 32]> {
+    let mut word = [0u8; 32];
+    if kind == "address" {
+        let address = value
+            .as_str()
+            .ok_or_else(|| anyhow!("invalid address {}", value))?
+            .parse::<Address>()
+            .map_err(|_| anyhow!("invalid address {}", value))?;
+        word[12..].copy_from_slice(address.as_bytes());
+    } else if kind == "bool" {
+        let flag = match value {
+            Value::Bool(flag) => *flag,
+            Value::String(text) if text == "true" || text == "false" => {
+                text == "true"
+            }
+            _ => bail!("invalid bool {}", value),
+        };
+        word[31] = flag as u8;
+    } else if let Some(size) = int_size(kind, "uint") {
+        let number = parse_uint(value)?;
+        if number.bits() > size {
+            bail!("value {} overflows {}", value, kind);
+        }
+        number.to_big_endian(&mut word);
+    } else if let Some(size) = int_size(kind, "int") {
+        let number = parse_int(value)?;
+        let max = I256::from_raw(U256::one() << (size - 1));
+        if size < 256 && (number >= max || number < -max) {
+            bail!("value {} overflows {}", value, kind);
+        }
+        number.into_raw().to_big_endian(&mut word);
+    } else if let Some(size) = bytes_size(kind) {
+        let bytes = match value {
+            Value::String(text) => decode_hex(text)?,
+            _ => bail!("invalid {} value {}", kind, value),
+        };
+        if bytes.len() > size {
+            bail!("value {} overflows {}", value, kind);
+        }
+        word[..bytes.len()].copy_from_slice(&bytes);
+    } else {
+        bail!("unknown type {}", kind);
+    }
+    Ok(word)
+}
+
+/// Render a value for display to the owner.
+fn display_value(value: &Value) -> String {
+    match value {
+        Value::String(text) => text.clone(),
+        Value::Null => String::new(),
+        _ => value.to_string(),
+    }
+}
+
+impl TypedData {
+    /// Parse typed data from a JSON value.
+    ///
+    /// Clients may send the typed data as a JSON string.
+    pub fn from_value(value: Value) -> Result<Self> {
+        let typed_data = match value {
+            Value::String(text) => serde_json::from_str(&text)?,
+            _ => serde_json::from_value(value)?,
+        };
+        Ok(typed_data)
+    }
+
+    /// Fields for a struct type.
+    fn fields(&self, name: &str) -> Result<&[TypedField]> {
+        if name == DOMAIN_TYPE && !self.types.contains_key(DOMAIN_TYPE) {
+            return Ok(&[]);
+        }
+        self.types
+            .get(name)
+            .map(|fields| fields.as_slice())
+            .ok_or_else(|| anyhow!("unknown type {}", name))
+    }
+
+    /// Validate the type definitions and domain.
+    pub fn validate(&self, version: Version) -> Result<()> {
+        if self.primary_type != DOMAIN_TYPE
+            && !self.types.contains_key(&self.primary_type)
+        {
+            bail!("primary type {} is not defined", self.primary_type);
+        }
+
+        for (name, fields) in &self.types {
+            let mut names = BTreeSet::new();
+            for field in fields {
+                if !names.insert(&field.name) {
+                    bail!("duplicate field {} in {}", field.name, name);
+                }
+                if array_type(&field.kind).is_some() && version == Version::V3 {
+                    bail!("arrays are not supported by version 3");
+                }
+                let kind = base_type(&field.kind);
+                if !is_atomic(kind) && !self.types.contains_key(kind) {
+                    bail!(
+                        "type {} of {}.{} is not defined",
+                        kind,
+                        name,
+                        field.name
+                    );
+                }
+            }
+        }
+
+        let domain_fields = self.fields(DOMAIN_TYPE)?;
+        for field in domain_fields {
+            let expected = DOMAIN_FIELDS
+                .iter()
+                .find(|(name, _)| *name == field.name)
+                .ok_or_else(|| {
+                    anyhow!("unknown domain field {}", field.name)
+                })?;
+            if expected.1 != field.kind {
+                bail!(
+                    "domain field {} must be of type {}",
+                    field.name,
+                    expected.1
+                );
+            }
+        }
+        for key in self.domain.keys() {
+            if !domain_fields.iter().any(|f| &f.name == key) {
+                bail!(
+                    "domain field {} is not declared in {}",
+                    key,
+                    DOMAIN_TYPE
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Chain identifier declared in the domain.
+    pub fn chain_id(&self) -> Result<Option<u64>> {
+        match self.domain.get("chainId") {
+            Some(value) => {
+                let chain_id = parse_uint(value)?;
+                if chain_id > U256::from(u64::MAX) {
+                    bail!("chain id {} is too large", chain_id);
+                }
+                Ok(Some(chain_id.as_u64()))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Collect the struct types referenced by a type.
+    fn dependencies<'a>(
+        &'a self,
+        name: &'a str,
+        found: &mut BTreeSet<&'a str>,
+    ) -> Result<()> {
+        if found.contains(name) || is_atomic(name) {
+            return Ok(());
+        }
+        found.insert(name);
+        for field in self.fields(name)? {
+            self.dependencies(base_type(&field.kind), found)?;
+        }
+        Ok(())
+    }
+
+    /// Encode a type and its dependencies, eg: `Mail(Person from)Person(...)`.
+    pub fn encode_type(&self, name: &str) -> Result<String> {
+        let mut found = BTreeSet::new();
+        self.dependencies(name, &mut found)?;
+        found.remove(name);
+
+        let mut encoded = String::new();
+        for dependency in std::iter::once(name).chain(found.into_iter()) {
+            let fields: Vec<String> = self
+                .fields(dependency)?
+                .iter()
+                .map(|f| format!("{} {}", f.kind, f.name))
+                .collect();
+            encoded.push_str(&format!("{}({})", dependency, fields.join(",")));
+        }
+        Ok(encoded)
+    }
+
+    /// Hash of the encoded type.
+    pub fn type_hash(&self, name: &str) -> Result<[u8; 32]> {
+        Ok(keccak256(self.encode_type(name)?))
+    }
+
+    /// Encode a field value into a single word.
+    fn encode_field(
+        &self,
+        kind: &str,
+        value: &Value,
+        version: Version,
+    ) -> Result<[u8; 32]> {
+        if self.types.contains_key(kind) {
+            if version == Version::V4 && value.is_null() {
+                return Ok([0u8; 32]);
+            }
+            return self.hash_struct(kind, value, version);
+        }
+
+        if let Some((element, length)) = array_type(kind) {
+            if version == Version::V3 {
+                bail!("arrays are not supported by version 3");
+            }
+            let items = value
+                .as_array()
+                .ok_or_else(|| anyhow!("expected array for {}", kind))?;
+            if let Some(length) = length {
+                if items.len() != length {
+                    bail!("expected {} items for {}", length, kind);
+                }
+            }
+            let mut encoded = Vec::with_capacity(items.len() * 32);
+            for item in items {
+                encoded.extend_from_slice(
+                    &self.encode_field(element, item, version)?,
+                );
+            }
+            return Ok(keccak256(encoded));
+        }
+
+        if kind == "bytes" || kind == "string" {
+            return Ok(keccak256(dynamic_bytes(kind, value)?));
+        }
+
+        encode_atomic(kind, value)
+    }
+
+    /// Encode the data for a struct.
+    fn encode_data(
+        &self,
+        name: &str,
+        data: &Value,
+        version: Version,
+    ) -> Result<Vec<u8>> {
+        let data = data
+            .as_object()
+            .ok_or_else(|| anyhow!("expected object for {}", name))?;
+        let mut encoded = self.type_hash(name)?.to_vec();
+        for field in self.fields(name)? {
+            match data.get(&field.name) {
+                Some(value) => encoded.extend_from_slice(&self.encode_field(
+                    &field.kind,
+                    value,
+                    version,
+                )?),
+                // Version 3 skips missing values
+                None if version == Version::V3 => {}
+                None if self.types.contains_key(&field.kind) => {
+                    encoded.extend_from_slice(&[0u8; 32])
+                }
+                None => bail!("missing value for {}.{}", name, field.name),
+            }
+        }
+        Ok(encoded)
+    }
+
+    /// Hash a struct.
+    pub fn hash_struct(
+        &self,
+        name: &str,
+        data: &Value,
+        version: Version,
+    ) -> Result<[u8; 32]> {
+        Ok(keccak256(self.encode_data(name, data, version)?))
+    }
+
+    /// Hash of the domain.
+    pub fn domain_separator(&self, version: Version) -> Result<[u8; 32]> {
+        self.hash_struct(
+            DOMAIN_TYPE,
+            &Value::Object(self.domain.clone()),
+            version,
+        )
+    }
+
+    /// Hash to be signed.
+    pub fn sign_hash(&self, version: Version) -> Result<H256> {
+        let mut encoded = vec![0x19, 0x01];
+        encoded.extend_from_slice(&self.domain_separator(version)?);
+        if self.primary_type != DOMAIN_TYPE {
+            encoded.extend_from_slice(&self.hash_struct(
+                &self.primary_type,
+                &self.message,
+                version,
+            )?);
+        }
+        Ok(H256::from(keccak256(encoded)))
+    }
+
+    /// Describe a value as a tree of named and typed fields.
+    fn describe(&self, kind: &str, value: &Value) -> Value {
+        if let (Some((element, _)), Some(items)) =
+            (array_type(kind), value.as_array())
+        {
+            let items: Vec<Value> = items
+                .iter()
+                .map(|item| self.describe(element, item))
+                .collect();
+            return json!({ "type": kind, "value": items });
+        }
+        match (self.fields(kind), value.as_object()) {
+            (Ok(fields), Some(data))
+                if self.types.contains_key(kind) || kind == DOMAIN_TYPE =>
+            {
+                let members: Vec<Value> = fields
+                    .iter()
+                    .map(|field| {
+                        let value =
+                            data.get(&field.name).unwrap_or(&Value::Null);
+                        let mut member = self.describe(&field.kind, value);
+                        member["name"] = Value::String(field.name.clone());
+                        member
+                    })
+                    .collect();
+                json!({ "type": kind, "value": members })
+            }
+            _ => json!({ "type": kind, "value": display_value(value) }),
+        }
+    }
+
+    /// Structured, human-readable version of the typed data.
+    pub fn describe_message(&self) -> Value {
+        json!({
+            "domain": self.describe(
+                DOMAIN_TYPE,
+                &Value::Object(self.domain.clone()),
+            ),
+            "message": self.describe(&self.primary_type, &self.message),
+        })
+    }
+}
+
+/// Render a description as indented text.
+pub fn describe_text(description: &Value) -> String {
+    fn render(value: &Value, depth: usize, output: &mut String) {
+        let indent = "  ".repeat(depth);
+        let name = value["name"].as_str();
+        match &value["value"] {
+            Value::Array(members) => {
+                if let Some(name) = name {
+                    output.push_str(&format!("{}{}:\n", indent, name));
+                }
+                let depth = if name.is_some() { depth + 1 } else { depth };
+                for member in members {
+                    render(member, depth, output);
+                }
+            }
+            other => {
+                output.push_str(&format!(
+                    "{}{}: {}\n",
+                    indent,
+                    name.unwrap_or(""),
+                    display_value(other)
+                ));
+            }
+        }
+    }
+
+    let mut output = String::new();
+    render(&description["domain"], 0, &mut output);
+    output.push('\n');
+    render(&description["message"], 0, &mut output);
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{TypedData, Version};
+    use anyhow::Result;
+    use serde_json::json;
+
+    fn mail() -> serde_json::Value {
+        json!({
+            "types": {
+                "EIP712Domain": [
+                    { "name": "name", "type": "string" },
+                    { "name": "version", "type": "string" },
+                    { "name": "chainId", "type": "uint256" },
+                    { "name": "verifyingContract", "type": "address" }
+                ],
+                "Person": [
+                    { "name": "name", "type": "string" },
+                    { "name": "wallet", "type": "address" }
+                ],
+                "Mail": [
+                    { "name": "from", "type": "Person" },
+                    { "name": "to", "type": "Person" },
+                    { "name": "contents", "type": "string" }
+                ]
+            },
+            "primaryType": "Mail",
+            "domain": {
+                "name": "Ether Mail",
+                "version": "1",
+                "chainId": 1,
+                "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+            },
+            "message": {
+                "from": {
+                    "name": "Cow",
+                    "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"
+                },
+                "to": {
+                    "name": "Bob",
+                    "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"
+                },
+                "contents": "Hello, Bob!"
+            }
+        })
+    }
+
+    #[test]
+    fn encode_mail_type() -> Result<()> {
+        let typed_data = TypedData::from_value(mail())?;
+        assert_eq!(
+            "Mail(Person from,Person to,string contents)Person(string name,address wallet)",
+            typed_data.encode_type("Mail")?
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn hash_mail_v3() -> Result<()> {
+        let typed_data = TypedData::from_value(mail())?;
+        typed_data.validate(Version::V3)?;
+        assert_eq!(Some(1), typed_data.chain_id()?);
+        assert_eq!(
+            "0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2",
+            format!("{:?}", typed_data.sign_hash(Version::V3)?)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn reject_arrays_v3() -> Result<()> {
+        let mut value = mail();
+        value["types"]["Mail"][1]["type"] = json!("Person[]");
+        let typed_data = TypedData::from_value(value)?;
+        assert!(typed_data.validate(Version::V3).is_err());
+        assert!(typed_data.validate(Version::V4).is_ok());
+        Ok(())
+    }
+}
@@ -0,0 +1,710 @@
+//! Smart accounts using user operations (ERC-4337).
+//!
+//! A smart account is a contract wallet deployed by a factory and
+//! controlled by one of the keystore accounts. Transactions from a
+//! smart account are wrapped in a user operation which is signed
+//! by the owner and sent to the bundler configured for the chain.
+//! The account is deployed by its first user operation so the
+//! address is known before the contract exists.
+use anyhow::{anyhow, Result};
+use ethers_core::{
+    abi::{self, Token as AbiToken},
+    types::{Address, Bytes, H256, U256},
+    utils::{hash_message, keccak256},
+};
+use ethers_signers::LocalWallet;
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::{json, Value};
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::helpers::format_address;
+use crate::upstream::Upstream;
+use crate::user::{AccountView, EntryPointVersion, SmartAccount, USER_DATA};
+
+use super::{
+    chain_id,
+    consent::{self, Approval},
+    send::fill_fees,
+    transaction::TransactionParams,
+    wallet::{parse_address, unlock},
+    Context, ProviderError, ProviderResult, CHAIN_DISCONNECTED,
+};
+
+/// Entry point contract for version 0.6.
+const ENTRY_POINT_V06: &str = "0x5FF137D4b0FDCD49DcA30c7CF57E578a026d2789";
+/// Entry point contract for version 0.7.
+const ENTRY_POINT_V07: &str = "0x0000000071727De22E5E9d8BAf0edAc6f37da032";
+/// `SimpleAccountFactory` for entry point version 0.6.
+const FACTORY_V06: &str = "0x9406Cc6185a346906296840746125a0E44976454";
+/// `SimpleAccountFactory` for entry point version 0.7.
+const FACTORY_V07: &str = "0x91E60e0613810449d098b0b5Ec8b51A0FE8c8985";
+
+/// Selector for `getAddress(address,uint256)`.
+const GET_ADDRESS: [u8; 4] = [0x8c, 0xb8, 0x4e, 0x18];
+/// Selector for `createAccount(address,uint256)`.
+const CREATE_ACCOUNT: [u8; 4] = [0x5f, 0xbf, 0xb9, 0xcf];
+/// Selector for `execute(address,uint256,bytes)`.
+const EXECUTE: [u8; 4] = [0xb6, 0x1d, 0x27, 0xf6];
+/// Selector for `getNonce(address,uint192)`.
+const GET_NONCE: [u8; 4] = [0x35, 0x56, 0x7e, 0x1a];
+
+/// Interval between checks for a receipt.
+const POLL_INTERVAL: Duration = Duration::from_secs(4);
+
+/// Time to wait for a user operation to be included.
+const RECEIPT_TIMEOUT: Duration = Duration::from_secs(600);
+
+/// Address of the entry point contract for a version.
+pub fn entry_point(version: EntryPointVersion) -> Address {
+    match version {
+        EntryPointVersion::V06 => ENTRY_POINT_V06,
+        EntryPointVersion::V07 => ENTRY_POINT_V07,
+    }
+    .parse()
+    .unwrap()
+}
+
+/// Address of the default account factory for a version.
+pub fn default_factory(version: EntryPointVersion) -> Address {
+    match version {
+        EntryPointVersion::V06 => FACTORY_V06,
+        EntryPointVersion::V07 => FACTORY_V07,
+    }
+    .parse()
+    .unwrap()
+}
+
+/// Operation sent to a bundler on behalf of a smart account.
+///
+/// Fields use the unpacked layout of version 0.7; they are packed
+/// for version 0.6 when hashing and sending the operation.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UserOperation {
+    pub sender: Address,
+    pub nonce: U256,
+    pub factory: Option<Address>,
+    pub factory_data: Bytes,
+    pub call_data: Bytes,
+    pub call_gas_limit: U256,
+    pub verification_gas_limit: U256,
+    pub pre_verification_gas: U256,
+    pub max_fee_per_gas: U256,
+    pub max_priority_fee_per_gas: U256,
+    pub paymaster: Option<Address>,
+    pub paymaster_verification_gas_limit: U256,
+    pub paymaster_post_op_gas_limit: U256,
+    pub paymaster_data: Bytes,
+    pub signature: Bytes,
+}
+
+/// Two 128 bit values packed into a word.
+fn pack_u128(high: U256, low: U256) -> [u8; 32] {
+    let mut word = [0u8; 32];
+    ((high << 128) | (low & U256::from(u128::MAX))).to_big_endian(&mut word);
+    word
+}
+
+impl UserOperation {
+    /// Factory address followed by the factory call data.
+    pub fn init_code(&self) -> Vec<u8> {
+        match self.factory {
+            Some(factory) => {
+                let mut init_code = factory.as_bytes().to_vec();
+                init_code.extend_from_slice(self.factory_data.as_ref());
+                init_code
+            }
+            None => vec![],
+        }
+    }
+
+    /// Paymaster address followed by the paymaster data.
+    ///
+    /// Version 0.7 includes the paymaster gas limits as 128 bit
+    /// values after the address.
+    pub fn paymaster_and_data(&self, version: EntryPointVersion) -> Vec<u8> {
+        match self.paymaster {
+            Some(paymaster) => {
+                let mut data = paymaster.as_bytes().to_vec();
+                if version == EntryPointVersion::V07 {
+                    let limits = pack_u128(
+                        self.paymaster_verification_gas_limit,
+                        self.paymaster_post_op_gas_limit,
+                    );
+                    data.extend_from_slice(&limits);
+                }
+                data.extend_from_slice(self.paymaster_data.as_ref());
+                data
+            }
+            None => vec![],
+        }
+    }
+
+    /// Hash signed by the owner of the sender.
+    pub fn hash(
+        &self,
+        version: EntryPointVersion,
+        entry_point: Address,
+        chain_id: u64,
+    ) -> H256 {
+        let hash_bytes =
+            |bytes: &[u8]| AbiToken::FixedBytes(keccak256(bytes).to_vec());
+        let mut fields = vec![
+            AbiToken::Address(self.sender),
+            AbiToken::Uint(self.nonce),
+            hash_bytes(&self.init_code()),
+            hash_bytes(self.call_data.as_ref()),
+        ];
+        match version {
+            EntryPointVersion::V06 => fields.extend([
+                AbiToken::Uint(self.call_gas_limit),
+                AbiToken::Uint(self.verification_gas_limit),
+                AbiToken::Uint(self.pre_verification_gas),
+                AbiToken::Uint(self.max_fee_per_gas),
+                AbiToken::Uint(self.max_priority_fee_per_gas),
+            ]),
+            EntryPointVersion::V07 => fields.extend([
+                AbiToken::FixedBytes(
+                    pack_u128(self.verification_gas_limit, self.call_gas_limit)
+                        .to_vec(),
+                ),
+                AbiToken::Uint(self.pre_verification_gas),
+                AbiToken::FixedBytes(
+                    pack_u128(
+                        self.max_priority_fee_per_gas,
+                        self.max_fee_per_gas,
+                    )
+                    .to_vec(),
+                ),
+            ]),
+        }
+        fields.push(hash_bytes(&self.paymaster_and_data(version)));
+
+        let packed = keccak256(abi::encode(&fields));
+        H256::from(keccak256(abi::encode(&[
+            AbiToken::FixedBytes(packed.to_vec()),
+            AbiToken::Address(entry_point),
+            AbiToken::Uint(U256::from(chain_id)),
+        ])))
+    }
+
+    /// Sign the operation with the wallet of the owner.
+    ///
+    /// Accounts verify the hash as a personal message.
+    pub fn sign(
+        &mut self,
+        wallet: &LocalWallet,
+        version: EntryPointVersion,
+        entry_point: Address,
+        chain_id: u64,
+    ) {
+        let hash = self.hash(version, entry_point, chain_id);
+        let signature = wallet.sign_hash(hash_message(hash), false);
+        self.signature = Bytes::from(signature.to_vec());
+    }
+
+    /// JSON representation expected by bundlers.
+    pub fn to_json(&self, version: EntryPointVersion) -> Value {
+        let mut value = json!({
+            "sender": format_address(self.sender),
+            "nonce": self.nonce,
+            "callData": self.call_data,
+            "callGasLimit": self.call_gas_limit,
+            "verificationGasLimit": self.verification_gas_limit,
+            "preVerificationGas": self.pre_verification_gas,
+            "maxFeePerGas": self.max_fee_per_gas,
+            "maxPriorityFeePerGas": self.max_priority_fee_per_gas,
+            "signature": self.signature,
+        });
+        match version {
+            EntryPointVersion::V06 => {
+                value["initCode"] = json!(Bytes::from(self.init_code()));
+                value["paymasterAndData"] =
+                    json!(Bytes::from(self.paymaster_and_data(version)));
+            }
+            EntryPointVersion::V07 => {
+                if let Some(factory) = self.factory {
+                    value["factory"] = json!(format_address(factory));
+                    value["factoryData"] = json!(self.factory_data);
+                }
+                if let Some(paymaster) = self.paymaster {
+                    value["paymaster"] = json!(format_address(paymaster));
+                    value["paymasterVerificationGasLimit"] =
+                        json!(self.paymaster_verification_gas_limit);
+                    value["paymasterPostOpGasLimit"] =
+                        json!(self.paymaster_post_op_gas_limit);
+                    value["paymasterData"] = json!(self.paymaster_data);
+                }
+            }
+        }
+        value
+    }
+}
+
+/// Gas limits estimated by a bundler.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GasEstimate {
+    pre_verification_gas: U256,
+    verification_gas_limit: U256,
+    call_gas_limit: U256,
+    paymaster_verification_gas_limit: Option<U256>,
+    paymaster_post_op_gas_limit: Option<U256>,
+}
+
+/// Response from a bundler.
+#[derive(Debug, Deserialize)]
+struct BundlerResponse {
+    result: Option<Value>,
+    error: Option<ProviderError>,
+}
+
+/// Client for the JSON-RPC API of a bundler.
+pub struct Bundler {
+    url: Url,
+    client: reqwest::Client,
+}
+
+impl Bundler {
+    /// Create a client for a bundler.
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// Create a client for the bundler configured for a chain.
+    pub fn for_chain(chain_id: u64) -> ProviderResult<Self> {
+        let url = {
+            let user = USER_DATA.read().unwrap();
+            user.settings()?.bundlers.get(&chain_id).cloned()
+        };
+        let url = url.ok_or_else(|| {
+            ProviderError::new(
+                CHAIN_DISCONNECTED,
+                format!("no bundler for chain {}", chain_id),
+            )
+        })?;
+        Ok(Self::new(url))
+    }
+
+    /// Call a method on the bundler.
+    ///
+    /// Errors returned by the bundler are passed through unchanged.
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> ProviderResult<T> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+        let response = self
+            .client
+            .post(self.url.clone())
+            .json(&body)
+            .send()
+            .await
+            .and_then(|response| response.error_for_status())
+            .map_err(|e| {
+                ProviderError::new(
+                    CHAIN_DISCONNECTED,
+                    format!("bundler {}: {}", self.url, e),
+                )
+            })?
+            .json::<BundlerResponse>()
+            .await
+            .map_err(ProviderError::internal)?;
+        if let Some(error) = response.error {
+            return Err(error);
+        }
+        serde_json::from_value(response.result.unwrap_or(Value::Null))
+            .map_err(ProviderError::internal)
+    }
+
+    /// Send a signed user operation and return the operation hash.
+    pub async fn send_user_operation(
+        &self,
+        op: &UserOperation,
+        version: EntryPointVersion,
+    ) -> ProviderResult<H256> {
+        self.call(
+            "eth_sendUserOperation",
+            json!([op.to_json(version), entry_point(version)]),
+        )
+        .await
+    }
+
+    /// Fill the gas limits of a user operation.
+    async fn estimate_gas(
+        &self,
+        op: &mut UserOperation,
+        version: EntryPointVersion,
+    ) -> ProviderResult<()> {
+        let estimate: GasEstimate = self
+            .call(
+                "eth_estimateUserOperationGas",
+                json!([op.to_json(version), entry_point(version)]),
+            )
+            .await?;
+        op.pre_verification_gas = estimate.pre_verification_gas;
+        op.verification_gas_limit = estimate.verification_gas_limit;
+        op.call_gas_limit = estimate.call_gas_limit;
+        if let Some(limit) = estimate.paymaster_verification_gas_limit {
+            op.paymaster_verification_gas_limit = limit;
+        }
+        if let Some(limit) = estimate.paymaster_post_op_gas_limit {
+            op.paymaster_post_op_gas_limit = limit;
+        }
+        Ok(())
+    }
+
+    /// Wait for a user operation to be included and return the
+    /// hash of the transaction that included it.
+    async fn wait(&self, hash: H256) -> ProviderResult<H256> {
+        let started = Instant::now();
+        while started.elapsed() < RECEIPT_TIMEOUT {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            let receipt: Option<Value> = self
+                .call("eth_getUserOperationReceipt", json!([hash]))
+                .await?;
+            if let Some(receipt) = receipt {
+                return serde_json::from_value(
+                    receipt["receipt"]["transactionHash"].clone(),
+                )
+                .map_err(ProviderError::internal);
+            }
+        }
+        Err(ProviderError::internal(format!(
+            "user operation {:#x} was not included",
+            hash
+        ))
+        .with_data(json!({ "userOpHash": hash })))
+    }
+}
+
+/// Call data for `createAccount(owner, salt)` on a factory.
+fn create_account_data(owner: Address, salt: u64) -> Bytes {
+    let mut data = CREATE_ACCOUNT.to_vec();
+    data.extend(abi::encode(&[
+        AbiToken::Address(owner),
+        AbiToken::Uint(U256::from(salt)),
+    ]));
+    Bytes::from(data)
+}
+
+/// Call data for `execute(to, value, data)` on an account.
+fn execute_data(to: Address, value: U256, data: &[u8]) -> Bytes {
+    let mut call_data = EXECUTE.to_vec();
+    call_data.extend(abi::encode(&[
+        AbiToken::Address(to),
+        AbiToken::Uint(value),
+        AbiToken::Bytes(data.to_vec()),
+    ]));
+    Bytes::from(call_data)
+}
+
+/// Call a contract and return the first word of the result.
+async fn call_word(
+    upstream: &Upstream,
+    to: Address,
+    data: Vec<u8>,
+) -> ProviderResult<[u8; 32]> {
+    let call = json!({ "to": to, "data": Bytes::from(data) });
+    let result: Bytes =
+        upstream.call("eth_call", json!([call, "latest"])).await?;
+    result
+        .as_ref()
+        .get(..32)
+        .and_then(|word| word.try_into().ok())
+        .ok_or_else(|| {
+            ProviderError::invalid_params(format!(
+                "unexpected result calling {}",
+                to
+            ))
+        })
+}
+
+/// Address the factory will deploy an account to.
+pub async fn counterfactual_address(
+    upstream: &Upstream,
+    factory: Address,
+    owner: Address,
+    salt: u64,
+) -> ProviderResult<Address> {
+    let mut data = GET_ADDRESS.to_vec();
+    data.extend(abi::encode(&[
+        AbiToken::Address(owner),
+        AbiToken::Uint(U256::from(salt)),
+    ]));
+    let word = call_word(upstream, factory, data).await?;
+    Ok(Address::from_slice(&word[12..]))
+}
+
+/// Add a smart account owned by a keystore account.
+///
+/// The factory of the entry point version is asked for the
+/// address on the given chain; the default factory is used when
+/// none is given.
+pub async fn create_smart_account(
+    owner: String,
+    chain_id: u64,
+    version: EntryPointVersion,
+    factory: Option<String>,
+    salt: u64,
+) -> Result<AccountView> {
+    let owner_address = parse_address(&owner)?;
+    let factory = match factory {
+        Some(factory) => parse_address(&factory)?,
+        None => default_factory(version),
+    };
+    let upstream = Upstream::for_chain(chain_id)?;
+    let address =
+        counterfactual_address(&upstream, factory, owner_address, salt).await?;
+    if address.is_zero() {
+        return Err(anyhow!("factory {} did not return an address", factory));
+    }
+
+    let smart = SmartAccount {
+        owner: format_address(owner_address),
+        factory: format_address(factory),
+        entry_point: version,
+        salt,
+    };
+    let mut user = USER_DATA.write().unwrap();
+    user.add_smart_account(format_address(address), smart)
+}
+
+/// Send a transaction from a smart account using
+/// `eth_sendTransaction`.
+///
+/// The transaction is executed by the account through a user
+/// operation; returns the hash of the transaction that included
+/// the operation.
+pub async fn send_transaction(
+    ctx: &Context,
+    method: &str,
+    params: &TransactionParams,
+    account: SmartAccount,
+) -> ProviderResult<Value> {
+    let sender = params
+        .from
+        .ok_or_else(|| ProviderError::invalid_params("from is required"))?;
+    let to = params.to.ok_or_else(|| {
+        ProviderError::invalid_params("smart accounts cannot deploy contracts")
+    })?;
+    let data = params.call_data().map_err(ProviderError::invalid_params)?;
+    if params.authorization_list.is_some() {
+        return Err(ProviderError::invalid_params(
+            "smart accounts cannot delegate",
+        ));
+    }
+
+    let chain_id = chain_id(ctx);
+    let upstream = Upstream::for_chain(chain_id)?;
+    let bundler = Bundler::for_chain(chain_id)?;
+    let version = account.entry_point;
+    let entry_point = entry_point(version);
+    let owner = parse_address(&account.owner)?;
+
+    let mut op = UserOperation {
+        sender,
+        call_data: execute_data(
+            to,
+            params.value.unwrap_or_default(),
+            data.as_ref().map(|data| data.as_ref()).unwrap_or_default(),
+        ),
+        ..Default::default()
+    };
+
+    let code: Bytes = upstream
+        .call("eth_getCode", json!([sender, "latest"]))
+        .await?;
+    if code.as_ref().is_empty() {
+        op.factory = Some(parse_address(&account.factory)?);
+        op.factory_data = create_account_data(owner, account.salt);
+    }
+
+    op.nonce = match params.nonce {
+        Some(nonce) => nonce,
+        None => {
+            let mut data = GET_NONCE.to_vec();
+            data.extend(abi::encode(&[
+                AbiToken::Address(sender),
+                AbiToken::Uint(U256::zero()),
+            ]));
+            U256::from_big_endian(
+                &call_word(&upstream, entry_point, data).await?,
+            )
+        }
+    };
+
+    let mut fees = params.clone();
+    fill_fees(&upstream, &mut fees).await?;
+    op.max_fee_per_gas =
+        fees.max_fee_per_gas.or(fees.gas_price).unwrap_or_default();
+    op.max_priority_fee_per_gas = fees
+        .max_priority_fee_per_gas
+        .or(fees.gas_price)
+        .unwrap_or_default();
+
+    // Estimate with a signature from another key; accounts report
+    // an invalid signature without reverting so gas is estimated
+    // the same as for a valid signature
+    let throwaway = LocalWallet::new(&mut rand::thread_rng());
+    op.sign(&throwaway, version, entry_point, chain_id);
+    bundler.estimate_gas(&mut op, version).await?;
+
+    let mut summary = params.describe();
+    summary.push_str(&format!(
+        "\n\nSent by the smart account {} owned by {}.",
+        format_address(sender),
+        account.owner
+    ));
+    if op.factory.is_some() {
+        summary.push_str("\nThe account is deployed by this operation.");
+    }
+    let approval = Approval::new(method, summary)
+        .account(&format_address(sender))
+        .payload(json!({
+            "chainId": chain_id,
+            "entryPoint": format_address(entry_point),
+            "userOperation": op.to_json(version),
+        }));
+    let passphrase = consent::approve(ctx, approval).await?;
+    let wallet = unlock(&account.owner, passphrase).await?;
+    op.sign(&wallet, version, entry_point, chain_id);
+
+    let hash = bundler.send_user_operation(&op, version).await?;
+    let transaction_hash = bundler.wait(hash).await?;
+    Ok(json!(transaction_hash))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::mock::MockServer;
+    use ethers_signers::Signer;
+
+    /// Hashes computed independently as `EntryPoint.getUserOpHash`
+    /// does on chain 1 for the operation in `hash_user_operation`.
+    const USER_OP_HASH_V06: &str =
+        "0xd9fc78a5120ff3e4579e007e3c8325e8e0b05f35fe5a9ff62bbe066c5d8852af";
+    const USER_OP_HASH_V07: &str =
+        "0x68035e6ec3e4e6a1166099c49fa6c0cf6a14b58833cb495aa14acacdb7aabad3";
+
+    fn user_operation() -> UserOperation {
+        UserOperation {
+            sender: "0x9406Cc6185a346906296840746125a0E44976454"
+                .parse()
+                .unwrap(),
+            nonce: U256::from(1),
+            call_data: execute_data(Address::zero(), U256::from(1), &[]),
+            call_gas_limit: U256::from(100_000),
+            verification_gas_limit: U256::from(200_000),
+            pre_verification_gas: U256::from(50_000),
+            max_fee_per_gas: U256::from(3_000_000_000u64),
+            max_priority_fee_per_gas: U256::from(1_000_000_000u64),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn sign_user_operation() {
+        let wallet: LocalWallet =
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+                .parse()
+                .unwrap();
+        for version in [EntryPointVersion::V06, EntryPointVersion::V07] {
+            let mut op = user_operation();
+            op.sign(&wallet, version, entry_point(version), 1);
+            let signature =
+                ethers_core::types::Signature::try_from(op.signature.as_ref())
+                    .unwrap();
+            let hash = op.hash(version, entry_point(version), 1);
+            assert_eq!(
+                wallet.address(),
+                signature.recover(hash_message(hash)).unwrap()
+            );
+            assert_ne!(hash, op.hash(version, entry_point(version), 5));
+        }
+
+        // Gas limits are packed for version 0.7 so the hashes differ
+        let op = user_operation();
+        assert_ne!(
+            op.hash(
+                EntryPointVersion::V06,
+                entry_point(EntryPointVersion::V06),
+                1
+            ),
+            op.hash(
+                EntryPointVersion::V07,
+                entry_point(EntryPointVersion::V06),
+                1
+            )
+        );
+        assert_eq!(None, op.to_json(EntryPointVersion::V07).get("initCode"));
+        assert_eq!(json!("0x"), op.to_json(EntryPointVersion::V06)["initCode"]);
+    }
+
+    #[test]
+    fn hash_user_operation() {
+        let op = UserOperation {
+            sender: "0x9406Cc6185a346906296840746125a0E44976454"
+                .parse()
+                .unwrap(),
+            nonce: U256::from(1),
+            factory: Some(Address::repeat_byte(0x11)),
+            factory_data: Bytes::from(vec![0xde, 0xad, 0xbe, 0xef]),
+            call_data: Bytes::from(vec![0x12, 0x34, 0x56, 0x78]),
+            call_gas_limit: U256::from(100_000),
+            verification_gas_limit: U256::from(200_000),
+            pre_verification_gas: U256::from(50_000),
+            max_fee_per_gas: U256::from(3_000_000_000u64),
+            max_priority_fee_per_gas: U256::from(1_000_000_000u64),
+            paymaster: Some(Address::repeat_byte(0x22)),
+            paymaster_verification_gas_limit: U256::from(30_000),
+            paymaster_post_op_gas_limit: U256::from(10_000),
+            paymaster_data: Bytes::from(vec![0xca, 0xfe]),
+            signature: Bytes::default(),
+        };
+        let expected = [
+            (EntryPointVersion::V06, USER_OP_HASH_V06),
+            (EntryPointVersion::V07, USER_OP_HASH_V07),
+        ];
+        for (version, hash) in expected {
+            assert_eq!(
+                hash.parse::<H256>().unwrap(),
+                op.hash(version, entry_point(version), 1)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn send_to_mock_bundler() {
+        let hash = H256::repeat_byte(0xab);
+        let server = MockServer::start(move |_, _| Ok(json!(hash)));
+
+        let bundler = Bundler::new(server.url());
+        let op = user_operation();
+        let result = bundler
+            .send_user_operation(&op, EntryPointVersion::V07)
+            .await
+            .unwrap();
+        assert_eq!(hash, result);
+
+        let requests = server.requests();
+        assert_eq!(1, requests.len());
+        let body = &requests[0];
+        assert_eq!("eth_sendUserOperation", body["method"]);
+        assert_eq!(op.to_json(EntryPointVersion::V07), body["params"][0]);
+        assert_eq!(
+            json!(entry_point(EntryPointVersion::V07)),
+            body["params"][1]
+        );
+    }
+}
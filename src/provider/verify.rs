@@ -0,0 +1,390 @@
+//! Verify signatures for externally owned and contract accounts.
+//!
+//! Signatures from externally owned accounts are checked with
+//! `ecrecover`. Contract accounts are asked through the upstream
+//! node using `isValidSignature` (EIP-1271); signatures from
+//! accounts that are not deployed yet are wrapped with the
+//! factory call that deploys them (ERC-6492) and are checked with
+//! a deployless validator in a single `eth_call`.
+use anyhow::{anyhow, bail, Result};
+use ethers_core::{
+    abi::{self, ParamType, Token as AbiToken},
+    types::{Address, Bytes, Signature, H256},
+    utils::hash_message,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::upstream::Upstream;
+
+use super::{
+    sign::decode_data,
+    typed_data::{TypedData, Version},
+    ProviderError,
+};
+
+/// Selector for `isValidSignature(bytes32,bytes)` which is also
+/// the value returned for a valid signature.
+const IS_VALID_SIGNATURE: [u8; 4] = [0x16, 0x26, 0xba, 0x7e];
+
+/// Error code used by nodes for a call that reverted.
+const EXECUTION_REVERTED: i64 = 3;
+
+/// Init code that validates a signature without deploying
+/// anything (ERC-6492 `ValidateSigOffchain`).
+///
+/// With `abi.encode(signer, hash, signature)` appended it calls
+/// the factory of a wrapped signature when the signer has no
+/// code, asks the signer with `isValidSignature` or falls back to
+/// `ecrecover`, and returns `0x01` for a valid signature.
+/// Assembled from `fixtures/validate_sig_offchain.asm`.
+const VALIDATE_SIG_OFFCHAIN: &str = concat!(
+    "61013c380361013c600039604051805190602001602082106100825781810160",
+    "209003517f649264926492649264926492649264926492649264926492649264",
+    "926492649214156100825790506000513b610071576000600082602001518301",
+    "805190602001600085515af115610137575b8060400151018051906020016100",
+    "d5565b6000513b6100d557604182141561013757596020518152816040015160",
+    "001a816020015281518160400152816020015181606001526020816080836001",
+    "5afa503d6020141561013757516000511461012e565b59631626ba7e60e01b81",
+    "526020518160040152604081602401528281604401528281606401848460045a",
+    "fa5082601f0160051c60051b60640160208282846000515afa3d602011151682",
+    "5160e01c631626ba7e14169150505b6000526001601ff35b600080fd",
+);
+
+/// Suffix of signatures wrapped for undeployed accounts.
+const ERC6492_SUFFIX: [u8; 32] = [
+    0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92,
+    0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92,
+    0x64, 0x92, 0x64, 0x92, 0x64, 0x92, 0x64, 0x92,
+];
+
+/// Message that was signed.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum SignedMessage {
+    /// Personal message (EIP-191 version `0x45`); hex encoded
+    /// when prefixed with `0x`.
+    Personal(String),
+    /// Typed data (EIP-712).
+    TypedData(Value),
+    /// Hash signed directly.
+    Hash(H256),
+}
+
+impl SignedMessage {
+    /// Hash that was signed.
+    pub fn hash(&self) -> Result<H256> {
+        match self {
+            Self::Personal(data) => Ok(hash_message(decode_data(data)?)),
+            Self::TypedData(value) => {
+                let typed_data = TypedData::from_value(value.clone())?;
+                typed_data.validate(Version::V4)?;
+                typed_data.sign_hash(Version::V4)
+            }
+            Self::Hash(hash) => Ok(*hash),
+        }
+    }
+}
+
+/// How a signature was verified.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize)]
+pub enum VerificationMethod {
+    #[serde(rename = "ecrecover")]
+    Ecrecover,
+    #[serde(rename = "erc1271")]
+    Erc1271,
+    #[serde(rename = "erc6492")]
+    Erc6492,
+}
+
+/// Result of verifying a signature.
+#[derive(Debug, Clone, Serialize)]
+pub struct Verification {
+    pub valid: bool,
+    pub method: VerificationMethod,
+}
+
+/// Account that signed a hash.
+fn recover(hash: H256, signature: &[u8]) -> Option<Address> {
+    let mut signature = Signature::try_from(signature).ok()?;
+    // Some signers use a recovery identifier of zero or one
+    if signature.v < 27 {
+        signature.v += 27;
+    }
+    signature.recover(hash).ok()
+}
+
+/// Split a signature wrapped for an undeployed account into the
+/// factory, the factory call data and the inner signature.
+fn unwrap_erc6492(signature: &[u8]) -> Result<Option<(Address, Bytes, Bytes)>> {
+    let wrapped = match signature.strip_suffix(&ERC6492_SUFFIX) {
+        Some(wrapped) => wrapped,
+        None => return Ok(None),
+    };
+    let tokens = abi::decode(
+        &[ParamType::Address, ParamType::Bytes, ParamType::Bytes],
+        wrapped,
+    )?;
+    let mut tokens = tokens.into_iter();
+    match (tokens.next(), tokens.next(), tokens.next()) {
+        (
+            Some(AbiToken::Address(factory)),
+            Some(AbiToken::Bytes(factory_data)),
+            Some(AbiToken::Bytes(inner)),
+        ) => Ok(Some((
+            factory,
+            Bytes::from(factory_data),
+            Bytes::from(inner),
+        ))),
+        _ => bail!("invalid ERC-6492 signature"),
+    }
+}
+
+/// Call data for `isValidSignature(hash, signature)`.
+fn is_valid_signature_data(hash: H256, signature: &[u8]) -> Bytes {
+    let mut data = IS_VALID_SIGNATURE.to_vec();
+    data.extend(abi::encode(&[
+        AbiToken::FixedBytes(hash.as_bytes().to_vec()),
+        AbiToken::Bytes(signature.to_vec()),
+    ]));
+    Bytes::from(data)
+}
+
+/// Determine if the result of `isValidSignature` is the magic value.
+fn is_magic_value(result: &[u8]) -> bool {
+    result.len() >= 4 && result[..4] == IS_VALID_SIGNATURE
+}
+
+/// Determine if an upstream error reports a reverted call.
+///
+/// Geth uses code 3 for reverts with data; reverts without data
+/// and other clients only report it in the message.
+fn is_revert(error: &ProviderError) -> bool {
+    error.code == EXECUTION_REVERTED
+        || error.message.to_lowercase().contains("revert")
+}
+
+/// Ask a deployed contract if a signature is valid.
+///
+/// A call that reverts means the signature is invalid; other
+/// errors are returned so an unavailable node is not reported as
+/// an invalid signature.
+async fn call_is_valid_signature(
+    upstream: &Upstream,
+    address: Address,
+    hash: H256,
+    signature: &[u8],
+) -> Result<bool> {
+    let call = json!({
+        "to": address,
+        "data": is_valid_signature_data(hash, signature),
+    });
+    match upstream
+        .call::<Bytes>("eth_call", json!([call, "latest"]))
+        .await
+    {
+        Ok(result) => Ok(is_magic_value(result.as_ref())),
+        Err(e) if is_revert(&e) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Call data that runs the deployless validator.
+fn validate_sig_offchain_data(
+    signer: Address,
+    hash: H256,
+    signature: &[u8],
+) -> Result<Bytes> {
+    let mut data = hex::decode(VALIDATE_SIG_OFFCHAIN)?;
+    data.extend(abi::encode(&[
+        AbiToken::Address(signer),
+        AbiToken::FixedBytes(hash.as_bytes().to_vec()),
+        AbiToken::Bytes(signature.to_vec()),
+    ]));
+    Ok(Bytes::from(data))
+}
+
+/// Check a signature that may belong to an account which is not
+/// deployed yet with the deployless validator.
+///
+/// Nothing is deployed on chain; a call that reverts, for example
+/// because the factory call failed, means the signature is invalid.
+async fn deployless_is_valid_signature(
+    upstream: &Upstream,
+    address: Address,
+    hash: H256,
+    signature: &[u8],
+) -> Result<bool> {
+    let call = json!({
+        "data": validate_sig_offchain_data(address, hash, signature)?,
+    });
+    match upstream
+        .call::<Bytes>("eth_call", json!([call, "latest"]))
+        .await
+    {
+        Ok(result) => Ok(result.as_ref() == [0x01]),
+        Err(e) if is_revert(&e) => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Verify that an account signed a hash.
+///
+/// The upstream node for the chain is only used for signatures
+/// that are not valid for an externally owned account.
+pub async fn verify_signature(
+    chain_id: u64,
+    address: Address,
+    hash: H256,
+    signature: Bytes,
+) -> Result<Verification> {
+    let signature = signature.as_ref();
+
+    // Malformed wrappers are reported instead of being sent upstream
+    if unwrap_erc6492(signature)?.is_some() {
+        let upstream = Upstream::for_chain(chain_id)?;
+        let valid =
+            deployless_is_valid_signature(&upstream, address, hash, signature)
+                .await?;
+        return Ok(Verification {
+            valid,
+            method: VerificationMethod::Erc6492,
+        });
+    }
+
+    if recover(hash, signature) == Some(address) {
+        return Ok(Verification {
+            valid: true,
+            method: VerificationMethod::Ecrecover,
+        });
+    }
+
+    let upstream = Upstream::for_chain(chain_id)?;
+    let code: Bytes = upstream
+        .call("eth_getCode", json!([address, "latest"]))
+        .await?;
+    if code.as_ref().is_empty() {
+        return Ok(Verification {
+            valid: false,
+            method: VerificationMethod::Ecrecover,
+        });
+    }
+    Ok(Verification {
+        valid: call_is_valid_signature(&upstream, address, hash, signature)
+            .await?,
+        method: VerificationMethod::Erc1271,
+    })
+}
+
+/// Verify a signature for a message signed by an account.
+pub async fn verify_message(
+    chain_id: u64,
+    address: &str,
+    message: &SignedMessage,
+    signature: &str,
+) -> Result<Verification> {
+    let address = address
+        .parse::<Address>()
+        .map_err(|_| anyhow!("invalid address {}", address))?;
+    let signature = signature
+        .parse::<Bytes>()
+        .map_err(|_| anyhow!("signature must be hex encoded"))?;
+    verify_signature(chain_id, address, message.hash()?, signature).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::provider::mock::MockServer;
+    use ethers_signers::{LocalWallet, Signer};
+
+    #[test]
+    fn recover_and_unwrap_signatures() -> Result<()> {
+        let wallet: LocalWallet =
+            "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318"
+                .parse()?;
+        let hash = SignedMessage::Personal("Hello world".to_string()).hash()?;
+        assert_eq!(
+            hash,
+            SignedMessage::Personal("0x48656c6c6f20776f726c64".to_string())
+                .hash()?
+        );
+
+        let mut signature = wallet.sign_hash(hash, false).to_vec();
+        assert_eq!(Some(wallet.address()), recover(hash, &signature));
+        signature[64] -= 27;
+        assert_eq!(Some(wallet.address()), recover(hash, &signature));
+        assert_ne!(Some(wallet.address()), recover(H256::zero(), &signature));
+        assert_eq!(None, recover(hash, &signature[..64]));
+        assert!(unwrap_erc6492(&signature)?.is_none());
+
+        let factory = Address::repeat_byte(0x11);
+        let mut wrapped = abi::encode(&[
+            AbiToken::Address(factory),
+            AbiToken::Bytes(vec![0xde, 0xad]),
+            AbiToken::Bytes(signature.clone()),
+        ]);
+        wrapped.extend_from_slice(&ERC6492_SUFFIX);
+        let (unwrapped_factory, factory_data, inner) =
+            unwrap_erc6492(&wrapped)?.unwrap();
+        assert_eq!(factory, unwrapped_factory);
+        assert_eq!(&[0xde, 0xad], factory_data.as_ref());
+        assert_eq!(signature, inner.to_vec());
+
+        assert!(is_magic_value(&is_valid_signature_data(hash, &[])));
+        assert!(!is_magic_value(&[0xff; 32]));
+
+        // Only reverted calls mean the signature is invalid
+        assert!(is_revert(&ProviderError::new(3, "execution reverted")));
+        assert!(is_revert(&ProviderError::new(-32000, "execution reverted")));
+        assert!(is_revert(&ProviderError::new(
+            -32015,
+            "VM execution error: Reverted",
+        )));
+        assert!(!is_revert(&ProviderError::new(-32005, "limit exceeded")));
+        assert!(!is_revert(&ProviderError::internal("header not found")));
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn call_deployless_validator() -> Result<()> {
+        let address = Address::repeat_byte(0xaa);
+        let hash = H256::repeat_byte(0x01);
+        let mut signature = abi::encode(&[
+            AbiToken::Address(Address::repeat_byte(0x11)),
+            AbiToken::Bytes(vec![0xde, 0xad]),
+            AbiToken::Bytes(vec![0xbe, 0xef]),
+        ]);
+        signature.extend_from_slice(&ERC6492_SUFFIX);
+
+        let data = validate_sig_offchain_data(address, hash, &signature)?;
+        let validator = hex::decode(VALIDATE_SIG_OFFCHAIN)?;
+        assert_eq!(&validator[..], &data.as_ref()[..validator.len()]);
+
+        let results = [
+            (Ok(json!("0x01")), Some(true)),
+            (Ok(json!("0x00")), Some(false)),
+            (
+                Err(ProviderError::new(3, "execution reverted")),
+                Some(false),
+            ),
+            (Err(ProviderError::internal("header not found")), None),
+        ];
+        for (result, expected) in results {
+            let server = MockServer::start(move |_, _| result.clone());
+            let upstream = server.upstream(1);
+            let valid = deployless_is_valid_signature(
+                &upstream, address, hash, &signature,
+            )
+            .await;
+            assert_eq!(expected, valid.ok());
+
+            // The validator is deployed in the call, not sent to a contract
+            let request = &server.requests()[0];
+            assert_eq!("eth_call", request["method"]);
+            assert_eq!(None, request["params"][0].get("to"));
+            assert_eq!(json!(data), request["params"][0]["data"]);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,71 @@
+//! Unlock account keys for signing.
+use ethers_core::types::Address;
+use ethers_signers::{LocalWallet, Wallet};
+use zeroize::Zeroizing;
+
+use crate::user::USER_DATA;
+
+use super::{accounts, Context, ProviderError, ProviderResult};
+
+/// Parse an account address.
+pub fn parse_address(address: &str) -> ProviderResult<Address> {
+    address.parse::<Address>().map_err(|_| {
+        ProviderError::invalid_params(format!("invalid address {}", address))
+    })
+}
+
+/// Ensure an account has been exposed to a client.
+pub fn ensure_connected(ctx: &Context, address: &str) -> ProviderResult<()> {
+    let connected = accounts::accounts(ctx)
+        .iter()
+        .any(|a| a.eq_ignore_ascii_case(address));
+    if !connected {
+        return Err(ProviderError::unauthorized());
+    }
+    Ok(())
+}
+
+/// Decrypt the keystore for an account.
+///
+/// Key derivation is expensive so it runs on a blocking thread.
+pub async fn unlock(
+    address: &str,
+    passphrase: String,
+) -> ProviderResult<LocalWallet> {
+    let file = {
+        let user = USER_DATA.read().unwrap();
+        user.keystore_file(address)?
+    };
+    tokio::task::spawn_blocking(move || {
+        Wallet::decrypt_keystore(file, &passphrase)
+    })
+    .await
+    .map_err(ProviderError::internal)?
+    .map_err(ProviderError::internal)
+}
+
+/// Decrypt the private key for an account from the keystore.
+///
+/// Used for schemes other than ECDSA signing which need the raw
+/// key bytes; the key is zeroed when dropped.
+pub async fn unlock_key(
+    address: &str,
+    passphrase: String,
+) -> ProviderResult<Zeroizing<[u8; 32]>> {
+    let file = {
+        let user = USER_DATA.read().unwrap();
+        user.keystore_file(address)?
+    };
+    let key = tokio::task::spawn_blocking(move || {
+        eth_keystore::decrypt_key(file, &passphrase).map(Zeroizing::new)
+    })
+    .await
+    .map_err(ProviderError::internal)?
+    .map_err(ProviderError::internal)?;
+    if key.len() != 32 {
+        return Err(ProviderError::internal("invalid private key length"));
+    }
+    let mut secret = Zeroizing::new([0u8; 32]);
+    secret.copy_from_slice(&key);
+    Ok(secret)
+}
@@ -23,7 +23,7 @@ struct CoinCapRateData {
 }
 
 /// Fetch the ETH/USD exchange rate.
-pub async fn eth_usd() -> Result<()> {
+pub async fn eth_usd() -> Result<f64> {
     let url = format!("{}/rates/ethereum", COINCAP_ENDPOINT);
     let client = reqwest::Client::builder().build()?;
     let result = client
@@ -33,5 +33,5 @@ pub async fn eth_usd() -> Result<()> {
         .await?
         .json::<CoinCapRate>()
         .await?;
-    Ok(())
+    Ok(result.data.rate_usd.parse()?)
 }
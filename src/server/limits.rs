@@ -0,0 +1,347 @@
+//! Rate limits for provider requests.
+//!
+//! Every client and every access token has a token bucket for
+//! requests that only read chain data and a separate, smaller
+//! bucket for requests that may prompt the owner. Each request
+//! in a batch takes one token; a message is rejected as a whole
+//! when any bucket it needs is empty, and a batch that needs more
+//! tokens than a bucket holds is never accepted. Limits are
+//! configured for each registered client and stored next to the
+//! user data.
+use anyhow::{bail, Result};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use crate::provider::requires_consent;
+
+const RATE_LIMITS: &str = "rate_limits.json";
+
+/// Buckets that have not been used for this long are removed.
+const IDLE_TIMEOUT: Duration = Duration::from_secs(3600);
+
+pub static RATE_LIMIT_CONFIG: Lazy<RwLock<RateLimitConfig>> = Lazy::new(|| {
+    RwLock::new(RateLimitConfig::load().unwrap_or_else(|e| {
+        log::warn!("could not load rate limit config: {}", e);
+        Default::default()
+    }))
+});
+
+static BUCKETS: Lazy<Mutex<HashMap<BucketKey, Bucket>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+/// Number of requests allowed over time.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Quota {
+    /// Sustained number of requests each minute.
+    pub per_minute: u32,
+    /// Number of requests that may be sent at once.
+    pub burst: u32,
+}
+
+impl Quota {
+    const fn new(per_minute: u32, burst: u32) -> Self {
+        Self { per_minute, burst }
+    }
+}
+
+/// Reason a message was rejected by the rate limits.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum Exceeded {
+    /// A bucket does not have enough tokens yet.
+    RetryAfter(Duration),
+    /// The message needs more tokens than the burst of a bucket so
+    /// retrying cannot succeed.
+    Burst(u32),
+}
+
+/// Quotas for each class of request.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct Quotas {
+    /// Requests that only read chain data.
+    pub read: Quota,
+    /// Requests that may ask the owner for consent.
+    pub sign: Quota,
+}
+
+/// Limits for a registered client.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ClientLimits {
+    /// Shared by every access token issued to the client.
+    pub client: Quotas,
+    /// Applied to each access token.
+    pub token: Quotas,
+}
+
+impl Default for ClientLimits {
+    fn default() -> Self {
+        Self {
+            client: Quotas {
+                read: Quota::new(1200, 200),
+                sign: Quota::new(30, 5),
+            },
+            token: Quotas {
+                read: Quota::new(600, 100),
+                sign: Quota::new(20, 5),
+            },
+        }
+    }
+}
+
+impl ClientLimits {
+    fn validate(&self) -> Result<()> {
+        let quotas = [
+            self.client.read,
+            self.client.sign,
+            self.token.read,
+            self.token.sign,
+        ];
+        if quotas.iter().any(|q| q.per_minute == 0 || q.burst == 0) {
+            bail!("rate limits must not be zero");
+        }
+        Ok(())
+    }
+}
+
+/// Rate limits for each client.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct RateLimitConfig {
+    /// Map client identifiers to limits; clients that are not
+    /// configured use the default limits.
+    clients: BTreeMap<String, ClientLimits>,
+}
+
+impl RateLimitConfig {
+    /// Load the configuration from disc.
+    fn load() -> Result<Self> {
+        let file = crate::user::storage()?.join(RATE_LIMITS);
+        if file.is_file() {
+            let contents = std::fs::read_to_string(file)?;
+            return Ok(serde_json::from_str(&contents)?);
+        }
+        Ok(Default::default())
+    }
+
+    /// Save the configuration to disc.
+    fn save(&self) -> Result<()> {
+        let file = crate::user::storage()?.join(RATE_LIMITS);
+        let contents = serde_json::to_string_pretty(self)?;
+        std::fs::write(file, contents)?;
+        Ok(())
+    }
+
+    /// Limits for a client.
+    pub fn limits(&self, client_id: &str) -> ClientLimits {
+        self.clients.get(client_id).copied().unwrap_or_default()
+    }
+
+    /// Set the limits for a client.
+    pub fn set_limits(
+        &mut self,
+        client_id: &str,
+        limits: ClientLimits,
+    ) -> Result<()> {
+        limits.validate()?;
+        self.clients.insert(client_id.to_string(), limits);
+        self.save()
+    }
+
+    /// Restore the default limits for a client.
+    pub fn remove_limits(&mut self, client_id: &str) -> Result<()> {
+        self.clients.remove(client_id);
+        self.save()
+    }
+}
+
+/// Class of a request.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Hash)]
+enum Class {
+    Read,
+    Sign,
+}
+
+/// Identifies the bucket for a client or an access token.
+#[derive(Debug, Clone, Eq, PartialEq, Hash)]
+enum BucketKey {
+    Client(String, Class),
+    Token(String, Class),
+}
+
+/// Token bucket refilled at a constant rate.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(quota: Quota, now: Instant) -> Self {
+        Self {
+            tokens: quota.burst as f64,
+            updated: now,
+        }
+    }
+
+    /// Add the tokens accrued since the last update.
+    fn refill(&mut self, quota: Quota, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated);
+        let accrued = elapsed.as_secs_f64() * quota.per_minute as f64 / 60.0;
+        self.tokens = (self.tokens + accrued).min(quota.burst as f64);
+        self.updated = now;
+    }
+
+    /// Time until a number of tokens are available.
+    fn wait_time(&self, quota: Quota, count: u32) -> Option<Duration> {
+        let missing = count as f64 - self.tokens;
+        if missing <= 0.0 {
+            return None;
+        }
+        Some(Duration::from_secs_f64(
+            missing * 60.0 / quota.per_minute as f64,
+        ))
+    }
+}
+
+/// Count the requests in a message by class.
+fn count(message: &Value) -> HashMap<Class, u32> {
+    let requests = match message {
+        Value::Array(requests) => requests.iter().collect(),
+        request => vec![request],
+    };
+    let mut counts = HashMap::new();
+    for request in requests {
+        let method = request.get("method").and_then(Value::as_str);
+        let class = match method {
+            Some(method) if requires_consent(method) => Class::Sign,
+            _ => Class::Read,
+        };
+        *counts.entry(class).or_insert(0) += 1;
+    }
+    counts
+}
+
+/// Take tokens from every bucket a message needs.
+///
+/// No tokens are taken when a bucket does not have enough tokens
+/// or the message can never fit in a bucket.
+fn take(
+    buckets: &mut HashMap<BucketKey, Bucket>,
+    limits: &ClientLimits,
+    client_id: &str,
+    token: &str,
+    message: &Value,
+    now: Instant,
+) -> Result<(), Exceeded> {
+    buckets.retain(|_, bucket| {
+        now.saturating_duration_since(bucket.updated) < IDLE_TIMEOUT
+    });
+
+    let mut needed = vec![];
+    for (class, count) in count(message) {
+        let (client_quota, token_quota) = match class {
+            Class::Read => (limits.client.read, limits.token.read),
+            Class::Sign => (limits.client.sign, limits.token.sign),
+        };
+        needed.push((
+            BucketKey::Client(client_id.to_string(), class),
+            client_quota,
+            count,
+        ));
+        needed.push((
+            BucketKey::Token(token.to_string(), class),
+            token_quota,
+            count,
+        ));
+    }
+
+    let burst = needed
+        .iter()
+        .filter(|(_, quota, count)| *count > quota.burst)
+        .map(|(_, quota, _)| quota.burst)
+        .min();
+    if let Some(burst) = burst {
+        return Err(Exceeded::Burst(burst));
+    }
+
+    let mut retry_after = None;
+    for (key, quota, count) in &needed {
+        let bucket = buckets
+            .entry(key.clone())
+            .or_insert_with(|| Bucket::new(*quota, now));
+        bucket.refill(*quota, now);
+        if let Some(wait) = bucket.wait_time(*quota, *count) {
+            retry_after = retry_after.max(Some(wait));
+        }
+    }
+    if let Some(retry_after) = retry_after {
+        return Err(Exceeded::RetryAfter(retry_after));
+    }
+
+    for (key, _, count) in needed {
+        if let Some(bucket) = buckets.get_mut(&key) {
+            bucket.tokens -= count as f64;
+        }
+    }
+    Ok(())
+}
+
+/// Check the rate limits for a message from a client.
+pub fn check(
+    client_id: &str,
+    token: &str,
+    message: &Value,
+) -> Result<(), Exceeded> {
+    let limits = RATE_LIMIT_CONFIG.read().unwrap().limits(client_id);
+    let mut buckets = BUCKETS.lock().unwrap();
+    take(
+        &mut buckets,
+        &limits,
+        client_id,
+        token,
+        message,
+        Instant::now(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn limit_requests() {
+        let limits = ClientLimits::default();
+        let mut buckets = HashMap::new();
+        let now = Instant::now();
+        let sign = json!({ "method": "personal_sign" });
+        let read = json!([{ "method": "eth_blockNumber" }]);
+
+        for _ in 0..limits.token.sign.burst {
+            assert!(take(&mut buckets, &limits, "a", "t1", &sign, now).is_ok());
+        }
+        let retry_after =
+            take(&mut buckets, &limits, "a", "t1", &sign, now).unwrap_err();
+        assert_eq!(Exceeded::RetryAfter(Duration::from_secs(3)), retry_after);
+
+        // Reads use a separate budget
+        assert!(take(&mut buckets, &limits, "a", "t1", &read, now).is_ok());
+
+        // Another token of the same client shares the client budget
+        assert!(take(&mut buckets, &limits, "a", "t2", &sign, now).is_err());
+        assert!(take(&mut buckets, &limits, "b", "t3", &sign, now).is_ok());
+
+        let later = now + Duration::from_secs(3);
+        assert!(take(&mut buckets, &limits, "a", "t1", &sign, later).is_ok());
+
+        // A batch larger than the burst is never accepted
+        let batch = json!(vec![json!({ "method": "eth_sign" }); 6]);
+        assert_eq!(
+            Err(Exceeded::Burst(limits.token.sign.burst)),
+            take(&mut buckets, &limits, "c", "t4", &batch, later)
+        );
+    }
+}
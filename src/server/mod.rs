@@ -9,9 +9,14 @@ use anyhow::Result;
 use std::net::{SocketAddr, ToSocketAddrs};
 use tokio::sync::oneshot;
 
+use crate::{provider::SCOPES, upstream};
+
 mod assets;
+pub(crate) mod limits;
 mod oauth;
 mod rpc;
+mod sse;
+mod ws;
 
 #[actix_web::main]
 pub async fn server<A: ToSocketAddrs>(
@@ -24,12 +29,15 @@ pub async fn server<A: ToSocketAddrs>(
     pkce_setup.register_client(
         "LocalClient",
         "http://localhost:7778/".parse::<url::Url>()?,
-        "default-scope",
+        &SCOPES.join(" "),
     );
 
     // Start the actor
     let pkce_agent = pkce_setup.start();
 
+    // Probe the upstream nodes in the background
+    upstream::health::spawn();
+
     let server = HttpServer::new(move || {
         let origins = vec![
             "http://localhost:7777",
@@ -63,6 +71,10 @@ pub async fn server<A: ToSocketAddrs>(
                     .route("/refresh", web::post().to(oauth::post_refresh)),
             )
             .service(web::resource("/rpc").route(web::post().to(rpc::handler)))
+            .service(web::resource("/ws").route(web::get().to(ws::handler)))
+            .service(
+                web::resource("/events").route(web::get().to(sse::handler)),
+            )
             .service(
                 web::resource("/{tail:.*}")
                     .route(web::get().to(assets::handler)),
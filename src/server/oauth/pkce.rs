@@ -16,6 +16,9 @@ use oxide_auth_actix::{
 };
 use std::borrow::Cow;
 
+use crate::provider::READ_SCOPE;
+use crate::user::USER_DATA;
+
 pub enum Extras {
     AuthGet,
     AuthPost(String),
@@ -35,7 +38,7 @@ impl Actor for PkceSetup {
 
 impl PkceSetup {
     pub fn new() -> PkceSetup {
-        let scopes: Vec<Scope> = vec!["default-scope".parse().unwrap()];
+        let scopes: Vec<Scope> = vec![READ_SCOPE.parse().unwrap()];
 
         let registrar = ClientMap::new();
         let authorizer = AuthMap::new(RandomGenerator::new(16));
@@ -126,10 +129,16 @@ where
             Extras::AuthPost(query_string) => {
                 let solicitor = FnSolicitor(
                     move |_: &mut OAuthRequest, _: Solicitation| {
-                        if query_string.contains("allow") {
-                            OwnerConsent::Authorized("dummy user".to_owned())
-                        } else {
-                            OwnerConsent::Denied
+                        if !query_string.contains("allow") {
+                            return OwnerConsent::Denied;
+                        }
+                        // Grants belong to the logged in user
+                        let user = USER_DATA.read().unwrap();
+                        match user.owner_id() {
+                            Some(owner_id) => {
+                                OwnerConsent::Authorized(owner_id)
+                            }
+                            None => OwnerConsent::Denied,
                         }
                     },
                 );
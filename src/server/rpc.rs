@@ -1,33 +1,149 @@
 use actix::Addr;
-use actix_web::web;
+use actix_web::{http::header, web, Either, HttpRequest, HttpResponse};
 use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::time::Duration;
 
+use oxide_auth::primitives::grant::Grant;
 use oxide_auth_actix::{
     OAuthOperation, OAuthResource, OAuthResponse, Resource, WebError,
 };
 
+use super::limits::{self, Exceeded};
 use super::oauth::{Extras, PkceSetup};
+use crate::provider::{self, Context, ProviderError, Response};
 
-static DENY_TEXT: &str = "NOT_AUTHORIZED";
+static JSON_TYPE: &str = "application/json";
 
-pub fn create_session() -> Result<OAuthResponse, WebError> {
-    // TODO: expose RPC API
-    Ok(OAuthResponse::ok()
-        .content_type("text/plain")?
-        .body("Logged in"))
+/// Write a JSON-RPC reply into a response.
+fn reply<T: Serialize>(
+    response: OAuthResponse,
+    reply: &T,
+) -> Result<OAuthResponse, WebError> {
+    let body = serde_json::to_string(reply)
+        .expect("failed to serialize JSON-RPC response");
+    Ok(response.content_type(JSON_TYPE)?.body(&body))
 }
 
-/// Handles JSON-RPC POST requests.
-pub(crate) async fn handler(
-    req: OAuthResource,
-    state: web::Data<Addr<PkceSetup>>,
+/// Dispatch a message to the provider.
+///
+/// The message may be a single request or a batch of requests.
+async fn create_session(
+    ctx: &Context,
+    message: Value,
 ) -> Result<OAuthResponse, WebError> {
+    match provider::handle_message(ctx, message).await {
+        Some(response) => reply(OAuthResponse::ok(), &response),
+        None => Ok(OAuthResponse::ok()),
+    }
+}
+
+/// Access token sent with a request.
+pub(super) fn bearer_token(req: &HttpRequest) -> &str {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or_default()
+}
+
+/// Identifier of a single request; batches use null.
+pub(super) fn request_id(message: &Value) -> Value {
+    message.get("id").cloned().unwrap_or(Value::Null)
+}
+
+/// Seconds a client must wait before retrying.
+fn retry_seconds(retry_after: Duration) -> u64 {
+    retry_after.as_secs_f64().ceil().max(1.0) as u64
+}
+
+/// Error for a message rejected by the rate limits.
+///
+/// A batch larger than the burst is an invalid request as it is
+/// rejected however long the client waits.
+pub(super) fn limit_error(exceeded: Exceeded) -> ProviderError {
+    match exceeded {
+        Exceeded::RetryAfter(retry_after) => {
+            ProviderError::limit_exceeded(format!(
+                "rate limit exceeded, retry after {} seconds",
+                retry_seconds(retry_after)
+            ))
+        }
+        Exceeded::Burst(burst) => ProviderError::invalid_request(format!(
+            "batch exceeds burst of {} requests",
+            burst
+        )),
+    }
+}
+
+/// Response for a client that exceeded a rate limit.
+fn too_many_requests(id: Value, retry_after: Duration) -> HttpResponse {
+    let error = limit_error(Exceeded::RetryAfter(retry_after));
+    HttpResponse::TooManyRequests()
+        .insert_header((
+            header::RETRY_AFTER,
+            retry_seconds(retry_after).to_string(),
+        ))
+        .content_type(JSON_TYPE)
+        .body(json!(Response::error(id, error)).to_string())
+}
+
+/// Authorize a request using the bearer token.
+///
+/// When access is denied the response to send is returned
+/// as the error.
+pub(super) async fn authorize(
+    req: OAuthResource,
+    state: &Addr<PkceSetup>,
+) -> Result<Result<Grant, OAuthResponse>, WebError> {
     let resource = state
         .send(Resource(req.into_request()).wrap(Extras::Nothing))
         .await?;
     match resource {
-        Ok(_grant) => create_session(),
-        Err(Ok(response)) => Ok(response.body(DENY_TEXT)),
+        Ok(grant) => Ok(Ok(grant)),
+        Err(Ok(response)) => Ok(Err(reply(
+            response,
+            &Response::error(Value::Null, ProviderError::unauthorized()),
+        )?)),
         Err(Err(e)) => Err(e.into()),
     }
 }
+
+/// Handles JSON-RPC POST requests.
+///
+/// Requests are counted against the rate limits of the client
+/// and the access token before they are dispatched.
+pub(crate) async fn handler(
+    req: HttpRequest,
+    resource: OAuthResource,
+    body: web::Bytes,
+    state: web::Data<Addr<PkceSetup>>,
+) -> actix_web::Result<Either<OAuthResponse, HttpResponse>> {
+    let grant = match authorize(resource, &state).await? {
+        Ok(grant) => grant,
+        Err(response) => return Ok(Either::Left(response)),
+    };
+    let ctx = Context::from(&grant);
+
+    let message = match serde_json::from_slice::<Value>(&body) {
+        Ok(message) => message,
+        Err(e) => {
+            let response =
+                Response::error(Value::Null, ProviderError::parse(e));
+            return Ok(Either::Left(reply(OAuthResponse::ok(), &response)?));
+        }
+    };
+    let id = request_id(&message);
+    match limits::check(&ctx.client_id, bearer_token(&req), &message) {
+        Ok(()) => {}
+        Err(Exceeded::RetryAfter(retry_after)) => {
+            return Ok(Either::Right(too_many_requests(id, retry_after)));
+        }
+        Err(exceeded) => {
+            let response = Response::error(id, limit_error(exceeded));
+            return Ok(Either::Left(reply(OAuthResponse::ok(), &response)?));
+        }
+    }
+    Ok(Either::Left(create_session(&ctx, message).await?))
+}
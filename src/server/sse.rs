@@ -0,0 +1,104 @@
+//! Server-sent events for provider clients.
+//!
+//! The stream starts with the current state of the client and
+//! then emits EIP-1193 events as the wallet changes; it ends when
+//! the owner revokes the connection of the client.
+use actix::Addr;
+use actix_web::{web, web::Bytes, Either, HttpResponse};
+use futures::stream;
+use std::collections::VecDeque;
+use std::time::Duration;
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+
+use oxide_auth_actix::{OAuthResource, OAuthResponse};
+
+use super::{oauth::PkceSetup, rpc::authorize};
+use crate::events::{self, WalletEvent};
+use crate::provider::{Context, Listener, ProviderEvent};
+
+/// Interval for comments that keep idle connections open.
+const KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// State of an event stream.
+struct EventStream {
+    client_id: String,
+    listener: Listener,
+    receiver: Receiver<WalletEvent>,
+    queue: VecDeque<Bytes>,
+    closed: bool,
+}
+
+impl EventStream {
+    fn push(&mut self, events: Vec<ProviderEvent>) {
+        self.queue.extend(events.into_iter().map(|event| {
+            Bytes::from(format!(
+                "event: {}\ndata: {}\n\n",
+                event.name, event.data
+            ))
+        }));
+    }
+
+    /// Wait for the next chunk to send to the client.
+    async fn next(&mut self) -> Option<Bytes> {
+        loop {
+            if let Some(chunk) = self.queue.pop_front() {
+                return Some(chunk);
+            }
+            if self.closed {
+                return None;
+            }
+            match tokio::time::timeout(KEEP_ALIVE, self.receiver.recv()).await {
+                Err(_) => return Some(Bytes::from_static(b": keep-alive\n\n")),
+                Ok(Ok(event)) => {
+                    if let WalletEvent::Revoked { client_id } = &event {
+                        self.closed = client_id == &self.client_id;
+                    }
+                    let events = self.listener.update(&event);
+                    self.push(events);
+                }
+                Ok(Err(RecvError::Lagged(_))) => {
+                    let events = self.listener.refresh();
+                    self.push(events);
+                }
+                Ok(Err(RecvError::Closed)) => return None,
+            }
+        }
+    }
+}
+
+/// Stream provider events to an authorized client.
+pub(crate) async fn handler(
+    req: OAuthResource,
+    state: web::Data<Addr<PkceSetup>>,
+) -> actix_web::Result<Either<OAuthResponse, HttpResponse>> {
+    let grant = match authorize(req, &state).await? {
+        Ok(grant) => grant,
+        Err(response) => return Ok(Either::Left(response)),
+    };
+
+    let ctx = Context::from(&grant);
+    // Listen before reading the state so no change is missed
+    let receiver = events::subscribe();
+    let client_id = ctx.client_id.clone();
+    let (listener, initial) = Listener::new(ctx);
+    let mut events = EventStream {
+        client_id,
+        listener,
+        receiver,
+        queue: VecDeque::new(),
+        closed: false,
+    };
+    events.push(initial);
+
+    let body = stream::unfold(events, |mut events| async move {
+        let chunk = events.next().await?;
+        Some((Ok::<_, actix_web::Error>(chunk), events))
+    });
+
+    Ok(Either::Right(
+        HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .streaming(body),
+    ))
+}
@@ -0,0 +1,269 @@
+//! WebSocket transport for the provider.
+//!
+//! Accepts the same messages as the `/rpc` endpoint and adds
+//! `eth_subscribe` and `eth_unsubscribe`. Messages count against
+//! the same rate limits using the access token of the upgrade
+//! request. Subscriptions belong to the connection and are
+//! cancelled when it is closed.
+use actix::{
+    Actor, ActorContext, ActorFutureExt, Addr, AsyncContext, Handler, Message,
+    StreamHandler, WrapFuture,
+};
+use actix_web::{web, Either, HttpRequest, HttpResponse};
+use actix_web_actors::ws;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+use oxide_auth_actix::{OAuthResource, OAuthResponse};
+
+use super::{
+    limits,
+    oauth::PkceSetup,
+    rpc::{authorize, bearer_token, limit_error, request_id},
+};
+use crate::provider::{
+    self, Context, ProviderError, ProviderResult, Request, Response,
+    Subscription,
+};
+
+/// Interval between pings sent to the client.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Close the connection when the client does not respond
+/// to pings within this time.
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Maximum number of subscriptions for a connection.
+const MAX_SUBSCRIPTIONS: usize = 32;
+
+/// Event for a subscription.
+#[derive(Message)]
+#[rtype(result = "()")]
+struct Notification {
+    subscription: String,
+    result: Value,
+}
+
+/// Connection from an authorized client.
+struct Session {
+    ctx: Context,
+    /// Access token the connection was authorized with.
+    token: String,
+    heartbeat: Instant,
+    /// Cancel the task for each subscription.
+    subscriptions: HashMap<String, oneshot::Sender<()>>,
+}
+
+impl Session {
+    fn new(ctx: Context, token: String) -> Self {
+        Self {
+            ctx,
+            token,
+            heartbeat: Instant::now(),
+            subscriptions: HashMap::new(),
+        }
+    }
+
+    /// Ping the client and close the connection if it has
+    /// stopped responding.
+    fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
+        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+            if Instant::now().duration_since(act.heartbeat) > CLIENT_TIMEOUT {
+                log::debug!("websocket client {} timed out", act.ctx.client_id);
+                ctx.stop();
+                return;
+            }
+            ctx.ping(b"");
+        });
+    }
+
+    /// Handle a text message from the client.
+    ///
+    /// Messages over the rate limits are rejected. Subscription
+    /// methods are handled by the session, other messages are
+    /// dispatched to the provider.
+    fn message(&mut self, text: &str, ctx: &mut ws::WebsocketContext<Self>) {
+        let message = match serde_json::from_str::<Value>(text) {
+            Ok(message) => message,
+            Err(e) => {
+                let response =
+                    Response::error(Value::Null, ProviderError::parse(e));
+                ctx.text(json!(response).to_string());
+                return;
+            }
+        };
+        if let Err(exceeded) =
+            limits::check(&self.ctx.client_id, &self.token, &message)
+        {
+            let response =
+                Response::error(request_id(&message), limit_error(exceeded));
+            ctx.text(json!(response).to_string());
+            return;
+        }
+
+        let method = message.get("method").and_then(Value::as_str);
+        if let Some("eth_subscribe" | "eth_unsubscribe") = method {
+            let request = match Request::from_value(message) {
+                Ok(request) => request,
+                Err(e) => {
+                    let response = Response::error(Value::Null, e);
+                    ctx.text(json!(response).to_string());
+                    return;
+                }
+            };
+            let result = if request.method() == "eth_subscribe" {
+                self.subscribe(&request, ctx)
+            } else {
+                self.unsubscribe(&request)
+            };
+            if let Some(id) = request.id() {
+                let response = Response::new(id.clone(), result);
+                ctx.text(json!(response).to_string());
+            }
+            return;
+        }
+
+        let provider_ctx = self.ctx.clone();
+        let reply = async move {
+            provider::handle_message(&provider_ctx, message).await
+        };
+        ctx.spawn(reply.into_actor(self).map(|reply, _act, ctx| {
+            if let Some(reply) = reply {
+                ctx.text(reply.to_string());
+            }
+        }));
+    }
+
+    /// Start a subscription.
+    fn subscribe(
+        &mut self,
+        request: &Request,
+        ctx: &mut ws::WebsocketContext<Self>,
+    ) -> ProviderResult<Value> {
+        if self.subscriptions.len() >= MAX_SUBSCRIPTIONS {
+            return Err(ProviderError::limit_exceeded(format!(
+                "at most {} subscriptions per connection",
+                MAX_SUBSCRIPTIONS
+            )));
+        }
+
+        let subscription = Subscription::new(&self.ctx, request)?;
+        let id = subscription.id().to_string();
+        let (cancel, cancelled) = oneshot::channel();
+        let addr = ctx.address();
+        let subscription_id = id.clone();
+        actix::spawn(subscription.run(cancelled, move |result| {
+            if !addr.connected() {
+                return false;
+            }
+            addr.do_send(Notification {
+                subscription: subscription_id.clone(),
+                result,
+            });
+            true
+        }));
+
+        self.subscriptions.insert(id.clone(), cancel);
+        Ok(json!(id))
+    }
+
+    /// Cancel a subscription.
+    fn unsubscribe(&mut self, request: &Request) -> ProviderResult<Value> {
+        let (id,): (String,) = request.params()?;
+        // Dropping the sender cancels the subscription task
+        Ok(json!(self.subscriptions.remove(&id).is_some()))
+    }
+}
+
+impl Actor for Session {
+    type Context = ws::WebsocketContext<Self>;
+
+    fn started(&mut self, ctx: &mut Self::Context) {
+        self.heartbeat(ctx);
+    }
+
+    fn stopped(&mut self, _ctx: &mut Self::Context) {
+        log::debug!(
+            "websocket client {} closed, cancelling {} subscription(s)",
+            self.ctx.client_id,
+            self.subscriptions.len()
+        );
+        self.subscriptions.clear();
+    }
+}
+
+impl Handler<Notification> for Session {
+    type Result = ();
+
+    fn handle(&mut self, msg: Notification, ctx: &mut Self::Context) {
+        // Ignore events that were queued before unsubscribing
+        if !self.subscriptions.contains_key(&msg.subscription) {
+            return;
+        }
+        let notification = json!({
+            "jsonrpc": "2.0",
+            "method": "eth_subscription",
+            "params": {
+                "subscription": msg.subscription,
+                "result": msg.result,
+            },
+        });
+        ctx.text(notification.to_string());
+    }
+}
+
+impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for Session {
+    fn handle(
+        &mut self,
+        msg: Result<ws::Message, ws::ProtocolError>,
+        ctx: &mut Self::Context,
+    ) {
+        match msg {
+            Ok(ws::Message::Ping(bytes)) => {
+                self.heartbeat = Instant::now();
+                ctx.pong(&bytes);
+            }
+            Ok(ws::Message::Pong(_)) => {
+                self.heartbeat = Instant::now();
+            }
+            Ok(ws::Message::Text(text)) => self.message(&text, ctx),
+            Ok(ws::Message::Binary(_)) => {
+                let response = Response::error(
+                    Value::Null,
+                    ProviderError::invalid_request(
+                        "binary messages are not supported",
+                    ),
+                );
+                ctx.text(json!(response).to_string());
+            }
+            Ok(ws::Message::Close(reason)) => {
+                ctx.close(reason);
+                ctx.stop();
+            }
+            Ok(_) => {}
+            Err(e) => {
+                log::warn!("websocket protocol error: {}", e);
+                ctx.stop();
+            }
+        }
+    }
+}
+
+/// Upgrade an authorized request to a WebSocket connection.
+pub(crate) async fn handler(
+    req: HttpRequest,
+    resource: OAuthResource,
+    stream: web::Payload,
+    state: web::Data<Addr<PkceSetup>>,
+) -> actix_web::Result<Either<OAuthResponse, HttpResponse>> {
+    match authorize(resource, &state).await? {
+        Ok(grant) => {
+            let token = bearer_token(&req).to_string();
+            let session = Session::new(Context::from(&grant), token);
+            Ok(Either::Right(ws::start(session, &req, stream)?))
+        }
+        Err(response) => Ok(Either::Left(response)),
+    }
+}
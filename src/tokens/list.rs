@@ -0,0 +1,201 @@
+//! Token lists in the Uniswap token list format.
+//!
+//! Lists are checked against the constraints of the token list
+//! JSON schema (https://uniswap.org/tokenlist.schema.json) and
+//! every token must also be valid for the token store.
+use anyhow::{anyhow, bail, Result};
+use ethers_core::types::Address;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+
+use super::{Token, TokenStandard};
+
+const MAX_NAME_LENGTH: usize = 30;
+const MAX_TOKEN_NAME_LENGTH: usize = 40;
+const MAX_TOKENS: usize = 10_000;
+
+/// Version of a token list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Version {
+    pub major: u32,
+    pub minor: u32,
+    pub patch: u32,
+}
+
+/// Token in a token list.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TokenInfo {
+    pub chain_id: u64,
+    pub address: String,
+    pub decimals: u8,
+    pub name: String,
+    pub symbol: String,
+    #[serde(default, rename = "logoURI")]
+    pub logo_uri: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub extensions: Option<Map<String, Value>>,
+}
+
+impl TokenInfo {
+    /// Convert to a token for the token store.
+    fn to_token(&self) -> Result<Token> {
+        Ok(Token {
+            standard: TokenStandard::ERC20,
+            chain_id: self.chain_id,
+            address: self.address.parse::<Address>()?,
+            token_id: None,
+            symbol: Some(self.symbol.clone()),
+            decimals: Some(self.decimals),
+            name: Some(self.name.clone()),
+            image: self.logo_uri.clone(),
+        })
+    }
+}
+
+/// List of tokens.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct TokenList {
+    pub name: String,
+    pub timestamp: String,
+    pub version: Version,
+    pub tokens: Vec<TokenInfo>,
+    #[serde(default)]
+    pub token_map: Option<Map<String, Value>>,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    #[serde(default)]
+    pub tags: Option<Map<String, Value>>,
+    #[serde(default, rename = "logoURI")]
+    pub logo_uri: Option<String>,
+}
+
+/// Check the length of a string in characters.
+fn check_length(field: &str, value: &str, max: usize) -> Result<()> {
+    let length = value.chars().count();
+    if length == 0 || length > max {
+        bail!("{} must be between 1 and {} characters", field, max);
+    }
+    Ok(())
+}
+
+impl TokenList {
+    /// Validate the list against the token list schema.
+    pub fn validate(&self) -> Result<()> {
+        check_length("name", &self.name, MAX_NAME_LENGTH)?;
+        chrono::DateTime::parse_from_rfc3339(&self.timestamp)
+            .map_err(|e| anyhow::anyhow!("timestamp is invalid: {}", e))?;
+        if self.tokens.is_empty() || self.tokens.len() > MAX_TOKENS {
+            bail!("list must contain between 1 and {} tokens", MAX_TOKENS);
+        }
+
+        let mut seen = HashSet::new();
+        for token in &self.tokens {
+            if token.chain_id == 0 {
+                bail!("token {} has an invalid chain id", token.address);
+            }
+            let is_address = token.address.len() == 42
+                && token.address.starts_with("0x")
+                && token.address[2..].chars().all(|c| c.is_ascii_hexdigit());
+            if !is_address {
+                bail!("token address {} is invalid", token.address);
+            }
+            check_length("token name", &token.name, MAX_TOKEN_NAME_LENGTH)?;
+            token
+                .to_token()
+                .and_then(|entry| entry.validate())
+                .map_err(|e| anyhow!("token {}: {}", token.address, e))?;
+            if token.symbol.chars().any(char::is_whitespace) {
+                bail!("token symbol {} contains whitespace", token.symbol);
+            }
+            if !seen.insert((token.chain_id, token.address.to_lowercase())) {
+                bail!(
+                    "token {} on chain {} is listed more than once",
+                    token.address,
+                    token.chain_id
+                );
+            }
+        }
+        Ok(())
+    }
+
+    /// Tokens in the list.
+    pub fn tokens(&self) -> impl Iterator<Item = Token> + '_ {
+        self.tokens.iter().filter_map(|info| info.to_token().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    const LIST: &str = r#"{
+        "name": "Example List",
+        "timestamp": "2022-02-01T00:00:00.000Z",
+        "version": { "major": 1, "minor": 0, "patch": 0 },
+        "tokens": [
+            {
+                "chainId": 1,
+                "address": "0x6B175474E89094C44Da98b954EedeAC495271d0F",
+                "symbol": "DAI",
+                "name": "Dai Stablecoin",
+                "decimals": 18,
+                "logoURI": "ipfs://QmNa8mQkrNKp1WEEeGjFezDmDeodkWRevGFN8JCV7b4Xir"
+            },
+            {
+                "chainId": 1,
+                "address": "0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48",
+                "symbol": "USDC",
+                "name": "USD Coin",
+                "decimals": 6
+            }
+        ]
+    }"#;
+
+    #[test]
+    fn parse_token_list() -> Result<()> {
+        let list: TokenList = serde_json::from_str(LIST)?;
+        list.validate()?;
+        let tokens: Vec<Token> = list.tokens().collect();
+        assert_eq!(2, tokens.len());
+        assert_eq!(Some(6), tokens[1].decimals);
+        for token in tokens {
+            token.validate()?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn reject_invalid_token_list() -> Result<()> {
+        let mut list: TokenList = serde_json::from_str(LIST)?;
+        list.tokens[1].address = list.tokens[0].address.to_lowercase();
+        assert!(list.validate().is_err());
+
+        let mut list: TokenList = serde_json::from_str(LIST)?;
+        list.tokens[0].address = "0x6B17".to_string();
+        assert!(list.validate().is_err());
+
+        let mut list: TokenList = serde_json::from_str(LIST)?;
+        list.tokens.clear();
+        assert!(list.validate().is_err());
+
+        // Tokens must be accepted by the token store
+        let mut list: TokenList = serde_json::from_str(LIST)?;
+        list.tokens[0].symbol = "DAISTABLECOIN".to_string();
+        assert!(list.validate().is_err());
+
+        let mut list: TokenList = serde_json::from_str(LIST)?;
+        list.tokens[1].logo_uri = Some("http://example.com/usdc.png".into());
+        assert!(list.validate().is_err());
+
+        let invalid = LIST.replace("\"decimals\": 6", "\"decimals\": 256");
+        assert!(serde_json::from_str::<TokenList>(&invalid).is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,308 @@
+//! Tokens tracked for each account.
+//!
+//! Tokens are suggested by clients using `wallet_watchAsset` or
+//! imported from token lists and are stored on disc next to the
+//! user data.
+use anyhow::{bail, Result};
+use ethers_core::types::{Address, U256};
+use once_cell::sync::Lazy;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+
+mod list;
+
+pub use list::TokenList;
+
+const TOKENS: &str = "tokens.json";
+
+/// Longest symbol accepted for a token.
+const MAX_SYMBOL_LENGTH: usize = 11;
+
+/// Largest number of decimals accepted for a fungible token.
+const MAX_DECIMALS: u8 = 36;
+
+pub static TOKEN_STORE: Lazy<RwLock<TokenStore>> = Lazy::new(|| {
+    RwLock::new(TokenStore::load().unwrap_or_else(|e| {
+        log::warn!("could not load tokens: {}", e);
+        Default::default()
+    }))
+});
+
+/// Token standards that can be tracked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenStandard {
+    ERC20,
+    ERC721,
+    ERC1155,
+}
+
+/// Token tracked for an account.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Token {
+    pub standard: TokenStandard,
+    pub chain_id: u64,
+    pub address: Address,
+    /// Identifier of a non-fungible token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub token_id: Option<U256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub symbol: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub decimals: Option<u8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// URL of an image for the token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub image: Option<String>,
+}
+
+impl Token {
+    /// Validate the token metadata.
+    pub fn validate(&self) -> Result<()> {
+        match self.standard {
+            TokenStandard::ERC20 => {
+                let symbol = self.symbol.as_deref().unwrap_or_default();
+                let length = symbol.chars().count();
+                if length == 0 || length > MAX_SYMBOL_LENGTH {
+                    bail!(
+                        "symbol must be between 1 and {} characters",
+                        MAX_SYMBOL_LENGTH
+                    );
+                }
+                match self.decimals {
+                    Some(decimals) if decimals <= MAX_DECIMALS => {}
+                    _ => {
+                        bail!("decimals must be between 0 and {}", MAX_DECIMALS)
+                    }
+                }
+                if self.token_id.is_some() {
+                    bail!("fungible tokens do not have a token id");
+                }
+            }
+            TokenStandard::ERC721 | TokenStandard::ERC1155 => {
+                if self.token_id.is_none() {
+                    bail!("token id is required for {:?}", self.standard);
+                }
+            }
+        }
+        if let Some(image) = &self.image {
+            let allowed = ["https://", "ipfs://", "data:image/"];
+            if !allowed.iter().any(|prefix| image.starts_with(prefix)) {
+                bail!("image must be an https, ipfs or data URL");
+            }
+        }
+        Ok(())
+    }
+
+    /// Determine if two entries refer to the same token.
+    fn is_same(&self, other: &Token) -> bool {
+        self.chain_id == other.chain_id
+            && self.address == other.address
+            && self.token_id == other.token_id
+    }
+}
+
+/// Tokens for every account.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct TokenStore {
+    /// Map account addresses to tokens.
+    accounts: BTreeMap<String, Vec<Token>>,
+}
+
+impl TokenStore {
+    /// Load the tokens from disc.
+    fn load() -> Result<Self> {
+        let file = crate::user::storage()?.join(TOKENS);
+        if file.is_file() {
+            let contents = std::fs::read_to_string(file)?;
+            return Ok(serde_json::from_str(&contents)?);
+        }
+        Ok(Default::default())
+    }
+
+    /// Save the tokens to disc.
+    fn save(&self) -> Result<()> {
+        let file = crate::user::storage()?.join(TOKENS);
+        let contents = serde_json::to_string_pretty(self)?;
+        std::fs::write(file, contents)?;
+        Ok(())
+    }
+
+    /// Tokens for an account.
+    pub fn list(&self, account: &str) -> &[Token] {
+        self.accounts
+            .get(&account.to_lowercase())
+            .map(|tokens| tokens.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Determine if an account tracks a token.
+    pub fn contains(&self, account: &str, token: &Token) -> bool {
+        self.list(account).iter().any(|t| t.is_same(token))
+    }
+
+    /// Insert a token or update the metadata of a known token.
+    fn insert(&mut self, account: &str, token: Token) {
+        let tokens = self.accounts.entry(account.to_lowercase()).or_default();
+        match tokens.iter_mut().find(|t| t.is_same(&token)) {
+            Some(existing) => *existing = token,
+            None => tokens.push(token),
+        }
+    }
+
+    /// Track a token for an account.
+    pub fn add(&mut self, account: &str, token: Token) -> Result<()> {
+        token.validate()?;
+        self.insert(account, token);
+        self.save()
+    }
+
+    /// Stop tracking a token.
+    pub fn remove(&mut self, account: &str, token: &Token) -> Result<bool> {
+        let tokens = self.accounts.entry(account.to_lowercase()).or_default();
+        let length = tokens.len();
+        tokens.retain(|t| !t.is_same(token));
+        let removed = tokens.len() != length;
+        if removed {
+            self.save()?;
+        }
+        Ok(removed)
+    }
+
+    /// Import the tokens from a token list.
+    ///
+    /// When a chain is given only tokens on that chain are
+    /// imported. Returns the number of tokens imported.
+    pub fn import(
+        &mut self,
+        account: &str,
+        list: &TokenList,
+        chain_id: Option<u64>,
+    ) -> Result<usize> {
+        list.validate()?;
+        let tokens: Vec<Token> = list
+            .tokens()
+            .filter(|token| {
+                chain_id.map(|id| id == token.chain_id).unwrap_or(true)
+            })
+            .collect();
+        let count = tokens.len();
+        for token in tokens {
+            self.insert(account, token);
+        }
+        self.save()?;
+        Ok(count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCOUNT: &str = "0x9406Cc6185a346906296840746125a0E44976454";
+
+    fn erc20() -> Token {
+        Token {
+            standard: TokenStandard::ERC20,
+            chain_id: 1,
+            address: "0x6B175474E89094C44Da98b954EedeAC495271d0F"
+                .parse()
+                .unwrap(),
+            token_id: None,
+            symbol: Some("DAI".to_string()),
+            decimals: Some(18),
+            name: None,
+            image: None,
+        }
+    }
+
+    fn is_valid<F: FnOnce(&mut Token)>(f: F) -> bool {
+        let mut token = erc20();
+        f(&mut token);
+        token.validate().is_ok()
+    }
+
+    #[test]
+    fn validate_symbol() {
+        assert!(is_valid(|_| {}));
+        assert!(is_valid(|t| t.symbol = Some("ÉÉÉÉÉÉÉÉÉÉÉ".into())));
+        assert!(!is_valid(|t| t.symbol = None));
+        assert!(!is_valid(|t| t.symbol = Some(String::new())));
+        assert!(!is_valid(|t| t.symbol = Some("DAISTABLECOIN".into())));
+    }
+
+    #[test]
+    fn validate_decimals() {
+        assert!(is_valid(|t| t.decimals = Some(0)));
+        assert!(is_valid(|t| t.decimals = Some(MAX_DECIMALS)));
+        assert!(!is_valid(|t| t.decimals = Some(MAX_DECIMALS + 1)));
+        assert!(!is_valid(|t| t.decimals = None));
+    }
+
+    #[test]
+    fn validate_image() {
+        for image in [
+            "https://example.com/dai.png",
+            "ipfs://QmNa8mQkrNKp1WEEeGjFezDmDeodkWRevGFN8JCV7b4Xir",
+            "data:image/png;base64,iVBORw0KGgo=",
+        ] {
+            assert!(is_valid(|t| t.image = Some(image.into())), "{}", image);
+        }
+        for image in [
+            "http://example.com/dai.png",
+            "javascript:alert(1)",
+            "data:text/html,<script></script>",
+        ] {
+            assert!(!is_valid(|t| t.image = Some(image.into())), "{}", image);
+        }
+    }
+
+    #[test]
+    fn validate_token_id() {
+        assert!(!is_valid(|t| t.token_id = Some(U256::one())));
+        let nft = |token_id: Option<U256>| Token {
+            standard: TokenStandard::ERC721,
+            token_id,
+            symbol: None,
+            decimals: None,
+            ..erc20()
+        };
+        assert!(nft(Some(U256::one())).validate().is_ok());
+        assert!(nft(None).validate().is_err());
+    }
+
+    #[test]
+    fn store_tokens_per_account() {
+        let mut store = TokenStore::default();
+        store.insert(ACCOUNT, erc20());
+        assert!(store.contains(&ACCOUNT.to_lowercase(), &erc20()));
+        assert!(!store
+            .contains("0x0000000000000000000000000000000000000001", &erc20()));
+
+        // The same token on another chain is a different entry
+        let other_chain = Token {
+            chain_id: 137,
+            ..erc20()
+        };
+        assert!(!store.contains(ACCOUNT, &other_chain));
+        store.insert(ACCOUNT, other_chain);
+        assert_eq!(2, store.list(ACCOUNT).len());
+
+        // Known tokens are updated in place
+        store.insert(
+            ACCOUNT,
+            Token {
+                image: Some("https://example.com/dai.png".into()),
+                ..erc20()
+            },
+        );
+        let tokens = store.list(ACCOUNT);
+        assert_eq!(2, tokens.len());
+        assert_eq!(
+            Some("https://example.com/dai.png"),
+            tokens[0].image.as_deref()
+        );
+    }
+}
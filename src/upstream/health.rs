@@ -0,0 +1,269 @@
+//! Health of upstream endpoints.
+//!
+//! Every request updates the health of the endpoint it was sent
+//! to and a background task probes the endpoints of every known
+//! chain, including the RPC URLs of the network registry, so
+//! that an endpoint which recovers is used again.
+use ethers_core::types::U64;
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::{BTreeSet, HashMap};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+use url::Url;
+
+use super::{endpoints, Endpoint, Upstream, UPSTREAM_CONFIG};
+use crate::events::{self, WalletEvent};
+use crate::network::NETWORK_REGISTRY;
+use crate::provider::DEFAULT_CHAIN_ID;
+use crate::user::USER_DATA;
+
+/// Interval between health checks.
+const CHECK_INTERVAL: Duration = Duration::from_secs(30);
+
+static HEALTH: Lazy<RwLock<HashMap<Url, EndpointHealth>>> =
+    Lazy::new(|| RwLock::new(HashMap::new()));
+
+/// Health of an endpoint.
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct EndpointHealth {
+    pub healthy: bool,
+    /// Number of consecutive failures.
+    pub failures: u32,
+    /// Latency of the last successful request in milliseconds.
+    pub latency_ms: Option<u128>,
+    /// Error from the last failed request.
+    pub last_error: Option<String>,
+    /// Whether the endpoint reported another chain; only cleared
+    /// by a health check that reports the expected chain.
+    pub chain_mismatch: bool,
+    /// Time of the last request as seconds since the UNIX epoch.
+    pub updated: u64,
+}
+
+/// Update the health of an endpoint.
+fn update<F: FnOnce(&mut EndpointHealth)>(url: &Url, f: F) {
+    let updated = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default();
+    let mut health = HEALTH.write().unwrap();
+    let entry = health.entry(url.clone()).or_insert(EndpointHealth {
+        healthy: true,
+        failures: 0,
+        latency_ms: None,
+        last_error: None,
+        chain_mismatch: false,
+        updated,
+    });
+    entry.updated = updated;
+    f(entry);
+}
+
+/// Record the outcome of a request to an endpoint.
+pub fn record(url: &Url, outcome: Result<Duration, String>) {
+    update(url, |entry| match outcome {
+        Ok(latency) => {
+            entry.healthy = true;
+            entry.failures = 0;
+            entry.latency_ms = Some(latency.as_millis());
+        }
+        Err(e) => {
+            entry.healthy = false;
+            entry.failures += 1;
+            entry.last_error = Some(e);
+        }
+    })
+}
+
+/// Record the chain id reported by an endpoint.
+fn record_chain(url: &Url, expected: u64, reported: u64) {
+    update(url, |entry| {
+        entry.chain_mismatch = reported != expected;
+        if entry.chain_mismatch {
+            entry.healthy = false;
+            entry.failures += 1;
+            entry.last_error = Some(format!(
+                "expected chain {} but endpoint is on chain {}",
+                expected, reported
+            ));
+        }
+    })
+}
+
+/// Determine if an endpoint reported another chain.
+fn is_mismatched(url: &Url) -> bool {
+    HEALTH
+        .read()
+        .unwrap()
+        .get(url)
+        .map(|h| h.chain_mismatch)
+        .unwrap_or(false)
+}
+
+/// Determine if an endpoint is healthy.
+///
+/// Endpoints that have not been used yet are assumed to be healthy;
+/// endpoints on the wrong chain never are.
+pub fn is_healthy(url: &Url) -> bool {
+    HEALTH
+        .read()
+        .unwrap()
+        .get(url)
+        .map(|h| h.healthy && !h.chain_mismatch)
+        .unwrap_or(true)
+}
+
+/// Determine if a chain has a healthy endpoint.
+pub fn is_chain_connected(chain_id: u64) -> bool {
+    endpoints(chain_id).iter().any(|e| is_healthy(&e.url))
+}
+
+/// Chains to probe: configured chains, networks in the registry
+/// and the chains selected by connected clients.
+fn chains() -> BTreeSet<u64> {
+    let mut chains: BTreeSet<u64> = UPSTREAM_CONFIG
+        .read()
+        .unwrap()
+        .chains()
+        .map(|(chain_id, _)| *chain_id)
+        .collect();
+    {
+        let registry = NETWORK_REGISTRY.read().unwrap();
+        chains.extend(registry.list().iter().map(|n| n.chain_id));
+    }
+    chains.insert(DEFAULT_CHAIN_ID);
+    let user = USER_DATA.read().unwrap();
+    if let Ok(connections) = user.connections() {
+        chains.extend(connections.values().filter_map(|c| c.chain_id()));
+    }
+    chains
+}
+
+/// Order endpoints so that healthy endpoints are tried first,
+/// preserving the configured order otherwise.
+///
+/// Endpoints on the wrong chain are left out.
+pub fn order(endpoints: &[Endpoint]) -> Vec<&Endpoint> {
+    let (mut healthy, unhealthy): (Vec<&Endpoint>, Vec<&Endpoint>) = endpoints
+        .iter()
+        .filter(|e| !is_mismatched(&e.url))
+        .partition(|e| is_healthy(&e.url));
+    healthy.extend(unhealthy);
+    healthy
+}
+
+/// Health of every endpoint that has been used.
+pub fn status() -> HashMap<String, EndpointHealth> {
+    HEALTH
+        .read()
+        .unwrap()
+        .iter()
+        .map(|(url, health)| (url.to_string(), health.clone()))
+        .collect()
+}
+
+/// Probe the endpoints of every known chain and verify that they
+/// are connected to the expected chain.
+///
+/// An event is emitted when a chain becomes reachable or
+/// unreachable.
+pub async fn check() {
+    for chain_id in chains() {
+        let endpoints = endpoints(chain_id);
+        if endpoints.is_empty() {
+            continue;
+        }
+        let was_connected = endpoints.iter().any(|e| is_healthy(&e.url));
+        let urls: Vec<Url> = endpoints.iter().map(|e| e.url.clone()).collect();
+        for endpoint in endpoints {
+            // A single endpoint client records the outcome
+            let url = endpoint.url.clone();
+            let upstream = Upstream::new(chain_id, vec![endpoint]);
+            let result = upstream.probe::<U64>("eth_chainId", json!([])).await;
+            if let Ok(remote_chain_id) = result {
+                record_chain(&url, chain_id, remote_chain_id.as_u64());
+            }
+        }
+        let connected = urls.iter().any(is_healthy);
+        if connected != was_connected {
+            events::emit(WalletEvent::ChainConnection {
+                chain_id,
+                connected,
+            });
+        }
+    }
+}
+
+/// Spawn a task that periodically probes the endpoints.
+pub fn spawn() {
+    tokio::spawn(async {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            interval.tick().await;
+            check().await;
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exclude_endpoints_on_wrong_chain() {
+        let endpoints: Vec<Endpoint> = [
+            "https://wrong-chain.example.com",
+            "https://failing.example.com",
+            "https://healthy.example.com",
+        ]
+        .iter()
+        .map(|url| Endpoint::new(url.parse().unwrap()))
+        .collect();
+        let [wrong_chain, failing, healthy] =
+            [0, 1, 2].map(|index| &endpoints[index].url);
+
+        record_chain(wrong_chain, 1, 5);
+        record(failing, Err("timed out".to_string()));
+        record(healthy, Ok(Duration::from_millis(20)));
+        let urls = |endpoints: Vec<&Endpoint>| -> Vec<Url> {
+            endpoints.into_iter().map(|e| e.url.clone()).collect()
+        };
+        assert_eq!(
+            vec![healthy.clone(), failing.clone()],
+            urls(order(&endpoints))
+        );
+
+        // Successful requests do not clear the mismatch
+        record(wrong_chain, Ok(Duration::from_millis(10)));
+        assert!(!is_healthy(wrong_chain));
+        assert_eq!(2, order(&endpoints).len());
+
+        // A health check on the expected chain does
+        record_chain(wrong_chain, 1, 1);
+        assert!(is_healthy(wrong_chain));
+        assert_eq!(wrong_chain, &order(&endpoints)[0].url);
+    }
+
+    #[test]
+    fn track_registry_chains() {
+        // Chains without configured endpoints use the registry
+        let chain_id = DEFAULT_CHAIN_ID;
+        assert!(!endpoints(chain_id).is_empty());
+        assert!(chains().contains(&chain_id));
+        assert!(is_chain_connected(chain_id));
+
+        for endpoint in endpoints(chain_id) {
+            record_chain(&endpoint.url, chain_id, 5);
+        }
+        assert!(!is_chain_connected(chain_id));
+        for endpoint in endpoints(chain_id) {
+            record_chain(&endpoint.url, chain_id, chain_id);
+        }
+        assert!(is_chain_connected(chain_id));
+
+        assert!(!is_chain_connected(u64::MAX));
+    }
+}
@@ -0,0 +1,297 @@
+//! Upstream Ethereum nodes used to reach a chain.
+//!
+//! Each chain is configured with an ordered list of HTTP JSON-RPC
+//! endpoints; requests fail over to the next endpoint when one
+//! errors or times out. The configuration is stored on disc next
+//! to the user data. Chains without configured endpoints use the
+//! RPC URLs from the network registry. An endpoint may name the
+//! WebSocket URL of the same node so that subscriptions are
+//! relayed instead of polled.
+use anyhow::{bail, Result};
+use once_cell::sync::Lazy;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::sync::RwLock;
+use std::time::{Duration, Instant};
+use url::Url;
+
+use crate::network::NETWORK_REGISTRY;
+use crate::provider::{ProviderError, ProviderResult, CHAIN_DISCONNECTED};
+
+pub mod health;
+pub mod socket;
+
+const UPSTREAM: &str = "upstream.json";
+
+/// Default timeout for a request to an endpoint.
+const DEFAULT_TIMEOUT_MS: u64 = 10_000;
+
+pub static UPSTREAM_CONFIG: Lazy<RwLock<UpstreamConfig>> = Lazy::new(|| {
+    RwLock::new(UpstreamConfig::load().unwrap_or_else(|e| {
+        log::warn!("could not load upstream config: {}", e);
+        Default::default()
+    }))
+});
+
+fn default_timeout() -> u64 {
+    DEFAULT_TIMEOUT_MS
+}
+
+/// JSON-RPC endpoint of an upstream node.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Endpoint {
+    pub url: Url,
+    /// Timeout for each request in milliseconds.
+    #[serde(default = "default_timeout")]
+    pub timeout_ms: u64,
+    /// WebSocket URL of the same node used for subscriptions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ws_url: Option<Url>,
+}
+
+impl Endpoint {
+    pub fn new(url: Url) -> Self {
+        Self {
+            url,
+            timeout_ms: DEFAULT_TIMEOUT_MS,
+            ws_url: None,
+        }
+    }
+
+    fn timeout(&self) -> Duration {
+        Duration::from_millis(self.timeout_ms)
+    }
+}
+
+/// Upstream endpoints for each chain.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct UpstreamConfig {
+    /// Map chain identifiers to JSON-RPC endpoints in order
+    /// of preference.
+    chains: BTreeMap<u64, Vec<Endpoint>>,
+}
+
+impl UpstreamConfig {
+    /// Load the configuration from disc.
+    fn load() -> Result<Self> {
+        let file = crate::user::storage()?.join(UPSTREAM);
+        if file.is_file() {
+            let contents = std::fs::read_to_string(file)?;
+            return Ok(serde_json::from_str(&contents)?);
+        }
+        Ok(Default::default())
+    }
+
+    /// Save the configuration to disc.
+    fn save(&self) -> Result<()> {
+        let file = crate::user::storage()?.join(UPSTREAM);
+        let contents = serde_json::to_string_pretty(self)?;
+        std::fs::write(file, contents)?;
+        Ok(())
+    }
+
+    /// Endpoints for a chain.
+    pub fn endpoints(&self, chain_id: u64) -> &[Endpoint] {
+        self.chains
+            .get(&chain_id)
+            .map(|endpoints| endpoints.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// Endpoints for every chain.
+    pub fn chains(&self) -> impl Iterator<Item = (&u64, &Vec<Endpoint>)> {
+        self.chains.iter()
+    }
+
+    /// Set the endpoints for a chain.
+    pub fn set_endpoints(
+        &mut self,
+        chain_id: u64,
+        endpoints: Vec<Endpoint>,
+    ) -> Result<()> {
+        if endpoints.is_empty() {
+            bail!("at least one endpoint is required");
+        }
+        for endpoint in &endpoints {
+            if !matches!(endpoint.url.scheme(), "http" | "https") {
+                bail!("endpoint {} must use http or https", endpoint.url);
+            }
+            if endpoint.timeout_ms == 0 {
+                bail!("endpoint {} timeout must not be zero", endpoint.url);
+            }
+            if let Some(ws_url) = &endpoint.ws_url {
+                if !matches!(ws_url.scheme(), "ws" | "wss") {
+                    bail!("endpoint {} must use ws or wss", ws_url);
+                }
+            }
+        }
+        self.chains.insert(chain_id, endpoints);
+        self.save()
+    }
+
+    /// Remove the endpoints for a chain.
+    pub fn remove_endpoints(&mut self, chain_id: u64) -> Result<()> {
+        self.chains.remove(&chain_id);
+        self.save()
+    }
+}
+
+/// Endpoints for a chain.
+///
+/// Configured endpoints take precedence over the RPC URLs
+/// of the network registry.
+pub fn endpoints(chain_id: u64) -> Vec<Endpoint> {
+    let configured =
+        UPSTREAM_CONFIG.read().unwrap().endpoints(chain_id).to_vec();
+    if !configured.is_empty() {
+        return configured;
+    }
+    NETWORK_REGISTRY
+        .read()
+        .unwrap()
+        .get(chain_id)
+        .map(|network| network.endpoints())
+        .unwrap_or_default()
+}
+
+/// Response from an upstream node.
+#[derive(Debug, Deserialize)]
+struct UpstreamResponse {
+    result: Option<Value>,
+    error: Option<ProviderError>,
+}
+
+/// Client for the upstream nodes of a chain.
+pub struct Upstream {
+    chain_id: u64,
+    endpoints: Vec<Endpoint>,
+    client: reqwest::Client,
+}
+
+impl Upstream {
+    /// Create a client for the endpoints of a chain.
+    pub fn for_chain(chain_id: u64) -> ProviderResult<Self> {
+        let endpoints = endpoints(chain_id);
+        if endpoints.is_empty() {
+            return Err(ProviderError::new(
+                CHAIN_DISCONNECTED,
+                format!("no upstream node for chain {}", chain_id),
+            ));
+        }
+        Ok(Self::new(chain_id, endpoints))
+    }
+
+    /// Create a client for a list of endpoints.
+    pub fn new(chain_id: u64, endpoints: Vec<Endpoint>) -> Self {
+        Self {
+            chain_id,
+            endpoints,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    /// The chain this client is connected to.
+    pub fn chain_id(&self) -> u64 {
+        self.chain_id
+    }
+
+    /// WebSocket URLs of the endpoints, healthy endpoints first.
+    pub fn ws_urls(&self) -> Vec<Url> {
+        health::order(&self.endpoints)
+            .into_iter()
+            .filter_map(|endpoint| endpoint.ws_url.clone())
+            .collect()
+    }
+
+    /// Send a request to a single endpoint.
+    async fn send(
+        &self,
+        endpoint: &Endpoint,
+        body: &Value,
+    ) -> reqwest::Result<UpstreamResponse> {
+        self.client
+            .post(endpoint.url.clone())
+            .timeout(endpoint.timeout())
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<UpstreamResponse>()
+            .await
+    }
+
+    /// Call a method on the upstream nodes.
+    ///
+    /// Healthy endpoints are tried first; when an endpoint cannot
+    /// be reached the request fails over to the next endpoint.
+    /// Errors returned by a node are passed through unchanged and
+    /// are reported as a disconnected chain when every endpoint
+    /// fails.
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> ProviderResult<T> {
+        self.call_endpoints(health::order(&self.endpoints), method, params)
+            .await
+    }
+
+    /// Call a method on every endpoint in the configured order,
+    /// including endpoints on the wrong chain.
+    ///
+    /// Used by the health checks so such an endpoint can recover.
+    pub(super) async fn probe<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: Value,
+    ) -> ProviderResult<T> {
+        self.call_endpoints(self.endpoints.iter().collect(), method, params)
+            .await
+    }
+
+    async fn call_endpoints<T: DeserializeOwned>(
+        &self,
+        endpoints: Vec<&Endpoint>,
+        method: &str,
+        params: Value,
+    ) -> ProviderResult<T> {
+        let body = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        });
+
+        let mut last_error = None;
+        for endpoint in endpoints {
+            let started = Instant::now();
+            match self.send(endpoint, &body).await {
+                Ok(response) => {
+                    health::record(&endpoint.url, Ok(started.elapsed()));
+                    if let Some(error) = response.error {
+                        return Err(error);
+                    }
+                    return serde_json::from_value(
+                        response.result.unwrap_or(Value::Null),
+                    )
+                    .map_err(ProviderError::internal);
+                }
+                Err(e) => {
+                    log::warn!("upstream {} failed: {}", endpoint.url, e);
+                    health::record(&endpoint.url, Err(e.to_string()));
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        let reason = last_error
+            .map(|e| e.to_string())
+            .unwrap_or_else(|| "no endpoints".to_string());
+        Err(ProviderError::new(
+            CHAIN_DISCONNECTED,
+            format!("upstream node for chain {}: {}", self.chain_id, reason),
+        ))
+    }
+}
@@ -0,0 +1,103 @@
+//! Subscriptions relayed from the WebSocket endpoint of an
+//! upstream node.
+//!
+//! Each subscription uses its own connection so that closing
+//! the connection also cancels the subscription on the node.
+use anyhow::{anyhow, bail, Result};
+use futures::{SinkExt, StreamExt};
+use serde_json::{json, Value};
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio_tungstenite::{
+    connect_async, tungstenite::Message, MaybeTlsStream, WebSocketStream,
+};
+use url::Url;
+
+/// Timeout to connect and for the node to confirm the subscription.
+const SUBSCRIBE_TIMEOUT: Duration = Duration::from_secs(10);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Subscription created with `eth_subscribe` on an upstream node.
+pub struct UpstreamSubscription {
+    socket: Socket,
+    /// Identifier assigned by the node.
+    id: Value,
+}
+
+impl UpstreamSubscription {
+    /// Connect to a node and subscribe using the parameters of an
+    /// `eth_subscribe` request.
+    pub async fn subscribe(url: &Url, params: Value) -> Result<Self> {
+        tokio::time::timeout(SUBSCRIBE_TIMEOUT, async {
+            let (mut socket, _) = connect_async(url.as_str()).await?;
+            let request = json!({
+                "jsonrpc": "2.0",
+                "id": 1,
+                "method": "eth_subscribe",
+                "params": params,
+            });
+            socket.send(Message::Text(request.to_string())).await?;
+            while let Some(message) = socket.next().await {
+                let response = match parse(message?) {
+                    Some(response) if response["id"] == json!(1) => response,
+                    _ => continue,
+                };
+                if let Some(error) = response.get("error") {
+                    bail!("eth_subscribe failed: {}", error);
+                }
+                let id = response["result"].clone();
+                if id.is_null() {
+                    bail!("eth_subscribe did not return an identifier");
+                }
+                return Ok(Self { socket, id });
+            }
+            bail!("connection closed before the subscription was confirmed")
+        })
+        .await
+        .map_err(|_| anyhow!("timed out subscribing to {}", url))?
+    }
+
+    /// Wait for the next event.
+    ///
+    /// Returns `None` once the connection is closed.
+    pub async fn next(&mut self) -> Option<Value> {
+        while let Some(message) = self.socket.next().await {
+            let notification = match message {
+                Ok(message) => parse(message),
+                Err(e) => {
+                    log::warn!("upstream subscription failed: {}", e);
+                    return None;
+                }
+            };
+            if let Some(mut notification) = notification {
+                if notification["method"] == "eth_subscription"
+                    && notification["params"]["subscription"] == self.id
+                {
+                    return Some(notification["params"]["result"].take());
+                }
+            }
+        }
+        None
+    }
+
+    /// Cancel the subscription and close the connection.
+    pub async fn unsubscribe(mut self) {
+        let request = json!({
+            "jsonrpc": "2.0",
+            "id": 2,
+            "method": "eth_unsubscribe",
+            "params": [self.id],
+        });
+        let _ = self.socket.send(Message::Text(request.to_string())).await;
+        let _ = self.socket.close(None).await;
+    }
+}
+
+/// Parse a text message as JSON.
+fn parse(message: Message) -> Option<Value> {
+    match message {
+        Message::Text(text) => serde_json::from_str(&text).ok(),
+        _ => None,
+    }
+}
@@ -1,13 +1,13 @@
 //! Encapsulates user private data and settings.
 
-// https://chainid.network/chains.json
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::path::PathBuf;
 use std::sync::RwLock;
 
 use anyhow::{anyhow, bail, Result};
 use once_cell::sync::Lazy;
 use serde::{Deserialize, Serialize};
+use url::Url;
 use zeroize::Zeroize;
 use totp_rs::{Algorithm, TOTP};
 
@@ -18,6 +18,7 @@ use ethers_signers::{
     MnemonicBuilder,
 };
 
+use crate::events::{self, WalletEvent};
 use crate::helpers::{format_address};
 
 mod account;
@@ -36,6 +37,20 @@ type Address = String;
 pub static USER_DATA: Lazy<RwLock<User<English>>> =
     Lazy::new(|| RwLock::new(Default::default()));
 
+/// Get the application-specific storage directory.
+pub(crate) fn storage() -> Result<PathBuf> {
+    let base = home::home_dir()
+        .ok_or_else(|| anyhow!("could not determine home directory"))?;
+
+    // FIXME: OS-specific locations!
+    let file = base.join("Library").join("MetaMask");
+    if !file.is_dir() {
+        std::fs::create_dir_all(&file)?;
+    }
+
+    Ok(file)
+}
+
 /// Helper function to create a TOTP.
 pub(crate) fn new_totp<T: AsRef<[u8]>>(secret: T) -> TOTP<T> {
     TOTP::new(Algorithm::SHA1, 6, 1, 30, secret)
@@ -47,15 +62,108 @@ pub enum AccountKind {
     Primary,
     #[serde(rename = "imported")]
     Imported,
+    /// Contract account controlled by another account (ERC-4337).
+    #[serde(rename = "smart")]
+    Smart,
+}
+
+/// Version of the ERC-4337 entry point contract.
+#[derive(Debug, Serialize, Deserialize, Eq, PartialEq, Clone, Copy)]
+pub enum EntryPointVersion {
+    #[serde(rename = "0.6")]
+    V06,
+    #[serde(rename = "0.7")]
+    V07,
+}
+
+/// Smart contract account owned by a keystore account.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct SmartAccount {
+    /// Account that signs user operations.
+    pub owner: Address,
+    /// Factory that deploys the account.
+    pub factory: Address,
+    /// Entry point the account is used with.
+    pub entry_point: EntryPointVersion,
+    /// Salt passed to the factory.
+    pub salt: u64,
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct AccountView {
     address: Address,
     kind: AccountKind,
+    /// Deployment details for smart accounts.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    smart: Option<SmartAccount>,
     // TODO: label, account type etc.
 }
 
+impl AccountView {
+    /// The public address for this account.
+    pub fn address(&self) -> &str {
+        &self.address
+    }
+
+    /// The kind of account.
+    pub fn kind(&self) -> &AccountKind {
+        &self.kind
+    }
+
+    /// Deployment details when this is a smart account.
+    pub fn smart(&self) -> Option<&SmartAccount> {
+        self.smart.as_ref()
+    }
+}
+
+/// Accounts the owner has exposed to an OAuth client.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Connection {
+    accounts: Vec<Address>,
+    /// Chain selected by the client.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    chain_id: Option<u64>,
+    /// Chains the client may switch to without asking the owner.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    permitted_chains: Vec<u64>,
+}
+
+impl Connection {
+    /// The addresses exposed to the client.
+    pub fn accounts(&self) -> &[Address] {
+        &self.accounts
+    }
+
+    /// The chain selected by the client.
+    pub fn chain_id(&self) -> Option<u64> {
+        self.chain_id
+    }
+
+    /// The chains the client may switch to without asking.
+    pub fn permitted_chains(&self) -> &[u64] {
+        &self.permitted_chains
+    }
+}
+
+/// User preferences.
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Settings {
+    /// Allow clients to use the legacy `eth_sign` method
+    /// which signs arbitrary hashes.
+    pub eth_sign: bool,
+    /// Contracts accounts may delegate to in addition to the
+    /// known implementations (EIP-7702).
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub delegates: Vec<Address>,
+    /// Map chain identifiers to the bundler used to send user
+    /// operations for smart accounts (ERC-4337).
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub bundlers: BTreeMap<u64, Url>,
+}
+
 // Serialized user data stored on disc.
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct UserData {
@@ -63,6 +171,12 @@ pub struct UserData {
     accounts: HashMap<UUID, AccountView>,
     /// Relative path to the TOTP secrets file.
     totp: Option<PathBuf>,
+    /// Map OAuth client identifiers to connections.
+    #[serde(default)]
+    connections: HashMap<String, Connection>,
+    /// User preferences.
+    #[serde(default)]
+    settings: Settings,
 }
 
 pub struct User<W>
@@ -128,16 +242,7 @@ where
 
     /// Get the application-specific storage directory.
     fn storage(&self) -> Result<PathBuf> {
-        let base = home::home_dir()
-            .ok_or_else(|| anyhow!("could not determine home directory"))?;
-
-        // FIXME: OS-specific locations!
-        let file = base.join("Library").join("MetaMask");
-        if !file.is_dir() {
-            std::fs::create_dir_all(&file)?;
-        }
-
-        Ok(file)
+        storage()
     }
 
     // Get the keystore folder.
@@ -149,6 +254,30 @@ where
         Ok(file)
     }
 
+    /// Get the keystore file for an account address.
+    ///
+    /// Smart accounts do not have a keystore, they are signed
+    /// for by their owner.
+    pub fn keystore_file(&self, address: &str) -> Result<PathBuf> {
+        let user_data = self
+            .user_data
+            .as_ref()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+        let (uuid, account) = user_data
+            .accounts
+            .iter()
+            .find(|(_, v)| v.address.eq_ignore_ascii_case(address))
+            .ok_or_else(|| anyhow!("unknown account {}", address))?;
+        if let Some(smart) = &account.smart {
+            bail!(
+                "{} is a smart account, use the owner {}",
+                address,
+                smart.owner
+            );
+        }
+        Ok(self.keystore()?.join(uuid))
+    }
+
     // Get the TOTP 2FA folder.
     fn totp(&self) -> Result<PathBuf> {
         let file = self.storage()?.join(TOTP);
@@ -222,6 +351,7 @@ where
         let account = AccountView {
             address,
             kind: AccountKind::Primary,
+            smart: None,
         };
 
         // Relative path to the TOTP secrets file
@@ -290,6 +420,7 @@ where
         let account = AccountView {
             address: address.clone(),
             kind,
+            smart: None,
         };
         user_data.accounts.insert(uuid, account);
         self.save()?;
@@ -304,12 +435,17 @@ where
 
     /// Login to the user's account.
     pub fn login(&mut self) -> Result<Option<AccountView>> {
-        authenticate(self)
+        let account = authenticate(self)?;
+        if account.is_some() {
+            events::emit(WalletEvent::AccountsChanged { client_id: None });
+        }
+        Ok(account)
     }
 
     /// Logout of the account.
     pub fn logout(&mut self) -> Result<()> {
         self.user_data = None;
+        events::emit(WalletEvent::AccountsChanged { client_id: None });
         Ok(())
     }
 
@@ -322,6 +458,225 @@ where
         Ok(user_data.accounts.values().collect())
     }
 
+    /// Identifier of the resource owner for OAuth grants.
+    ///
+    /// This is the address of the primary account and is only
+    /// available when logged in.
+    pub fn owner_id(&self) -> Option<String> {
+        self.primary()
+            .ok()
+            .flatten()
+            .map(|(_, account)| account.address.clone())
+    }
+
+    /// List the accounts exposed to an OAuth client.
+    pub fn connected_accounts(&self, client_id: &str) -> Result<Vec<Address>> {
+        let user_data = self
+            .user_data
+            .as_ref()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+        Ok(user_data
+            .connections
+            .get(client_id)
+            .map(|c| c.accounts.clone())
+            .unwrap_or_default())
+    }
+
+    /// Expose accounts to an OAuth client.
+    ///
+    /// Every address must belong to one of the user's accounts.
+    pub fn connect_accounts(
+        &mut self,
+        client_id: &str,
+        accounts: Vec<Address>,
+    ) -> Result<()> {
+        let user_data = self
+            .user_data
+            .as_mut()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+
+        for address in &accounts {
+            let owned = user_data
+                .accounts
+                .values()
+                .any(|a| a.address.eq_ignore_ascii_case(address));
+            if !owned {
+                bail!("unknown account {}", address);
+            }
+        }
+
+        let connection = user_data
+            .connections
+            .entry(client_id.to_string())
+            .or_default();
+        connection.accounts = accounts;
+        self.save()?;
+        events::emit(WalletEvent::AccountsChanged {
+            client_id: Some(client_id.to_string()),
+        });
+        Ok(())
+    }
+
+    /// The chain selected by an OAuth client.
+    pub fn connected_chain(&self, client_id: &str) -> Option<u64> {
+        self.user_data
+            .as_ref()
+            .and_then(|user_data| user_data.connections.get(client_id))
+            .and_then(|c| c.chain_id)
+    }
+
+    /// Select the chain for an OAuth client.
+    pub fn switch_chain(
+        &mut self,
+        client_id: &str,
+        chain_id: u64,
+    ) -> Result<()> {
+        let user_data = self
+            .user_data
+            .as_mut()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+        let connection = user_data
+            .connections
+            .entry(client_id.to_string())
+            .or_default();
+        connection.chain_id = Some(chain_id);
+        self.save()?;
+        events::emit(WalletEvent::ChainChanged {
+            client_id: client_id.to_string(),
+            chain_id,
+        });
+        Ok(())
+    }
+
+    /// The chains an OAuth client may switch to without asking.
+    pub fn permitted_chains(&self, client_id: &str) -> Vec<u64> {
+        self.user_data
+            .as_ref()
+            .and_then(|user_data| user_data.connections.get(client_id))
+            .map(|c| c.permitted_chains.clone())
+            .unwrap_or_default()
+    }
+
+    /// Set the chains an OAuth client may switch to.
+    pub fn set_permitted_chains(
+        &mut self,
+        client_id: &str,
+        chains: Vec<u64>,
+    ) -> Result<()> {
+        let user_data = self
+            .user_data
+            .as_mut()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+        let connection = user_data
+            .connections
+            .entry(client_id.to_string())
+            .or_default();
+        connection.permitted_chains = chains;
+        self.save()
+    }
+
+    /// List the connections to OAuth clients.
+    pub fn connections(&self) -> Result<&HashMap<String, Connection>> {
+        let user_data = self
+            .user_data
+            .as_ref()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+        Ok(&user_data.connections)
+    }
+
+    /// Revoke the connection of an OAuth client.
+    ///
+    /// The client must request accounts again to reconnect.
+    pub fn revoke_connection(&mut self, client_id: &str) -> Result<bool> {
+        let user_data = self
+            .user_data
+            .as_mut()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+        let revoked = user_data.connections.remove(client_id).is_some();
+        if revoked {
+            self.save()?;
+            events::emit(WalletEvent::Revoked {
+                client_id: client_id.to_string(),
+            });
+        }
+        Ok(revoked)
+    }
+
+    /// Get the user preferences.
+    pub fn settings(&self) -> Result<&Settings> {
+        let user_data = self
+            .user_data
+            .as_ref()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+        Ok(&user_data.settings)
+    }
+
+    /// Update the user preferences.
+    pub fn set_settings(&mut self, settings: Settings) -> Result<()> {
+        let user_data = self
+            .user_data
+            .as_mut()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+        user_data.settings = settings;
+        self.save()
+    }
+
+    /// Find the smart account for an address.
+    pub fn smart_account(&self, address: &str) -> Option<SmartAccount> {
+        self.user_data.as_ref().and_then(|user_data| {
+            user_data
+                .accounts
+                .values()
+                .find(|v| v.address.eq_ignore_ascii_case(address))
+                .and_then(|v| v.smart.clone())
+        })
+    }
+
+    /// Add a smart account.
+    ///
+    /// The owner must be one of the keystore accounts; the
+    /// address is the counterfactual address of the account
+    /// which does not need to be deployed yet.
+    pub fn add_smart_account(
+        &mut self,
+        address: Address,
+        smart: SmartAccount,
+    ) -> Result<AccountView> {
+        let user_data = self
+            .user_data
+            .as_mut()
+            .ok_or_else(|| anyhow!("not logged in"))?;
+
+        let owner = user_data
+            .accounts
+            .values()
+            .find(|a| a.address.eq_ignore_ascii_case(&smart.owner))
+            .ok_or_else(|| anyhow!("unknown account {}", smart.owner))?;
+        if owner.kind == AccountKind::Smart {
+            bail!("smart account {} cannot own an account", smart.owner);
+        }
+        let exists = user_data
+            .accounts
+            .values()
+            .any(|a| a.address.eq_ignore_ascii_case(&address));
+        if exists {
+            bail!("account {} already exists", address);
+        }
+
+        let account = AccountView {
+            address,
+            kind: AccountKind::Smart,
+            smart: Some(smart),
+        };
+        // Smart accounts have no keystore so use a random identifier
+        let uuid =
+            format!("smart-{}", hex::encode(rand::random::<[u8; 16]>()));
+        user_data.accounts.insert(uuid, account.clone());
+        self.save()?;
+        events::emit(WalletEvent::AccountsChanged { client_id: None });
+        Ok(account)
+    }
+
     /// Add a derived account.
     pub fn add_account(&mut self) -> Result<String> {
         todo!()